@@ -0,0 +1,591 @@
+use crate::{
+    client_ip::{ip_in_range, parse_range},
+    error::{AppError, AppResult},
+    sessions::Session,
+    tls::ClientCertificate,
+};
+use axum::http::Method;
+use chrono::{DateTime, Duration, Utc};
+use rand::RngCore;
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use sqlx::{Row, SqlitePool};
+use std::net::IpAddr;
+use uuid::Uuid;
+
+/// Start of every API token, which tells them apart from session tokens.
+pub const TOKEN_PREFIX: &str = "kgk_";
+/// Endpoints, relative to `/api`, that tokens can be scoped to. Accounts,
+/// sessions, tokens, users and history are only managed from a browser
+/// session.
+pub const TOKEN_ENDPOINTS: &[&str] = &["/passwords", "/otp", "/passkeys", "/sync", "/store"];
+/// Endpoints that name entries, and so the only ones a token limited to
+/// folders can call. The others report on the whole store.
+const ENTRY_ENDPOINTS: &[&str] = &["/passwords", "/otp"];
+/// Endpoints of tokens created without a list.
+const DEFAULT_ENDPOINTS: &[&str] = &["/passwords", "/otp"];
+const DEFAULT_VALID_DAYS: u32 = 90;
+const MAX_VALID_DAYS: u32 = 365;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenAccess {
+    /// Only `GET` requests.
+    #[default]
+    ReadOnly,
+    ReadWrite,
+}
+
+/// A long-lived token for scripts and CI. Only the SHA-256 of the token
+/// itself is stored.
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiToken {
+    pub id: String,
+    pub user_id: String,
+    pub name: String,
+    pub access: TokenAccess,
+    /// Folders, as the user names them, whose entries the token can use;
+    /// all of them when empty.
+    pub folders: Vec<String>,
+    /// Endpoint prefixes from [`TOKEN_ENDPOINTS`] the token can call.
+    pub endpoints: Vec<String>,
+    /// Addresses or CIDR ranges the token can be used from; any when empty.
+    pub allowed_ips: Vec<String>,
+    /// Client certificate common name or subject alternative name the
+    /// token is bound to. A certificate presenting it stands in for the
+    /// secret, and the secret alone no longer works.
+    pub client_identity: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub last_used_ip: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateTokenRequest {
+    pub name: String,
+    #[serde(default)]
+    pub access: TokenAccess,
+    #[serde(default)]
+    pub folders: Vec<String>,
+    /// Defaults to `/passwords` and `/otp`.
+    #[serde(default)]
+    pub endpoints: Vec<String>,
+    #[serde(default)]
+    pub allowed_ips: Vec<String>,
+    #[serde(default)]
+    pub client_identity: Option<String>,
+    /// Defaults to 90, at most 365.
+    #[serde(default)]
+    pub expires_in_days: Option<u32>,
+}
+
+impl ApiToken {
+    /// Checks a request against the token's scope. `path` is relative to
+    /// `/api`.
+    pub fn authorize(
+        &self,
+        method: &Method,
+        path: &str,
+        client: Option<IpAddr>,
+        certificate: Option<&ClientCertificate>,
+    ) -> AppResult<()> {
+        if let Some(identity) = &self.client_identity {
+            if !certificate.is_some_and(|certificate| certificate.presents(identity)) {
+                return Err(AppError::AuthorizationFailed(
+                    "Token requires its client certificate".to_string(),
+                ));
+            }
+        }
+
+        if !self.allowed_ips.is_empty()
+            && !client.is_some_and(|client| self.allowed_ips.iter().any(|range| ip_in_range(client, range)))
+        {
+            return Err(AppError::AuthorizationFailed(
+                "Token cannot be used from this address".to_string(),
+            ));
+        }
+
+        if !self.endpoints.iter().any(|endpoint| within(path, endpoint)) {
+            return Err(AppError::AuthorizationFailed(format!(
+                "Token is not allowed to call {}",
+                path
+            )));
+        }
+
+        if !self.folders.is_empty() && !ENTRY_ENDPOINTS.iter().any(|endpoint| within(path, endpoint)) {
+            return Err(AppError::AuthorizationFailed(format!(
+                "Token limited to folders is not allowed to call {}",
+                path
+            )));
+        }
+
+        if self.access == TokenAccess::ReadOnly && !matches!(*method, Method::GET | Method::HEAD) {
+            return Err(AppError::AuthorizationFailed("Token is read-only".to_string()));
+        }
+
+        // Entry paths follow the endpoint; listings are narrowed by the
+        // handler
+        let entry = ["/passwords/", "/otp/"]
+            .iter()
+            .find_map(|prefix| path.strip_prefix(prefix));
+        if let Some(entry) = entry {
+            if !self.allows_entry(entry) {
+                return Err(AppError::AuthorizationFailed(format!(
+                    "Token is not allowed to use {}",
+                    entry
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether the entry at `path`, as the user names it, lies in one of
+    /// the token's folders.
+    pub fn allows_entry(&self, path: &str) -> bool {
+        self.folders.is_empty() || self.folders.iter().any(|folder| within(path.trim_matches('/'), folder))
+    }
+
+    /// Session standing in for the token, so handlers treat both alike.
+    /// Tokens are scoped when they are created, so each request within the
+    /// scope counts as freshly authenticated. Operations on the whole store
+    /// refuse tokens outright; see `AppState::require_interactive_auth`.
+    pub fn session(&self, client: Option<IpAddr>) -> Session {
+        let now = Utc::now();
+        Session {
+            id: self.id.clone(),
+            user_id: self.user_id.clone(),
+            created_at: self.created_at,
+            last_seen_at: now,
+            expires_at: self.expires_at,
+            absolute_expires_at: self.expires_at,
+            authenticated_at: now,
+            user_agent: None,
+            ip_address: client.map(|ip| ip.to_string()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ApiTokenStore {
+    pool: SqlitePool,
+}
+
+impl ApiTokenStore {
+    pub async fn new(pool: SqlitePool) -> AppResult<Self> {
+        let store = Self { pool };
+        store.init_schema().await?;
+        Ok(store)
+    }
+
+    async fn init_schema(&self) -> AppResult<()> {
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS api_tokens (
+                id TEXT PRIMARY KEY,
+                token_hash TEXT NOT NULL UNIQUE,
+                user_id TEXT NOT NULL,
+                name TEXT NOT NULL,
+                access TEXT NOT NULL,
+                folders TEXT NOT NULL,
+                endpoints TEXT NOT NULL,
+                allowed_ips TEXT NOT NULL,
+                client_identity TEXT,
+                created_at TIMESTAMP NOT NULL,
+                expires_at TIMESTAMP NOT NULL,
+                last_used_at TIMESTAMP,
+                last_used_ip TEXT
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_api_tokens_user ON api_tokens(user_id);
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Creates a token and returns it with its secret, which is shown only
+    /// this once.
+    pub async fn create(&self, user_id: &str, request: CreateTokenRequest) -> AppResult<(String, ApiToken)> {
+        let name = request.name.trim();
+        if name.is_empty() {
+            return Err(AppError::ValidationError("Token name is required".to_string()));
+        }
+        let valid_days = request.expires_in_days.unwrap_or(DEFAULT_VALID_DAYS);
+        if !(1..=MAX_VALID_DAYS).contains(&valid_days) {
+            return Err(AppError::ValidationError(format!(
+                "Tokens expire after 1 to {} days",
+                MAX_VALID_DAYS
+            )));
+        }
+        let folders = request
+            .folders
+            .iter()
+            .map(|folder| normalize_folder(folder))
+            .collect::<AppResult<Vec<_>>>()?;
+        let endpoints = if request.endpoints.is_empty() {
+            DEFAULT_ENDPOINTS.iter().map(|endpoint| endpoint.to_string()).collect()
+        } else {
+            request
+                .endpoints
+                .iter()
+                .map(|endpoint| normalize_endpoint(endpoint))
+                .collect::<AppResult<Vec<_>>>()?
+        };
+        if !folders.is_empty() {
+            if let Some(endpoint) = endpoints.iter().find(|endpoint| !ENTRY_ENDPOINTS.contains(&endpoint.as_str())) {
+                return Err(AppError::ValidationError(format!(
+                    "Tokens limited to folders can only call {}, not {}",
+                    ENTRY_ENDPOINTS.join(" and "),
+                    endpoint
+                )));
+            }
+        }
+        for range in &request.allowed_ips {
+            parse_range(range)?;
+        }
+        let client_identity = request.client_identity.as_deref().map(str::trim);
+        if let Some(identity) = client_identity {
+            if identity.is_empty() {
+                return Err(AppError::ValidationError("Client identity cannot be empty".to_string()));
+            }
+            // A certificate has to lead to a single token
+            if !self.bound_to(identity).await?.is_empty() {
+                return Err(AppError::Conflict(format!(
+                    "Another token is bound to client identity {}",
+                    identity
+                )));
+            }
+        }
+
+        let mut bytes = [0u8; 32];
+        rand::rngs::OsRng.fill_bytes(&mut bytes);
+        let token = format!("{}{}", TOKEN_PREFIX, hex::encode(bytes));
+
+        let id = Uuid::new_v4().to_string();
+        let now = Utc::now();
+        sqlx::query(
+            r#"
+            INSERT INTO api_tokens (
+                id, token_hash, user_id, name, access, folders, endpoints, allowed_ips, client_identity, created_at,
+                expires_at
+            )
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+            "#,
+        )
+        .bind(&id)
+        .bind(hash_token(&token))
+        .bind(user_id)
+        .bind(name)
+        .bind(access_name(request.access))
+        .bind(serde_json::to_string(&folders)?)
+        .bind(serde_json::to_string(&endpoints)?)
+        .bind(serde_json::to_string(&request.allowed_ips)?)
+        .bind(client_identity)
+        .bind(now)
+        .bind(now + Duration::days(valid_days as i64))
+        .execute(&self.pool)
+        .await?;
+
+        let api_token = self.by_id(user_id, &id).await?;
+        Ok((token, api_token))
+    }
+
+    /// Unexpired token of `token`.
+    pub async fn get(&self, token: &str) -> AppResult<Option<ApiToken>> {
+        let row = sqlx::query(&format!("{} WHERE token_hash = ?1", SELECT_TOKENS))
+            .bind(hash_token(token))
+            .fetch_optional(&self.pool)
+            .await?;
+        let Some(api_token) = row.map(|row| row_to_token(&row)).transpose()? else {
+            return Ok(None);
+        };
+
+        Ok((api_token.expires_at > Utc::now()).then_some(api_token))
+    }
+
+    /// Unexpired token bound to one of `certificate`'s identities.
+    /// Certificates matching several tokens are refused rather than
+    /// guessed at.
+    pub async fn find_by_certificate(&self, certificate: &ClientCertificate) -> AppResult<Option<ApiToken>> {
+        let mut found = Vec::new();
+        for identity in &certificate.identities {
+            found.extend(self.bound_to(identity).await?);
+        }
+        // Common names are often repeated as a DNS name
+        found.sort_by(|a, b| a.id.cmp(&b.id));
+        found.dedup_by(|a, b| a.id == b.id);
+
+        match found.len() {
+            0 | 1 => Ok(found.pop()),
+            _ => Err(AppError::AuthenticationFailed(format!(
+                "Client certificate {} matches several tokens",
+                certificate.fingerprint
+            ))),
+        }
+    }
+
+    async fn bound_to(&self, identity: &str) -> AppResult<Vec<ApiToken>> {
+        let rows = sqlx::query(&format!("{} WHERE client_identity = ?1 AND expires_at > ?2", SELECT_TOKENS))
+            .bind(identity)
+            .bind(Utc::now())
+            .fetch_all(&self.pool)
+            .await?;
+
+        rows.iter().map(row_to_token).collect()
+    }
+
+    /// Records a use of token `id`.
+    pub async fn touch(&self, id: &str, client: Option<IpAddr>) -> AppResult<()> {
+        sqlx::query("UPDATE api_tokens SET last_used_at = ?1, last_used_ip = ?2 WHERE id = ?3")
+            .bind(Utc::now())
+            .bind(client.map(|ip| ip.to_string()))
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Tokens of `user_id`, including expired ones, newest first.
+    pub async fn list(&self, user_id: &str) -> AppResult<Vec<ApiToken>> {
+        let rows = sqlx::query(&format!("{} WHERE user_id = ?1 ORDER BY created_at DESC", SELECT_TOKENS))
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await?;
+
+        rows.iter().map(row_to_token).collect()
+    }
+
+    /// Revokes token `id` of `user_id`.
+    pub async fn revoke(&self, user_id: &str, id: &str) -> AppResult<()> {
+        let result = sqlx::query("DELETE FROM api_tokens WHERE id = ?1 AND user_id = ?2")
+            .bind(id)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("Token not found: {}", id)));
+        }
+
+        Ok(())
+    }
+
+    pub async fn delete_user(&self, user_id: &str) -> AppResult<()> {
+        sqlx::query("DELETE FROM api_tokens WHERE user_id = ?1")
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn by_id(&self, user_id: &str, id: &str) -> AppResult<ApiToken> {
+        let row = sqlx::query(&format!("{} WHERE id = ?1 AND user_id = ?2", SELECT_TOKENS))
+            .bind(id)
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("Token not found: {}", id)))?;
+
+        row_to_token(&row)
+    }
+}
+
+const SELECT_TOKENS: &str = "SELECT id, user_id, name, access, folders, endpoints, allowed_ips, \
+     client_identity, created_at, expires_at, last_used_at, last_used_ip FROM api_tokens";
+
+/// Whether `path` is `prefix` or lies below it.
+fn within(path: &str, prefix: &str) -> bool {
+    path.strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+fn normalize_folder(folder: &str) -> AppResult<String> {
+    let folder = folder.trim_matches('/');
+    if folder.is_empty() || folder.split('/').any(|part| part.is_empty() || part == "." || part == "..") {
+        return Err(AppError::ValidationError(format!("Invalid folder: {}", folder)));
+    }
+    Ok(folder.to_string())
+}
+
+fn normalize_endpoint(endpoint: &str) -> AppResult<String> {
+    let endpoint = format!("/{}", endpoint.trim_matches('/'));
+    if !TOKEN_ENDPOINTS.contains(&endpoint.as_str()) {
+        return Err(AppError::ValidationError(format!(
+            "Tokens can only be scoped to {}",
+            TOKEN_ENDPOINTS.join(", ")
+        )));
+    }
+    Ok(endpoint)
+}
+
+fn access_name(access: TokenAccess) -> &'static str {
+    match access {
+        TokenAccess::ReadOnly => "read_only",
+        TokenAccess::ReadWrite => "read_write",
+    }
+}
+
+fn hash_token(token: &str) -> String {
+    hex::encode(digest::digest(&digest::SHA256, token.as_bytes()))
+}
+
+fn row_to_token(row: &sqlx::sqlite::SqliteRow) -> AppResult<ApiToken> {
+    let access = match row.get::<String, _>("access").as_str() {
+        "read_only" => TokenAccess::ReadOnly,
+        "read_write" => TokenAccess::ReadWrite,
+        other => return Err(AppError::DatabaseError(format!("Unknown token access: {}", other))),
+    };
+
+    Ok(ApiToken {
+        id: row.get("id"),
+        user_id: row.get("user_id"),
+        name: row.get("name"),
+        access,
+        folders: serde_json::from_str(row.get("folders"))?,
+        endpoints: serde_json::from_str(row.get("endpoints"))?,
+        allowed_ips: serde_json::from_str(row.get("allowed_ips"))?,
+        client_identity: row.get("client_identity"),
+        created_at: row.get("created_at"),
+        expires_at: row.get("expires_at"),
+        last_used_at: row.get("last_used_at"),
+        last_used_ip: row.get("last_used_ip"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    async fn create_test_store() -> ApiTokenStore {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        ApiTokenStore::new(pool).await.unwrap()
+    }
+
+    fn request(name: &str) -> CreateTokenRequest {
+        CreateTokenRequest {
+            name: name.to_string(),
+            access: TokenAccess::ReadOnly,
+            folders: vec!["deploy".to_string()],
+            endpoints: Vec::new(),
+            allowed_ips: vec!["10.1.0.0/16".to_string()],
+            client_identity: None,
+            expires_in_days: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tokens_are_stored_hashed() {
+        let store = create_test_store().await;
+        let (token, created) = store.create("alice", request("ci")).await.unwrap();
+        assert!(token.starts_with(TOKEN_PREFIX));
+        assert_eq!(created.endpoints, vec!["/passwords", "/otp"]);
+
+        let found = store.get(&token).await.unwrap().unwrap();
+        assert_eq!(found.id, created.id);
+        assert!(store.get(&format!("{}00", token)).await.unwrap().is_none());
+
+        store.touch(&created.id, Some("10.1.2.3".parse().unwrap())).await.unwrap();
+        let listed = store.list("alice").await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].last_used_ip.as_deref(), Some("10.1.2.3"));
+
+        assert!(matches!(store.revoke("bob", &created.id).await, Err(AppError::NotFound(_))));
+        store.revoke("alice", &created.id).await.unwrap();
+        assert!(store.get(&token).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_scopes_are_rejected() {
+        let store = create_test_store().await;
+        let mut admin_scope = request("ci");
+        admin_scope.endpoints = vec!["/admin".to_string()];
+        let mut escaping_folder = request("ci");
+        escaping_folder.folders = vec!["deploy/../other".to_string()];
+        let mut bad_ip = request("ci");
+        bad_ip.allowed_ips = vec!["10.0.0.0/33".to_string()];
+        let mut history_scope = request("ci");
+        history_scope.folders = Vec::new();
+        history_scope.endpoints = vec!["/history".to_string()];
+        // Signatures and the store check list entries of every folder
+        let mut folders_with_sync = request("ci");
+        folders_with_sync.endpoints = vec!["/passwords".to_string(), "/sync".to_string()];
+
+        for request in [admin_scope, escaping_folder, bad_ip, history_scope, folders_with_sync] {
+            assert!(matches!(store.create("alice", request).await, Err(AppError::ValidationError(_))));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_requests_are_checked_against_scope() {
+        let store = create_test_store().await;
+        let (_, token) = store.create("alice", request("ci")).await.unwrap();
+        let inside = Some("10.1.2.3".parse().unwrap());
+
+        assert!(token.authorize(&Method::GET, "/passwords/deploy/db", inside, None).is_ok());
+        assert!(token.authorize(&Method::GET, "/passwords", inside, None).is_ok());
+        assert!(token.authorize(&Method::GET, "/passwords/deployment/db", inside, None).is_err());
+        assert!(token.authorize(&Method::DELETE, "/passwords/deploy/db", inside, None).is_err());
+        assert!(token.authorize(&Method::GET, "/history", inside, None).is_err());
+        let outside = Some("10.2.0.1".parse().unwrap());
+        assert!(token.authorize(&Method::GET, "/passwords/deploy/db", outside, None).is_err());
+        assert!(token.authorize(&Method::GET, "/passwords/deploy/db", None, None).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_folder_tokens_only_call_entry_endpoints() {
+        let store = create_test_store().await;
+        let (_, mut token) = store.create("alice", request("ci")).await.unwrap();
+        // As if stored with a broader list
+        token.endpoints = TOKEN_ENDPOINTS.iter().map(|endpoint| endpoint.to_string()).collect();
+        let inside = Some("10.1.2.3".parse().unwrap());
+
+        assert!(token.authorize(&Method::GET, "/otp/deploy/db", inside, None).is_ok());
+        for path in ["/sync/signatures", "/passkeys", "/store/check"] {
+            assert!(token.authorize(&Method::GET, path, inside, None).is_err());
+        }
+
+        token.folders = Vec::new();
+        assert!(token.authorize(&Method::GET, "/sync/signatures", inside, None).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_tokens_bound_to_client_certificates() {
+        let store = create_test_store().await;
+        let certificate = |identities: &[&str]| ClientCertificate {
+            fingerprint: "ab".repeat(32),
+            subject: "CN=ci-runner".to_string(),
+            identities: identities.iter().map(|identity| identity.to_string()).collect(),
+        };
+        let mut bound = request("ci");
+        bound.allowed_ips = Vec::new();
+        bound.client_identity = Some("ci.example.com".to_string());
+        let (_, token) = store.create("alice", bound).await.unwrap();
+
+        let runner = certificate(&["ci-runner", "ci.example.com"]);
+        let found = store.find_by_certificate(&runner).await.unwrap().unwrap();
+        assert_eq!(found.id, token.id);
+        assert!(store.find_by_certificate(&certificate(&["other"])).await.unwrap().is_none());
+
+        // The secret alone is not enough
+        assert!(token.authorize(&Method::GET, "/passwords/deploy/db", None, Some(&runner)).is_ok());
+        assert!(token.authorize(&Method::GET, "/passwords/deploy/db", None, None).is_err());
+
+        let mut duplicate = request("ci-2");
+        duplicate.client_identity = Some("ci.example.com".to_string());
+        assert!(matches!(store.create("bob", duplicate).await, Err(AppError::Conflict(_))));
+
+        let mut other_name = request("ci-3");
+        other_name.client_identity = Some("ci-runner".to_string());
+        store.create("alice", other_name).await.unwrap();
+        assert!(matches!(
+            store.find_by_certificate(&runner).await,
+            Err(AppError::AuthenticationFailed(_))
+        ));
+    }
+}
@@ -0,0 +1,673 @@
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::request::Parts,
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use sqlx::{Row, SqlitePool};
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+use tokio::sync::Mutex;
+use crate::{
+    client_ip::client_ip,
+    error::{AppError, AppResult},
+    state::{AppState, Session},
+    tls::ClientCertificate,
+};
+
+const DEFAULT_PER_PAGE: usize = 50;
+const MAX_PER_PAGE: usize = 500;
+/// Previous hash of the first record.
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+/// Structured data ID of exported syslog messages, under the enterprise
+/// number RFC 5612 reserves for documentation, as Kagikanri has none.
+const SYSLOG_SD_ID: &str = "audit@32473";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Login,
+    LoginFailed,
+    Logout,
+    /// Step-up re-authentication.
+    Reauth,
+    /// A password or OTP code was shown.
+    Reveal,
+    Write,
+    Delete,
+    Export,
+    Sync,
+    /// User administration, history changes and lockouts.
+    Admin,
+    /// Changes to the user's own credentials, sessions and tokens.
+    Account,
+}
+
+/// One entry of the audit log. `hash` covers every other field and the
+/// previous record's hash, so changing, removing or reordering records
+/// breaks the chain from there on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// Position in the chain, starting at 1.
+    pub seq: i64,
+    pub timestamp: DateTime<Utc>,
+    pub action: AuditAction,
+    pub user_id: Option<String>,
+    pub ip_address: Option<String>,
+    pub request_id: Option<String>,
+    /// What the action was applied to, such as an entry path or user.
+    pub target: Option<String>,
+    pub detail: Option<String>,
+    /// SHA-256 fingerprint of the client certificate, if one was used.
+    pub certificate: Option<String>,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+/// An event to append; the log adds the sequence number, time and hashes.
+#[derive(Debug, Clone, Default)]
+pub struct AuditEvent {
+    pub user_id: Option<String>,
+    pub ip_address: Option<String>,
+    pub request_id: Option<String>,
+    pub target: Option<String>,
+    pub detail: Option<String>,
+    pub certificate: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AuditQuery {
+    pub user: Option<String>,
+    pub action: Option<AuditAction>,
+    pub ip: Option<String>,
+    pub request_id: Option<String>,
+    /// Only records whose target lies under this path.
+    pub target: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    /// 1-based page number.
+    pub page: Option<usize>,
+    pub per_page: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditPage {
+    /// Newest first.
+    pub records: Vec<AuditRecord>,
+    pub page: usize,
+    pub per_page: usize,
+    pub has_more: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditVerification {
+    pub valid: bool,
+    pub records: u64,
+    /// Hash of the newest record. Keeping it elsewhere also reveals
+    /// records cut off the end of the log.
+    pub head_hash: String,
+    /// First record that does not match the chain.
+    pub broken_at: Option<i64>,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// JSON Lines, one record per line.
+    Jsonl,
+    /// RFC 5424 syslog messages, one per line.
+    Syslog,
+}
+
+/// Append-only, hash-chained audit log in the local database. Triggers
+/// refuse updates and deletes; the chain reveals changes made around them.
+#[derive(Debug)]
+pub struct AuditLog {
+    pool: SqlitePool,
+    /// Appends read the previous hash and write the next record as one
+    /// step.
+    append_lock: Mutex<()>,
+}
+
+impl AuditLog {
+    pub async fn new(pool: SqlitePool) -> AppResult<Self> {
+        let log = Self {
+            pool,
+            append_lock: Mutex::new(()),
+        };
+        log.init_schema().await?;
+        Ok(log)
+    }
+
+    async fn init_schema(&self) -> AppResult<()> {
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS audit_log (
+                seq INTEGER PRIMARY KEY,
+                timestamp TEXT NOT NULL,
+                action TEXT NOT NULL,
+                user_id TEXT,
+                ip_address TEXT,
+                request_id TEXT,
+                target TEXT,
+                detail TEXT,
+                certificate TEXT,
+                prev_hash TEXT NOT NULL,
+                hash TEXT NOT NULL
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_audit_log_user ON audit_log(user_id);
+            CREATE INDEX IF NOT EXISTS idx_audit_log_timestamp ON audit_log(timestamp);
+
+            CREATE TRIGGER IF NOT EXISTS audit_log_no_update BEFORE UPDATE ON audit_log
+            BEGIN
+                SELECT RAISE(ABORT, 'audit log is append-only');
+            END;
+
+            CREATE TRIGGER IF NOT EXISTS audit_log_no_delete BEFORE DELETE ON audit_log
+            BEGIN
+                SELECT RAISE(ABORT, 'audit log is append-only');
+            END;
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn append(&self, action: AuditAction, event: AuditEvent) -> AppResult<AuditRecord> {
+        let _append = self.append_lock.lock().await;
+
+        let last = sqlx::query("SELECT seq, hash FROM audit_log ORDER BY seq DESC LIMIT 1")
+            .fetch_optional(&self.pool)
+            .await?;
+        let (seq, prev_hash) = match last {
+            Some(row) => (row.get::<i64, _>("seq") + 1, row.get::<String, _>("hash")),
+            None => (1, GENESIS_HASH.to_string()),
+        };
+
+        // Stored as text, so the hashed timestamp is exactly what is read
+        // back
+        let timestamp = parse_timestamp(&format_timestamp(Utc::now()))?;
+        let mut record = AuditRecord {
+            seq,
+            timestamp,
+            action,
+            user_id: event.user_id,
+            ip_address: event.ip_address,
+            request_id: event.request_id,
+            target: event.target,
+            detail: event.detail,
+            certificate: event.certificate,
+            prev_hash,
+            hash: String::new(),
+        };
+        record.hash = record_hash(&record);
+
+        sqlx::query(
+            r#"
+            INSERT INTO audit_log (
+                seq, timestamp, action, user_id, ip_address, request_id, target, detail, certificate, prev_hash, hash
+            )
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+            "#,
+        )
+        .bind(record.seq)
+        .bind(format_timestamp(record.timestamp))
+        .bind(action_name(record.action))
+        .bind(&record.user_id)
+        .bind(&record.ip_address)
+        .bind(&record.request_id)
+        .bind(&record.target)
+        .bind(&record.detail)
+        .bind(&record.certificate)
+        .bind(&record.prev_hash)
+        .bind(&record.hash)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(record)
+    }
+
+    /// Records matching `query`, newest first.
+    pub async fn list(&self, query: &AuditQuery) -> AppResult<AuditPage> {
+        let per_page = query.per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(1, MAX_PER_PAGE);
+        let page = query.page.unwrap_or(1).max(1);
+
+        // One more than a page tells whether another follows
+        let mut records = self
+            .matching(query, "DESC", Some((per_page + 1) as i64), ((page - 1) * per_page) as i64)
+            .await?;
+        let has_more = records.len() > per_page;
+        records.truncate(per_page);
+
+        Ok(AuditPage {
+            records,
+            page,
+            per_page,
+            has_more,
+        })
+    }
+
+    /// Every record matching `query` in the export format, oldest first,
+    /// one per line. `hostname` names Kagikanri in syslog messages.
+    pub async fn export(&self, query: &AuditQuery, format: ExportFormat, hostname: &str) -> AppResult<String> {
+        let records = self.matching(query, "ASC", None, 0).await?;
+
+        let mut lines = String::new();
+        for record in &records {
+            match format {
+                ExportFormat::Jsonl => lines.push_str(&serde_json::to_string(record)?),
+                ExportFormat::Syslog => lines.push_str(&to_syslog(record, hostname)),
+            }
+            lines.push('\n');
+        }
+        Ok(lines)
+    }
+
+    async fn matching(
+        &self,
+        query: &AuditQuery,
+        order: &str,
+        limit: Option<i64>,
+        offset: i64,
+    ) -> AppResult<Vec<AuditRecord>> {
+        let target_prefix = query.target.as_deref().map(|target| format!("{}/", target.trim_end_matches('/')));
+        let rows = sqlx::query(&format!(
+            r#"
+            SELECT * FROM audit_log
+            WHERE (?1 IS NULL OR user_id = ?1)
+              AND (?2 IS NULL OR action = ?2)
+              AND (?3 IS NULL OR ip_address = ?3)
+              AND (?4 IS NULL OR request_id = ?4)
+              AND (?5 IS NULL OR target = ?5 OR substr(target, 1, length(?6)) = ?6)
+              AND (?7 IS NULL OR timestamp >= ?7)
+              AND (?8 IS NULL OR timestamp <= ?8)
+            ORDER BY seq {}
+            LIMIT ?9 OFFSET ?10
+            "#,
+            order
+        ))
+        .bind(&query.user)
+        .bind(query.action.map(action_name))
+        .bind(&query.ip)
+        .bind(&query.request_id)
+        .bind(query.target.as_deref().map(|target| target.trim_end_matches('/')))
+        .bind(target_prefix)
+        .bind(query.since.map(format_timestamp))
+        .bind(query.until.map(format_timestamp))
+        .bind(limit.unwrap_or(-1))
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter().map(row_to_record).collect()
+    }
+
+    /// Walks the chain from the first record, checking every hash and
+    /// link.
+    pub async fn verify(&self) -> AppResult<AuditVerification> {
+        let rows = sqlx::query("SELECT * FROM audit_log ORDER BY seq ASC")
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut prev_hash = GENESIS_HASH.to_string();
+        let mut records = 0;
+        for (index, row) in rows.iter().enumerate() {
+            let broken = |seq: i64, reason: &str| AuditVerification {
+                valid: false,
+                records: rows.len() as u64,
+                head_hash: rows.last().map(|row| row.get("hash")).unwrap_or_default(),
+                broken_at: Some(seq),
+                reason: Some(reason.to_string()),
+            };
+            let seq: i64 = row.get("seq");
+            let record = match row_to_record(row) {
+                Ok(record) => record,
+                Err(_) => return Ok(broken(seq, "Record cannot be read")),
+            };
+
+            if record.seq != index as i64 + 1 {
+                return Ok(broken(record.seq, "Records are missing before this one"));
+            }
+            if record.prev_hash != prev_hash {
+                return Ok(broken(record.seq, "Record does not link to the previous one"));
+            }
+            if record.hash != record_hash(&record) {
+                return Ok(broken(record.seq, "Record was changed after it was written"));
+            }
+            prev_hash = record.hash;
+            records += 1;
+        }
+
+        Ok(AuditVerification {
+            valid: true,
+            records,
+            head_hash: prev_hash,
+            broken_at: None,
+            reason: None,
+        })
+    }
+}
+
+/// Who is behind a request, for recording what they do. Sessions are only
+/// known behind the authentication middleware; logins name their user
+/// themselves.
+#[derive(Clone)]
+pub struct AuditContext {
+    log: Arc<AuditLog>,
+    pub user_id: Option<String>,
+    pub ip_address: Option<IpAddr>,
+    pub request_id: Option<String>,
+    pub certificate: Option<String>,
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for AuditContext {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(address)| address.ip());
+
+        Ok(Self {
+            log: state.audit.clone(),
+            user_id: parts.extensions.get::<Session>().map(|session| session.user_id.clone()),
+            ip_address: client_ip(peer, &parts.headers, &state.config.server.trusted_proxies),
+            request_id: parts
+                .headers
+                .get("x-request-id")
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+            certificate: parts
+                .extensions
+                .get::<ClientCertificate>()
+                .map(|certificate| certificate.fingerprint.clone()),
+        })
+    }
+}
+
+impl AuditContext {
+    /// Attributes the request to the account a login tries to log into,
+    /// so failures show whom they were aimed at.
+    pub fn claiming(mut self, user_id: &str) -> Self {
+        self.user_id = Some(user_id.to_string());
+        self
+    }
+
+    /// Records an action of the request's user.
+    pub async fn record(&self, action: AuditAction, target: Option<&str>, detail: Option<&str>) -> AppResult<()> {
+        self.record_as(self.user_id.as_deref(), action, target, detail).await
+    }
+
+    /// Records an action on behalf of `user_id`, such as a login.
+    pub async fn record_as(
+        &self,
+        user_id: Option<&str>,
+        action: AuditAction,
+        target: Option<&str>,
+        detail: Option<&str>,
+    ) -> AppResult<()> {
+        let event = AuditEvent {
+            user_id: user_id.map(str::to_string),
+            target: target.map(str::to_string),
+            detail: detail.map(str::to_string),
+            ..self.event()
+        };
+        self.log.append(action, event).await?;
+        Ok(())
+    }
+
+    /// The request's attribution, for actions recorded elsewhere, such as
+    /// syncs on the sync actor.
+    pub fn event(&self) -> AuditEvent {
+        AuditEvent {
+            user_id: self.user_id.clone(),
+            ip_address: self.ip_address.map(|ip| ip.to_string()),
+            request_id: self.request_id.clone(),
+            certificate: self.certificate.clone(),
+            ..AuditEvent::default()
+        }
+    }
+}
+
+fn format_timestamp(timestamp: DateTime<Utc>) -> String {
+    timestamp.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+fn parse_timestamp(timestamp: &str) -> AppResult<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(timestamp)
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .map_err(|e| AppError::DatabaseError(format!("Invalid audit timestamp {}: {}", timestamp, e)))
+}
+
+/// SHA-256 of the previous hash and the record's fields, in a fixed order.
+fn record_hash(record: &AuditRecord) -> String {
+    let fields = serde_json::json!([
+        record.seq,
+        format_timestamp(record.timestamp),
+        action_name(record.action),
+        record.user_id,
+        record.ip_address,
+        record.request_id,
+        record.target,
+        record.detail,
+        record.certificate,
+    ]);
+    let mut context = digest::Context::new(&digest::SHA256);
+    context.update(record.prev_hash.as_bytes());
+    context.update(b"\n");
+    context.update(fields.to_string().as_bytes());
+    hex::encode(context.finish())
+}
+
+/// RFC 5424 message under the authpriv facility; failed logins are
+/// warnings, everything else a notice.
+fn to_syslog(record: &AuditRecord, hostname: &str) -> String {
+    let severity = if record.action == AuditAction::LoginFailed { 4 } else { 5 };
+    let params = [
+        ("seq", Some(record.seq.to_string())),
+        ("user", record.user_id.clone()),
+        ("ip", record.ip_address.clone()),
+        ("request_id", record.request_id.clone()),
+        ("target", record.target.clone()),
+        ("certificate", record.certificate.clone()),
+        ("hash", Some(record.hash.clone())),
+    ];
+    let structured_data: String = params
+        .iter()
+        .filter_map(|(name, value)| value.as_ref().map(|value| format!(" {}=\"{}\"", name, escape_param(value))))
+        .collect();
+
+    let mut message = format!(
+        "<{}>1 {} {} kagikanri - {} [{}{}]",
+        10 * 8 + severity,
+        format_timestamp(record.timestamp),
+        if hostname.is_empty() { "-" } else { hostname },
+        action_name(record.action),
+        SYSLOG_SD_ID,
+        structured_data
+    );
+    if let Some(detail) = &record.detail {
+        message.push(' ');
+        message.push_str(&detail.replace(['\r', '\n'], " "));
+    }
+    message
+}
+
+fn escape_param(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace(']', "\\]")
+}
+
+fn action_name(action: AuditAction) -> &'static str {
+    match action {
+        AuditAction::Login => "login",
+        AuditAction::LoginFailed => "login_failed",
+        AuditAction::Logout => "logout",
+        AuditAction::Reauth => "reauth",
+        AuditAction::Reveal => "reveal",
+        AuditAction::Write => "write",
+        AuditAction::Delete => "delete",
+        AuditAction::Export => "export",
+        AuditAction::Sync => "sync",
+        AuditAction::Admin => "admin",
+        AuditAction::Account => "account",
+    }
+}
+
+fn row_to_record(row: &sqlx::sqlite::SqliteRow) -> AppResult<AuditRecord> {
+    let action = serde_json::from_value(serde_json::Value::String(row.get("action")))
+        .map_err(|_| AppError::DatabaseError(format!("Unknown audit action: {}", row.get::<String, _>("action"))))?;
+
+    Ok(AuditRecord {
+        seq: row.get("seq"),
+        timestamp: parse_timestamp(row.get("timestamp"))?,
+        action,
+        user_id: row.get("user_id"),
+        ip_address: row.get("ip_address"),
+        request_id: row.get("request_id"),
+        target: row.get("target"),
+        detail: row.get("detail"),
+        certificate: row.get("certificate"),
+        prev_hash: row.get("prev_hash"),
+        hash: row.get("hash"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    async fn create_test_log() -> AuditLog {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        AuditLog::new(pool).await.unwrap()
+    }
+
+    fn event(user_id: &str, target: Option<&str>) -> AuditEvent {
+        AuditEvent {
+            user_id: Some(user_id.to_string()),
+            ip_address: Some("192.0.2.7".to_string()),
+            request_id: Some("req-1".to_string()),
+            target: target.map(str::to_string),
+            ..AuditEvent::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_records_are_chained() {
+        let log = create_test_log().await;
+        let first = log.append(AuditAction::Login, event("alice", Some("password"))).await.unwrap();
+        let second = log.append(AuditAction::Reveal, event("alice", Some("users/alice/mail"))).await.unwrap();
+
+        assert_eq!(first.seq, 1);
+        assert_eq!(first.prev_hash, GENESIS_HASH);
+        assert_eq!(second.prev_hash, first.hash);
+
+        let verification = log.verify().await.unwrap();
+        assert!(verification.valid);
+        assert_eq!(verification.records, 2);
+        assert_eq!(verification.head_hash, second.hash);
+    }
+
+    #[tokio::test]
+    async fn test_log_is_append_only_and_tampering_is_detected() {
+        let log = create_test_log().await;
+        for target in ["a", "b", "c"] {
+            log.append(AuditAction::Reveal, event("alice", Some(target))).await.unwrap();
+        }
+
+        assert!(sqlx::query("UPDATE audit_log SET user_id = 'mallory'").execute(&log.pool).await.is_err());
+        assert!(sqlx::query("DELETE FROM audit_log WHERE seq = 2").execute(&log.pool).await.is_err());
+
+        // Someone with the database file can drop the triggers, but not
+        // forge the chain
+        sqlx::query("DROP TRIGGER audit_log_no_update").execute(&log.pool).await.unwrap();
+        sqlx::query("UPDATE audit_log SET user_id = 'mallory' WHERE seq = 2")
+            .execute(&log.pool)
+            .await
+            .unwrap();
+        let verification = log.verify().await.unwrap();
+        assert!(!verification.valid);
+        assert_eq!(verification.broken_at, Some(2));
+
+        sqlx::query("DROP TRIGGER audit_log_no_delete").execute(&log.pool).await.unwrap();
+        sqlx::query("DELETE FROM audit_log WHERE seq = 1").execute(&log.pool).await.unwrap();
+        let verification = log.verify().await.unwrap();
+        assert_eq!(verification.broken_at, Some(2));
+        assert_eq!(verification.reason.as_deref(), Some("Records are missing before this one"));
+    }
+
+    #[tokio::test]
+    async fn test_filters_and_paging() {
+        let log = create_test_log().await;
+        log.append(AuditAction::LoginFailed, event("bob", None)).await.unwrap();
+        log.append(AuditAction::Reveal, event("alice", Some("users/alice/mail"))).await.unwrap();
+        log.append(AuditAction::Reveal, event("alice", Some("users/alice/bank/card"))).await.unwrap();
+        log.append(AuditAction::Reveal, event("alice", Some("users/alicea/mail"))).await.unwrap();
+
+        let query = AuditQuery {
+            user: Some("alice".to_string()),
+            target: Some("users/alice".to_string()),
+            per_page: Some(1),
+            ..AuditQuery::default()
+        };
+        let page = log.list(&query).await.unwrap();
+        assert_eq!(page.records[0].target.as_deref(), Some("users/alice/bank/card"));
+        assert!(page.has_more);
+        let page = log.list(&AuditQuery { page: Some(2), ..query }).await.unwrap();
+        assert_eq!(page.records[0].target.as_deref(), Some("users/alice/mail"));
+        assert!(!page.has_more);
+
+        let failures = AuditQuery {
+            action: Some(AuditAction::LoginFailed),
+            ..AuditQuery::default()
+        };
+        let page = log.list(&failures).await.unwrap();
+        assert_eq!(page.records.len(), 1);
+        assert_eq!(page.records[0].user_id.as_deref(), Some("bob"));
+
+        let future = AuditQuery {
+            since: Some(Utc::now() + chrono::Duration::minutes(1)),
+            ..AuditQuery::default()
+        };
+        assert!(log.list(&future).await.unwrap().records.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_export_formats() {
+        let log = create_test_log().await;
+        let mut failed = event("bob", None);
+        failed.detail = Some("Invalid credentials".to_string());
+        let first = log.append(AuditAction::LoginFailed, failed).await.unwrap();
+        log.append(AuditAction::Delete, event("alice", Some("odd\"path]"))).await.unwrap();
+
+        let jsonl = log.export(&AuditQuery::default(), ExportFormat::Jsonl, "vault.example.com").await.unwrap();
+        let lines: Vec<&str> = jsonl.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(serde_json::from_str::<AuditRecord>(lines[0]).unwrap(), first);
+
+        let syslog = log.export(&AuditQuery::default(), ExportFormat::Syslog, "vault.example.com").await.unwrap();
+        let lines: Vec<&str> = syslog.lines().collect();
+        assert_eq!(
+            lines[0],
+            format!(
+                "<84>1 {} vault.example.com kagikanri - login_failed [audit@32473 seq=\"1\" user=\"bob\" \
+                 ip=\"192.0.2.7\" request_id=\"req-1\" hash=\"{}\"] Invalid credentials",
+                format_timestamp(first.timestamp),
+                first.hash
+            )
+        );
+        assert!(lines[1].starts_with("<85>1 "));
+        assert!(lines[1].contains("target=\"odd\\\"path\\]\""));
+    }
+}
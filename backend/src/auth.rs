@@ -1,30 +1,99 @@
 use crate::{
-    config::AuthConfig,
+    config::{AuthConfig, OidcLogin, PasskeyLogin},
+    credentials::{self, CredentialStore, MIN_MASTER_PASSWORD_LENGTH},
     error::{AppError, AppResult},
-    pass::PassInterface,
+    login_passkeys::PasskeyAssertion,
+    oidc::OidcIdentity,
+    pass::{PassInterface, PasswordEntry},
+    proxy_auth::ProxyIdentity,
+    state::Session,
+    totp::Totp,
+    users::{self, Role, User, UserStatus, UserStore, BOOTSTRAP_ADMIN},
 };
+use rand::RngCore;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
-use totp_lite::{totp, Sha1};
+use std::{collections::HashMap, sync::Arc};
 use tracing::{debug, info, warn};
 
+/// Remaining recovery codes at or below which logins warn about them.
+const LOW_RECOVERY_CODES: usize = 3;
+
 #[derive(Debug, Clone)]
 pub struct AuthService {
     config: AuthConfig,
     pass: Arc<PassInterface>,
+    credentials: Arc<CredentialStore>,
+    users: Arc<UserStore>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct LoginRequest {
+    /// Account to log into; the bootstrap admin when omitted.
+    #[serde(default)]
+    pub username: Option<String>,
     pub master_password: String,
+    /// Current TOTP code, or one of the recovery codes.
     pub totp_code: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct ChangeMasterPasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+/// Step-up re-authentication with any one of the user's factors.
+#[derive(Debug, Deserialize)]
+pub struct ReauthRequest {
+    #[serde(default)]
+    pub master_password: Option<String>,
+    #[serde(default)]
+    pub totp_code: Option<String>,
+    #[serde(default)]
+    pub passkey: Option<PasskeyAssertion>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AcceptInviteRequest {
+    pub token: String,
+    pub master_password: String,
+}
+
+/// Credentials of a newly activated account. The TOTP URI and recovery
+/// codes are shown only in this response.
+#[derive(Debug, Serialize)]
+pub struct InviteAccepted {
+    pub user_id: String,
+    pub totp_uri: String,
+    /// Pass entry the TOTP URI was stored in.
+    pub totp_path: String,
+    pub recovery_codes: Vec<String>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct LoginResponse {
     pub success: bool,
     pub user_id: String,
+    pub role: Role,
     pub expires_at: chrono::DateTime<chrono::Utc>,
+    pub recovery_codes_remaining: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+}
+
+/// Second step of a first-factor OpenID Connect login.
+#[derive(Debug, Deserialize)]
+pub struct OidcTotpRequest {
+    /// Current TOTP code, or one of the recovery codes.
+    pub totp_code: String,
+}
+
+/// Where an OpenID Connect login leaves the user.
+#[derive(Debug)]
+pub enum OidcOutcome {
+    LoggedIn(LoginResponse),
+    /// The provider was a first factor; the user still owes a TOTP code.
+    TotpRequired(String),
 }
 
 #[derive(Debug, Serialize)]
@@ -34,89 +103,361 @@ pub struct AuthStatus {
 }
 
 impl AuthService {
-    pub fn new(config: AuthConfig, pass: Arc<PassInterface>) -> Self {
-        Self { config, pass }
+    pub fn new(
+        config: AuthConfig,
+        pass: Arc<PassInterface>,
+        credentials: Arc<CredentialStore>,
+        users: Arc<UserStore>,
+    ) -> Self {
+        Self { config, pass, credentials, users }
     }
 
     pub async fn authenticate(&self, request: LoginRequest) -> AppResult<LoginResponse> {
-        info!("Attempting authentication");
+        let user_id = request.username.as_deref().unwrap_or(BOOTSTRAP_ADMIN);
+        info!("Attempting authentication for {}", user_id);
+        let user = self.active_user(user_id).await?;
         
         // Verify master password
-        self.verify_master_password(&request.master_password).await?;
+        self.verify_master_password(&user.id, &request.master_password).await?;
         
-        // Verify TOTP code
-        self.verify_totp(&request.totp_code).await?;
+        // Verify TOTP code, or use up a recovery code in its place
+        if credentials::is_recovery_code(&request.totp_code) {
+            self.verify_recovery_code(&user.id, &request.totp_code).await?;
+        } else {
+            self.verify_totp(&user.id, &request.totp_code).await?;
+        }
         
+        info!("Authentication successful for {}", user.id);
+        self.login_response(&user).await
+    }
+
+    /// Completes a login for `user_id`, whose passkey assertion has been
+    /// verified. Unless passkeys are configured for passwordless login, the
+    /// passkey only replaces TOTP and the master password is still needed.
+    pub async fn authenticate_passkey(
+        &self,
+        user_id: String,
+        master_password: Option<&str>,
+    ) -> AppResult<LoginResponse> {
+        let user = self.active_user(&user_id).await?;
+        if self.config.passkey_login == PasskeyLogin::SecondFactor {
+            let master_password = master_password.ok_or_else(|| {
+                AppError::AuthenticationFailed("Master password is required with a passkey".to_string())
+            })?;
+            self.verify_master_password(&user.id, master_password).await?;
+        }
+
+        info!("Passkey authentication successful for {}", user.id);
+        self.login_response(&user).await
+    }
+
+    /// Logs in the account linked to an OpenID Connect identity. In
+    /// alternative mode, accounts are created and linked on first login;
+    /// roles follow the groups once admin groups are configured.
+    pub async fn authenticate_oidc(&self, identity: &OidcIdentity) -> AppResult<OidcOutcome> {
+        let oidc = self
+            .config
+            .oidc
+            .as_ref()
+            .ok_or_else(|| AppError::NotFound("OpenID Connect is not configured".to_string()))?;
+        let role = identity.role(oidc).ok_or_else(|| {
+            AppError::AuthenticationFailed(format!("{} is not in a group allowed to log in", identity.user_id))
+        })?;
+
+        let user = match self.users.get_by_oidc(&identity.issuer, &identity.subject).await? {
+            Some(user) if user.status != UserStatus::Active => {
+                return Err(AppError::AuthenticationFailed("Invalid credentials".to_string()));
+            }
+            Some(user) if !oidc.admin_groups.is_empty() => self.sync_role(user, role).await?,
+            Some(user) => user,
+            // Anyone who can set the user claim at the provider could take
+            // over a local account that matched it, so existing accounts
+            // must be linked by an admin first. First-factor mode has no
+            // TOTP secret to check without one.
+            None if oidc.login == OidcLogin::FirstFactor || self.users.get(&identity.user_id).await?.is_some() => {
+                warn!(
+                    "OpenID Connect subject {} ({}) is not linked to an account",
+                    identity.subject, identity.user_id
+                );
+                return Err(AppError::AuthenticationFailed("Invalid credentials".to_string()));
+            }
+            None => {
+                let user = self
+                    .users
+                    .provision(
+                        &identity.user_id,
+                        identity.email.as_deref(),
+                        role,
+                        Some((&identity.issuer, &identity.subject)),
+                    )
+                    .await?;
+                info!("Created account {} for OpenID Connect subject {}", user.id, identity.subject);
+                user
+            }
+        };
+
+        info!("OpenID Connect authentication successful for {}", user.id);
+        match oidc.login {
+            OidcLogin::Alternative => Ok(OidcOutcome::LoggedIn(self.login_response(&user).await?)),
+            OidcLogin::FirstFactor => Ok(OidcOutcome::TotpRequired(user.id)),
+        }
+    }
+
+    /// Checks a login asserted by a trusted reverse proxy. Only existing,
+    /// active accounts can log in, so the master password is there for
+    /// step-up re-authentication.
+    pub async fn authenticate_proxy(&self, identity: &ProxyIdentity) -> AppResult<User> {
+        let proxy_auth = self
+            .config
+            .proxy_auth
+            .as_ref()
+            .ok_or_else(|| AppError::NotFound("Reverse proxy authentication is not configured".to_string()))?;
+        // The bootstrap admin keeps its local credentials
+        if identity.user_id == BOOTSTRAP_ADMIN {
+            return Err(AppError::AuthenticationFailed("Invalid credentials".to_string()));
+        }
+        let role = identity.role(proxy_auth).ok_or_else(|| {
+            AppError::AuthenticationFailed(format!("{} is not in a group allowed to log in", identity.user_id))
+        })?;
+
+        let user = self.active_user(&identity.user_id).await?;
+        if proxy_auth.admin_groups.is_empty() {
+            Ok(user)
+        } else {
+            self.sync_role(user, role).await
+        }
+    }
+
+    /// Gives `user` the role their provider's groups grant. Demoting the
+    /// last admin is refused, in which case the old role is kept.
+    async fn sync_role(&self, user: User, role: Role) -> AppResult<User> {
+        if user.role == role {
+            return Ok(user);
+        }
+        match self.users.set_role(&user.id, role).await {
+            Ok(user) => Ok(user),
+            Err(AppError::Conflict(reason)) => {
+                warn!("Keeping the role of {}: {}", user.id, reason);
+                Ok(user)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Completes a first-factor OpenID Connect login with the TOTP code or
+    /// a recovery code.
+    pub async fn authenticate_oidc_totp(&self, user_id: &str, totp_code: &str) -> AppResult<LoginResponse> {
+        let user = self.active_user(user_id).await?;
+        if credentials::is_recovery_code(totp_code) {
+            self.verify_recovery_code(&user.id, totp_code).await?;
+        } else {
+            self.verify_totp(&user.id, totp_code).await?;
+        }
+
+        info!("Authentication successful for {}", user.id);
+        self.login_response(&user).await
+    }
+
+    /// Checks the master password or TOTP code of a re-authentication.
+    /// Passkey assertions are verified by the caller, which holds the
+    /// ceremonies.
+    pub async fn reauthenticate(&self, user_id: &str, request: &ReauthRequest) -> AppResult<()> {
+        if let Some(master_password) = &request.master_password {
+            self.verify_master_password(user_id, master_password).await?;
+        } else if let Some(totp_code) = &request.totp_code {
+            self.verify_totp(user_id, totp_code).await?;
+        } else {
+            return Err(AppError::ValidationError(
+                "Provide the master password, a TOTP code or a passkey".to_string(),
+            ));
+        }
+
+        info!("{} re-authenticated", user_id);
+        Ok(())
+    }
+
+    /// Disabled and invited accounts fail like wrong credentials, so logins
+    /// do not reveal which accounts exist.
+    async fn active_user(&self, user_id: &str) -> AppResult<User> {
+        self.users
+            .get_active(user_id)
+            .await?
+            .ok_or_else(|| AppError::AuthenticationFailed("Invalid credentials".to_string()))
+    }
+
+    async fn login_response(&self, user: &User) -> AppResult<LoginResponse> {
         let expires_at = chrono::Utc::now() + chrono::Duration::hours(self.config.session_timeout_hours as i64);
+        let recovery_codes_remaining = self.credentials.recovery_codes_remaining(&user.id).await?;
+        let warning = (recovery_codes_remaining <= LOW_RECOVERY_CODES).then(|| {
+            format!(
+                "{} recovery codes left; generate a new set to keep access if the authenticator is lost",
+                recovery_codes_remaining
+            )
+        });
         
-        info!("Authentication successful");
         Ok(LoginResponse {
             success: true,
-            user_id: "user".to_string(), // Simple single-user system
+            user_id: user.id.clone(),
+            role: user.role,
             expires_at,
+            recovery_codes_remaining,
+            warning,
         })
     }
 
-    pub async fn get_auth_status(&self, session_id: Option<String>) -> AuthStatus {
-        // Simple implementation - in a real system you'd check the session store
-        if session_id.is_some() {
-            AuthStatus {
-                user_id: Some("user".to_string()),
-                expires_at: Some(chrono::Utc::now() + chrono::Duration::hours(self.config.session_timeout_hours as i64)),
-            }
-        } else {
-            AuthStatus {
-                user_id: None,
-                expires_at: None,
-            }
+    /// Activates an invited account: sets its master password, enrolls a
+    /// new TOTP secret in the store and generates recovery codes. The
+    /// caller is responsible for committing the TOTP entry.
+    pub async fn accept_invite(&self, request: AcceptInviteRequest) -> AppResult<InviteAccepted> {
+        let user = self.users.invited(&request.token).await?;
+        validate_new_password(&request.master_password)?;
+        let verifier = credentials::hash_password(request.master_password).await?;
+
+        let mut secret = [0u8; 20];
+        rand::rngs::OsRng.fill_bytes(&mut secret);
+        let totp_uri = format!(
+            "otpauth://totp/Kagikanri:{}?secret={}&issuer=Kagikanri&algorithm=SHA1&digits=6&period=30",
+            user.id,
+            base32::encode(base32::Alphabet::RFC4648 { padding: false }, &secret)
+        );
+        let totp_path = users::totp_path(&user.id);
+        self.pass
+            .create_or_update_password(&totp_path, &PasswordEntry {
+                password: totp_uri.clone(),
+                metadata: HashMap::new(),
+            })
+            .await?;
+
+        self.users.activate(&user.id, &request.token).await?;
+        self.credentials.set_master_password_verifier(&user.id, &verifier).await?;
+        let recovery_codes = self.credentials.regenerate_recovery_codes(&user.id).await?;
+
+        info!("{} accepted the invitation", user.id);
+        Ok(InviteAccepted {
+            user_id: user.id,
+            totp_uri,
+            totp_path,
+            recovery_codes,
+        })
+    }
+
+    /// Replaces `user_id`'s recovery codes and returns the new set.
+    pub async fn regenerate_recovery_codes(&self, user_id: &str) -> AppResult<Vec<String>> {
+        let codes = self.credentials.regenerate_recovery_codes(user_id).await?;
+        info!("Recovery codes of {} regenerated", user_id);
+        Ok(codes)
+    }
+
+    pub async fn get_auth_status(&self, session: Option<&Session>) -> AuthStatus {
+        AuthStatus {
+            user_id: session.map(|session| session.user_id.clone()),
+            expires_at: session.map(|session| session.expires_at),
+        }
+    }
+
+    /// Replaces `user_id`'s master password verifier. The caller is
+    /// responsible for ending the user's sessions.
+    pub async fn change_master_password(&self, user_id: &str, request: ChangeMasterPasswordRequest) -> AppResult<()> {
+        self.verify_master_password(user_id, &request.current_password).await?;
+
+        validate_new_password(&request.new_password)?;
+        if request.new_password == request.current_password {
+            return Err(AppError::ValidationError(
+                "New master password must differ from the current one".to_string(),
+            ));
         }
+
+        let verifier = credentials::hash_password(request.new_password).await?;
+        self.credentials.set_master_password_verifier(user_id, &verifier).await?;
+
+        info!("Master password of {} changed", user_id);
+        Ok(())
     }
 
-    async fn verify_master_password(&self, provided_password: &str) -> AppResult<()> {
+    async fn verify_master_password(&self, user_id: &str, provided_password: &str) -> AppResult<()> {
         debug!("Verifying master password");
         
-        let stored_password = self.pass
-            .get_password(&self.config.master_password_path)
-            .await?;
+        let verifier = match self.credentials.master_password_verifier(user_id).await? {
+            Some(verifier) => verifier,
+            None if user_id == BOOTSTRAP_ADMIN => self.migrate_master_password().await?,
+            None => return Err(AppError::AuthenticationFailed("Invalid master password".to_string())),
+        };
         
-        if provided_password == stored_password.password {
+        if credentials::verify_password(provided_password.to_string(), verifier).await? {
             Ok(())
         } else {
             Err(AppError::AuthenticationFailed("Invalid master password".to_string()))
         }
     }
 
-    async fn verify_totp(&self, provided_code: &str) -> AppResult<()> {
+    /// Creates the verifier from the plaintext master password entry of
+    /// earlier versions. After this the entry is no longer read.
+    async fn migrate_master_password(&self) -> AppResult<String> {
+        let stored_password = self.pass
+            .get_password(&self.config.master_password_path)
+            .await?;
+        
+        let verifier = credentials::hash_password(stored_password.password).await?;
+        self.credentials.set_master_password_verifier(BOOTSTRAP_ADMIN, &verifier).await?;
+        
+        info!(
+            "Migrated master password to an Argon2id verifier; {} can be removed from the store",
+            self.config.master_password_path
+        );
+        Ok(verifier)
+    }
+
+    async fn verify_totp(&self, user_id: &str, provided_code: &str) -> AppResult<()> {
         debug!("Verifying TOTP code");
         
-        // Get TOTP secret from pass store
+        // Get TOTP parameters from pass store
+        let totp_path = if user_id == BOOTSTRAP_ADMIN {
+            self.config.totp_path.clone()
+        } else {
+            users::totp_path(user_id)
+        };
         let totp_entry = self.pass
-            .get_password(&self.config.totp_path)
+            .get_password(&totp_path)
             .await?;
+        let totp = Totp::from_entry(&totp_entry)?;
         
-        let secret_base32 = &totp_entry.password;
-        
-        // Decode base32 secret
-        let secret_bytes = base32::decode(base32::Alphabet::RFC4648 { padding: true }, secret_base32)
-            .ok_or_else(|| AppError::AuthenticationFailed("Invalid TOTP secret format".to_string()))?;
-        
-        // Get current time window
         let current_time = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap()
             .as_secs();
         
-        // Check current window and previous window (to account for clock drift)
-        let windows = [current_time / 30, (current_time / 30) - 1];
+        let step = totp
+            .matching_step(
+                provided_code.trim(),
+                current_time,
+                self.config.totp_skew_past,
+                self.config.totp_skew_future,
+            )
+            .ok_or_else(|| AppError::AuthenticationFailed("Invalid TOTP code".to_string()))?;
         
-        for window in windows {
-            let expected_code = totp::<Sha1>(&secret_bytes, window);
-            if provided_code == expected_code {
-                return Ok(());
-            }
+        // Remember the step until it leaves the window, so an intercepted
+        // code cannot be replayed
+        let oldest_valid = totp.step(current_time).saturating_sub(self.config.totp_skew_past);
+        if !self.credentials.consume_totp_step(user_id, step, oldest_valid).await? {
+            return Err(AppError::AuthenticationFailed("TOTP code has already been used".to_string()));
+        }
+        
+        Ok(())
+    }
+
+    async fn verify_recovery_code(&self, user_id: &str, code: &str) -> AppResult<()> {
+        debug!("Verifying recovery code");
+        
+        if !self.credentials.consume_recovery_code(user_id, code).await? {
+            return Err(AppError::AuthenticationFailed("Invalid recovery code".to_string()));
         }
         
-        Err(AppError::AuthenticationFailed("Invalid TOTP code".to_string()))
+        warn!(
+            "{} logged in with a recovery code, {} left",
+            user_id,
+            self.credentials.recovery_codes_remaining(user_id).await?
+        );
+        Ok(())
     }
 
     pub fn extract_session_from_header(&self, auth_header: Option<&str>) -> Option<String> {
@@ -129,94 +470,346 @@ impl AuthService {
     }
 }
 
+fn validate_new_password(password: &str) -> AppResult<()> {
+    if password.chars().count() < MIN_MASTER_PASSWORD_LENGTH {
+        return Err(AppError::ValidationError(format!(
+            "Master password must be at least {} characters",
+            MIN_MASTER_PASSWORD_LENGTH
+        )));
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::{AuthConfig, PassConfig};
+    use crate::config::{AuthConfig, PassConfig, ProxyAuthConfig};
+    use crate::oidc::{
+        tests::{test_config as oidc_test_config, MockIdp},
+        OidcClient,
+    };
     use std::path::PathBuf;
+    use totp_lite::{totp, Sha1};
 
     fn create_test_config() -> AuthConfig {
         AuthConfig {
             master_password_path: "kagikanri/master-password".to_string(),
             totp_path: "kagikanri/totp".to_string(),
             session_timeout_hours: 24,
+            ..AuthConfig::default()
         }
     }
 
-    #[test]
-    fn test_extract_session_from_header_success() {
-        let config = create_test_config();
-        // Create a dummy PassInterface for testing
+    async fn create_test_auth_service() -> AuthService {
+        // Entries are never read once a verifier is stored
         let pass_config = PassConfig {
             store_dir: PathBuf::from("/tmp/test"),
             gpg_key_id: Some("test-key-id".to_string()),
         };
         let pass_interface = PassInterface::new(pass_config).unwrap();
-        let auth_service = AuthService::new(config, Arc::new(pass_interface));
+        let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+        let credentials = CredentialStore::new(pool.clone()).await.unwrap();
+        let users = UserStore::new(pool).await.unwrap();
+        AuthService::new(create_test_config(), Arc::new(pass_interface), Arc::new(credentials), Arc::new(users))
+    }
+
+    async fn store_verifier(auth_service: &AuthService, user_id: &str, password: &str) {
+        let verifier = credentials::hash_password(password.to_string()).await.unwrap();
+        auth_service.credentials.set_master_password_verifier(user_id, &verifier).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_extract_session_from_header_success() {
+        let auth_service = create_test_auth_service().await;
 
         let session_id = auth_service.extract_session_from_header(Some("Bearer abc123def456"));
         assert_eq!(session_id, Some("abc123def456".to_string()));
     }
 
-    #[test]
-    fn test_extract_session_from_header_invalid_format() {
-        let config = create_test_config();
-        let pass_config = PassConfig {
-            store_dir: PathBuf::from("/tmp/test"),
-            gpg_key_id: Some("test-key-id".to_string()),
-        };
-        let pass_interface = PassInterface::new(pass_config).unwrap();
-        let auth_service = AuthService::new(config, Arc::new(pass_interface));
+    #[tokio::test]
+    async fn test_extract_session_from_header_invalid_format() {
+        let auth_service = create_test_auth_service().await;
 
         let session_id = auth_service.extract_session_from_header(Some("InvalidFormat abc123"));
         assert_eq!(session_id, None);
     }
 
-    #[test]
-    fn test_extract_session_from_header_none() {
-        let config = create_test_config();
-        let pass_config = PassConfig {
-            store_dir: PathBuf::from("/tmp/test"),
-            gpg_key_id: Some("test-key-id".to_string()),
-        };
-        let pass_interface = PassInterface::new(pass_config).unwrap();
-        let auth_service = AuthService::new(config, Arc::new(pass_interface));
+    #[tokio::test]
+    async fn test_extract_session_from_header_none() {
+        let auth_service = create_test_auth_service().await;
 
         let session_id = auth_service.extract_session_from_header(None);
         assert_eq!(session_id, None);
     }
 
-    #[test]
-    fn test_get_auth_status_with_session() {
-        let config = create_test_config();
-        let pass_config = PassConfig {
-            store_dir: PathBuf::from("/tmp/test"),
-            gpg_key_id: Some("test-key-id".to_string()),
+    #[tokio::test]
+    async fn test_get_auth_status_with_session() {
+        let auth_service = create_test_auth_service().await;
+
+        let now = chrono::Utc::now();
+        let session = Session {
+            id: "session-id".to_string(),
+            user_id: "alice".to_string(),
+            created_at: now,
+            last_seen_at: now,
+            expires_at: now + chrono::Duration::hours(1),
+            absolute_expires_at: now + chrono::Duration::hours(24),
+            authenticated_at: now,
+            user_agent: None,
+            ip_address: None,
         };
-        let pass_interface = PassInterface::new(pass_config).unwrap();
-        let auth_service = AuthService::new(config, Arc::new(pass_interface));
 
-        let status = tokio_test::block_on(auth_service.get_auth_status(Some("session123".to_string())));
-        assert!(status.user_id.is_some());
-        assert_eq!(status.user_id.unwrap(), "user");
-        assert!(status.expires_at.is_some());
+        let status = auth_service.get_auth_status(Some(&session)).await;
+        assert_eq!(status.user_id.as_deref(), Some("alice"));
+        assert_eq!(status.expires_at, Some(session.expires_at));
     }
 
-    #[test]
-    fn test_get_auth_status_without_session() {
-        let config = create_test_config();
-        let pass_config = PassConfig {
-            store_dir: PathBuf::from("/tmp/test"),
-            gpg_key_id: Some("test-key-id".to_string()),
-        };
-        let pass_interface = PassInterface::new(pass_config).unwrap();
-        let auth_service = AuthService::new(config, Arc::new(pass_interface));
+    #[tokio::test]
+    async fn test_get_auth_status_without_session() {
+        let auth_service = create_test_auth_service().await;
 
-        let status = tokio_test::block_on(auth_service.get_auth_status(None));
+        let status = auth_service.get_auth_status(None).await;
         assert!(status.user_id.is_none());
         assert!(status.expires_at.is_none());
     }
 
+    #[tokio::test]
+    async fn test_master_password_is_checked_against_verifier() {
+        let auth_service = create_test_auth_service().await;
+        store_verifier(&auth_service, BOOTSTRAP_ADMIN, "correct horse battery").await;
+
+        assert!(auth_service.verify_master_password(BOOTSTRAP_ADMIN, "correct horse battery").await.is_ok());
+        assert!(matches!(
+            auth_service.verify_master_password(BOOTSTRAP_ADMIN, "wrong").await,
+            Err(AppError::AuthenticationFailed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_reauthenticate_with_master_password() {
+        let auth_service = create_test_auth_service().await;
+        store_verifier(&auth_service, BOOTSTRAP_ADMIN, "correct horse battery").await;
+        let request = |master_password: Option<&str>| ReauthRequest {
+            master_password: master_password.map(str::to_string),
+            totp_code: None,
+            passkey: None,
+        };
+
+        assert!(auth_service.reauthenticate(BOOTSTRAP_ADMIN, &request(Some("correct horse battery"))).await.is_ok());
+        assert!(matches!(
+            auth_service.reauthenticate(BOOTSTRAP_ADMIN, &request(Some("wrong"))).await,
+            Err(AppError::AuthenticationFailed(_))
+        ));
+        assert!(matches!(
+            auth_service.reauthenticate(BOOTSTRAP_ADMIN, &request(None)).await,
+            Err(AppError::ValidationError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_change_master_password() {
+        let auth_service = create_test_auth_service().await;
+        store_verifier(&auth_service, BOOTSTRAP_ADMIN, "correct horse battery").await;
+
+        let change = |current: &str, new: &str| ChangeMasterPasswordRequest {
+            current_password: current.to_string(),
+            new_password: new.to_string(),
+        };
+        assert!(matches!(
+            auth_service.change_master_password(BOOTSTRAP_ADMIN, change("wrong", "a much longer passphrase")).await,
+            Err(AppError::AuthenticationFailed(_))
+        ));
+        assert!(matches!(
+            auth_service.change_master_password(BOOTSTRAP_ADMIN, change("correct horse battery", "short")).await,
+            Err(AppError::ValidationError(_))
+        ));
+
+        auth_service
+            .change_master_password(BOOTSTRAP_ADMIN, change("correct horse battery", "a much longer passphrase"))
+            .await
+            .unwrap();
+        assert!(auth_service.verify_master_password(BOOTSTRAP_ADMIN, "correct horse battery").await.is_err());
+        assert!(auth_service.verify_master_password(BOOTSTRAP_ADMIN, "a much longer passphrase").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_recovery_code_replaces_totp() {
+        let auth_service = create_test_auth_service().await;
+        store_verifier(&auth_service, BOOTSTRAP_ADMIN, "correct horse battery").await;
+        let codes = auth_service.regenerate_recovery_codes(BOOTSTRAP_ADMIN).await.unwrap();
+
+        let login = |code: &str| LoginRequest {
+            username: None,
+            master_password: "correct horse battery".to_string(),
+            totp_code: code.to_string(),
+        };
+        let response = auth_service.authenticate(login(&codes[0])).await.unwrap();
+        assert_eq!(response.recovery_codes_remaining, codes.len() - 1);
+        assert!(response.warning.is_none());
+
+        assert!(matches!(
+            auth_service.authenticate(login(&codes[0])).await,
+            Err(AppError::AuthenticationFailed(_))
+        ));
+        for code in &codes[1..7] {
+            assert!(auth_service.credentials.consume_recovery_code(BOOTSTRAP_ADMIN, code).await.unwrap());
+        }
+        let response = auth_service.authenticate(login(&codes[7])).await.unwrap();
+        assert_eq!(response.recovery_codes_remaining, 2);
+        assert!(response.warning.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_only_active_users_can_log_in() {
+        let auth_service = create_test_auth_service().await;
+        let (_, token) = auth_service
+            .users
+            .invite(&users::InviteRequest {
+                user_id: "alice".to_string(),
+                email: "alice@example.com".to_string(),
+                role: Role::Member,
+            })
+            .await
+            .unwrap();
+        store_verifier(&auth_service, "alice", "alice's passphrase").await;
+        let codes = auth_service.regenerate_recovery_codes("alice").await.unwrap();
+
+        let login = |code: &str| LoginRequest {
+            username: Some("alice".to_string()),
+            master_password: "alice's passphrase".to_string(),
+            totp_code: code.to_string(),
+        };
+        // Invited users cannot log in before accepting
+        assert!(matches!(
+            auth_service.authenticate(login(&codes[0])).await,
+            Err(AppError::AuthenticationFailed(_))
+        ));
+
+        auth_service.users.activate("alice", &token).await.unwrap();
+        let response = auth_service.authenticate(login(&codes[0])).await.unwrap();
+        assert_eq!((response.user_id.as_str(), response.role), ("alice", Role::Member));
+
+        // Credentials belong to one account
+        assert!(auth_service.verify_master_password(BOOTSTRAP_ADMIN, "alice's passphrase").await.is_err());
+
+        auth_service.users.set_disabled("alice", true).await.unwrap();
+        assert!(matches!(
+            auth_service.authenticate(login(&codes[1])).await,
+            Err(AppError::AuthenticationFailed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_oidc_login_creates_account_from_mock_provider() {
+        let idp = MockIdp::start().await;
+        let mut auth_service = create_test_auth_service().await;
+        auth_service.config.oidc = Some(idp.config());
+        let client = OidcClient::new(idp.config());
+
+        let login = |subject: &'static str, username: &'static str, groups: serde_json::Value| {
+            let client = &client;
+            let idp = &idp;
+            async move {
+                let start = client.start().await.unwrap();
+                let claims = serde_json::json!({"sub": subject, "preferred_username": username, "groups": groups});
+                let code = idp.authorize(&start, claims);
+                client.finish(&start.state, &code).await.unwrap()
+            }
+        };
+
+        let identity = login("42", "carol", serde_json::json!(["kagikanri-admins"])).await;
+        let OidcOutcome::LoggedIn(response) = auth_service.authenticate_oidc(&identity).await.unwrap() else {
+            panic!("alternative mode logs in directly");
+        };
+        assert_eq!((response.user_id.as_str(), response.role), ("carol", Role::Admin));
+
+        // Later logins find the account by subject, and roles follow the groups
+        let identity = login("42", "carol.renamed", serde_json::json!([])).await;
+        let OidcOutcome::LoggedIn(response) = auth_service.authenticate_oidc(&identity).await.unwrap() else {
+            panic!("alternative mode logs in directly");
+        };
+        assert_eq!((response.user_id.as_str(), response.role), ("carol", Role::Member));
+
+        // Another subject claiming an existing account is refused, whether
+        // that account is linked or local
+        auth_service.users.provision("dave", None, Role::Member, None).await.unwrap();
+        for username in ["carol", "dave", BOOTSTRAP_ADMIN] {
+            let identity = login("43", username, serde_json::json!([])).await;
+            assert!(matches!(
+                auth_service.authenticate_oidc(&identity).await,
+                Err(AppError::AuthenticationFailed(_))
+            ));
+        }
+
+        auth_service.users.set_disabled("carol", true).await.unwrap();
+        assert!(matches!(
+            auth_service.authenticate_oidc(&identity).await,
+            Err(AppError::AuthenticationFailed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_oidc_first_factor_needs_linked_account() {
+        let mut auth_service = create_test_auth_service().await;
+        let mut oidc = oidc_test_config("https://idp.example.com");
+        oidc.login = OidcLogin::FirstFactor;
+        auth_service.config.oidc = Some(oidc);
+        let identity = |user_id: &str| OidcIdentity {
+            issuer: "https://idp.example.com".to_string(),
+            subject: "42".to_string(),
+            user_id: user_id.to_string(),
+            email: None,
+            groups: Vec::new(),
+        };
+
+        assert!(matches!(
+            auth_service.authenticate_oidc(&identity("dave")).await,
+            Err(AppError::AuthenticationFailed(_))
+        ));
+        assert!(matches!(
+            auth_service.authenticate_oidc(&identity(BOOTSTRAP_ADMIN)).await,
+            Err(AppError::AuthenticationFailed(_))
+        ));
+
+        auth_service.users.provision("dave", None, Role::Member, None).await.unwrap();
+        let codes = auth_service.regenerate_recovery_codes("dave").await.unwrap();
+        // An account is only logged into once linked to the subject
+        assert!(matches!(
+            auth_service.authenticate_oidc(&identity("dave")).await,
+            Err(AppError::AuthenticationFailed(_))
+        ));
+        auth_service.users.link_oidc("dave", "https://idp.example.com", "42").await.unwrap();
+        assert!(matches!(
+            auth_service.authenticate_oidc(&identity("dave")).await,
+            Ok(OidcOutcome::TotpRequired(user_id)) if user_id == "dave"
+        ));
+        let response = auth_service.authenticate_oidc_totp("dave", &codes[0]).await.unwrap();
+        assert_eq!(response.user_id, "dave");
+    }
+
+    #[tokio::test]
+    async fn test_passkey_login_needs_master_password_as_second_factor() {
+        let mut auth_service = create_test_auth_service().await;
+        store_verifier(&auth_service, BOOTSTRAP_ADMIN, "correct horse battery").await;
+
+        assert!(matches!(
+            auth_service.authenticate_passkey(BOOTSTRAP_ADMIN.to_string(), None).await,
+            Err(AppError::AuthenticationFailed(_))
+        ));
+        assert!(auth_service
+            .authenticate_passkey(BOOTSTRAP_ADMIN.to_string(), Some("wrong"))
+            .await
+            .is_err());
+        let response = auth_service
+            .authenticate_passkey(BOOTSTRAP_ADMIN.to_string(), Some("correct horse battery"))
+            .await
+            .unwrap();
+        assert_eq!(response.user_id, "user");
+
+        auth_service.config.passkey_login = PasskeyLogin::Passwordless;
+        assert!(auth_service.authenticate_passkey(BOOTSTRAP_ADMIN.to_string(), None).await.is_ok());
+    }
+
     #[test]
     fn test_totp_validation_logic() {
         // Test TOTP calculation logic directly
@@ -258,4 +851,41 @@ mod tests {
         let result = base32::decode(base32::Alphabet::RFC4648 { padding: true }, invalid_secret);
         assert!(result.is_none());
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_proxy_login_needs_existing_account_in_allowed_group() {
+        let mut auth_service = create_test_auth_service().await;
+        auth_service.config.proxy_auth = Some(ProxyAuthConfig {
+            trusted_proxies: vec!["10.0.0.1".to_string()],
+            user_header: "Remote-User".to_string(),
+            groups_header: "Remote-Groups".to_string(),
+            admin_groups: vec!["vault-admins".to_string()],
+            member_groups: vec!["vault".to_string()],
+        });
+        let identity = |user_id: &str, group: &str| ProxyIdentity {
+            user_id: user_id.to_string(),
+            groups: vec![group.to_string()],
+        };
+
+        // No account is created for the proxy's users
+        assert!(matches!(
+            auth_service.authenticate_proxy(&identity("erin", "vault")).await,
+            Err(AppError::AuthenticationFailed(_))
+        ));
+        assert!(matches!(
+            auth_service.authenticate_proxy(&identity(BOOTSTRAP_ADMIN, "vault-admins")).await,
+            Err(AppError::AuthenticationFailed(_))
+        ));
+
+        auth_service.users.provision("erin", None, Role::Member, None).await.unwrap();
+        assert!(matches!(
+            auth_service.authenticate_proxy(&identity("erin", "staff")).await,
+            Err(AppError::AuthenticationFailed(_))
+        ));
+        let user = auth_service.authenticate_proxy(&identity("erin", "vault-admins")).await.unwrap();
+        assert_eq!(user.role, Role::Admin);
+
+        auth_service.users.set_disabled("erin", true).await.unwrap();
+        assert!(auth_service.authenticate_proxy(&identity("erin", "vault-admins")).await.is_err());
+    }
+}
@@ -1,34 +1,197 @@
 use axum::{
-    extract::Request,
+    extract::{ConnectInfo, OriginalUri, Request},
     http::{header, HeaderMap, StatusCode},
     middleware::Next,
-    response::Response,
+    response::{IntoResponse, Response},
+};
+use std::net::{IpAddr, SocketAddr};
+use crate::{
+    api_tokens::TOKEN_PREFIX,
+    audit::{AuditAction, AuditEvent},
+    client_ip::client_ip,
+    error::AppError,
+    proxy_auth::{self, ProxyIdentity},
+    state::{AppState, Session},
+    tls::ClientCertificate,
 };
-use crate::state::AppState;
 
 pub async fn auth_middleware(
-    headers: HeaderMap,
     state: axum::extract::State<AppState>,
-    request: Request,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    mut request: Request,
     next: Next,
 ) -> Result<Response, StatusCode> {
-    // Skip authentication for public routes
-    let path = request.uri().path();
-    if path.starts_with("/api/auth/login") || path.starts_with("/api/health") || path.starts_with("/assets") || path == "/" {
+    // Identity headers never reach handlers, and are only believed from
+    // trusted proxies
+    let peer = connect_info.map(|ConnectInfo(address)| address.ip());
+    let proxy_identity = state
+        .config
+        .auth
+        .proxy_auth
+        .as_ref()
+        .and_then(|config| proxy_auth::take_identity(request.headers_mut(), peer, config));
+    let headers = request.headers().clone();
+
+    // Skip authentication for public routes. Nested routers see the path
+    // without their prefix, so match against the original one.
+    let path = request
+        .extensions()
+        .get::<OriginalUri>()
+        .map(|uri| uri.path().to_string())
+        .unwrap_or_else(|| request.uri().path().to_string());
+    let path = path.as_str();
+    if path.starts_with("/api/auth/login")
+        || path == "/api/auth/invite/accept"
+        || path.starts_with("/api/health")
+        || path.starts_with("/assets")
+        || path == "/"
+    {
+        return Ok(next.run(request).await);
+    }
+
+    // API tokens are only accepted in the Authorization header, or found
+    // from the client certificate of requests without other credentials,
+    // and work within their scope
+    let certificate = request.extensions().get::<ClientCertificate>().cloned();
+    let api_token = match (extract_api_token(&headers), &certificate) {
+        (Some(token), _) => match state.api_tokens.get(&token).await {
+            Ok(Some(api_token)) => Some(api_token),
+            Ok(None) => return Err(StatusCode::UNAUTHORIZED),
+            Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+        },
+        (None, Some(certificate)) if extract_session(&headers).is_none() => {
+            match state.api_tokens.find_by_certificate(certificate).await {
+                Ok(api_token) => api_token,
+                Err(e @ AppError::AuthenticationFailed(_)) => return Ok(e.into_response()),
+                Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+            }
+        }
+        _ => None,
+    };
+    if let Some(api_token) = api_token {
+        let active = state
+            .users
+            .get_active(&api_token.user_id)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        if active.is_none() {
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+
+        let client = client_ip(peer, &headers, &state.config.server.trusted_proxies);
+        let api_path = path.strip_prefix("/api").unwrap_or(path);
+        if let Err(e) = api_token.authorize(request.method(), api_path, client, certificate.as_ref()) {
+            return Ok(e.into_response());
+        }
+        if let Some(certificate) = &certificate {
+            tracing::debug!(
+                "Token {} of {} used with client certificate {} ({})",
+                api_token.id,
+                api_token.user_id,
+                certificate.fingerprint,
+                certificate.subject
+            );
+        }
+        state
+            .api_tokens
+            .touch(&api_token.id, client)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+        request.extensions_mut().insert(api_token.session(client));
+        request.extensions_mut().insert(api_token);
         return Ok(next.run(request).await);
     }
 
     // Extract session from cookie or Authorization header
     let session_id = extract_session(&headers);
     
-    match session_id {
-        Some(id) if state.is_authenticated(&id).await => {
+    let session = match session_id {
+        Some(token) => state
+            .get_session(&token)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+        None => None,
+    };
+
+    if let Some(identity) = proxy_identity {
+        let client = client_ip(peer, &headers, &state.config.server.trusted_proxies);
+        return proxy_session(&state, &identity, session, client, request, next).await;
+    }
+
+    match session {
+        Some(session) => {
+            // Make the session available to handlers, e.g. for commit authorship
+            request.extensions_mut().insert(session);
             Ok(next.run(request).await)
         }
-        _ => Err(StatusCode::UNAUTHORIZED),
+        None => Err(StatusCode::UNAUTHORIZED),
     }
 }
 
+/// Serves a request for a user the reverse proxy vouches for. Their
+/// session is kept while it belongs to the same user; otherwise a new one
+/// is opened, which has to be re-authenticated before secrets are revealed.
+async fn proxy_session(
+    state: &AppState,
+    identity: &ProxyIdentity,
+    session: Option<Session>,
+    client: Option<IpAddr>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let user = match state.auth_service().authenticate_proxy(identity).await {
+        Ok(user) => user,
+        Err(e) => return Ok(e.into_response()),
+    };
+    if let Some(session) = session.filter(|session| session.user_id == user.id) {
+        request.extensions_mut().insert(session);
+        return Ok(next.run(request).await);
+    }
+
+    let user_agent = request
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let ip_address = client.map(|ip| ip.to_string());
+    let (token, session) = state
+        .sessions
+        .create_unverified(&user.id, user_agent.as_deref(), ip_address.as_deref())
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    tracing::info!("Opened a session for {} asserted by the reverse proxy", user.id);
+    let event = AuditEvent {
+        user_id: Some(user.id.clone()),
+        ip_address,
+        request_id: request
+            .headers()
+            .get("x-request-id")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string),
+        target: Some("proxy".to_string()),
+        ..Default::default()
+    };
+    if let Err(e) = state.audit.append(AuditAction::Login, event).await {
+        tracing::warn!("Failed to audit proxy login of {}: {}", user.id, e);
+    }
+
+    request.extensions_mut().insert(session);
+    let mut response = next.run(request).await;
+    let cookie = state
+        .session_cookie(&token)
+        .parse()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    response.headers_mut().append(header::SET_COOKIE, cookie);
+    Ok(response)
+}
+
+fn extract_api_token(headers: &HeaderMap) -> Option<String> {
+    let auth_str = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let token = auth_str.strip_prefix("Bearer ")?;
+    token.starts_with(TOKEN_PREFIX).then(|| token.to_string())
+}
+
 fn extract_session(headers: &HeaderMap) -> Option<String> {
     // Try to get session from cookie first
     if let Some(cookie_header) = headers.get(header::COOKIE) {
@@ -0,0 +1,106 @@
+use axum::http::HeaderMap;
+use std::net::IpAddr;
+use crate::error::{AppError, AppResult};
+
+/// Address of the client behind a request. `X-Forwarded-For` is only
+/// believed when the connection comes from a trusted proxy, and then the
+/// right-most address not added by a trusted proxy is used, since clients
+/// can put anything at the start of the header. Proxies are given as
+/// addresses or CIDR ranges.
+pub fn client_ip(peer: Option<IpAddr>, headers: &HeaderMap, trusted_proxies: &[String]) -> Option<IpAddr> {
+    let trusted = |address: IpAddr| trusted_proxies.iter().any(|range| ip_in_range(address, range));
+    let peer = peer?;
+    if !trusted(peer) {
+        return Some(peer);
+    }
+
+    let forwarded: Vec<IpAddr> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|address| address.trim().parse().ok())
+        .collect();
+
+    forwarded
+        .iter()
+        .rev()
+        .find(|address| !trusted(**address))
+        .or(forwarded.first())
+        .copied()
+        .or(Some(peer))
+}
+
+/// Parses an address or a CIDR range into its network and prefix length.
+pub fn parse_range(range: &str) -> AppResult<(IpAddr, u32)> {
+    let invalid = || AppError::ValidationError(format!("Invalid address or CIDR range: {}", range));
+    let (address, prefix) = match range.split_once('/') {
+        Some((address, prefix)) => (address, Some(prefix)),
+        None => (range, None),
+    };
+    let address: IpAddr = address.trim().parse().map_err(|_| invalid())?;
+    let max_prefix = if address.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        Some(prefix) => prefix.trim().parse().map_err(|_| invalid())?,
+        None => max_prefix,
+    };
+    if prefix > max_prefix {
+        return Err(invalid());
+    }
+    Ok((address, prefix))
+}
+
+pub fn ip_in_range(client: IpAddr, range: &str) -> bool {
+    let Ok((network, prefix)) = parse_range(range) else {
+        return false;
+    };
+    let (client, network, bits) = match (client, network) {
+        (IpAddr::V4(client), IpAddr::V4(network)) => (u32::from(client) as u128, u32::from(network) as u128, 32),
+        (IpAddr::V6(client), IpAddr::V6(network)) => (u128::from(client), u128::from(network), 128),
+        _ => return false,
+    };
+    let mask = if prefix == 0 { 0 } else { u128::MAX << (bits - prefix) };
+    (client ^ network) & mask == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn forwarded_for(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_client_ip_ignores_header_from_untrusted_peer() {
+        let peer = "203.0.113.7".parse().unwrap();
+        let headers = forwarded_for("198.51.100.1");
+
+        assert_eq!(client_ip(Some(peer), &headers, &[]), Some(peer));
+        assert_eq!(client_ip(None, &headers, &[]), None);
+    }
+
+    #[test]
+    fn test_client_ip_from_trusted_proxy() {
+        let proxy: IpAddr = "10.0.0.2".parse().unwrap();
+        let trusted = ["10.0.0.0/24".to_string()];
+
+        // The spoofed first entry is skipped in favour of what the proxy saw
+        let headers = forwarded_for("192.0.2.66, 198.51.100.1, 10.0.0.3");
+        assert_eq!(client_ip(Some(proxy), &headers, &trusted), Some("198.51.100.1".parse().unwrap()));
+
+        assert_eq!(client_ip(Some(proxy), &HeaderMap::new(), &trusted), Some(proxy));
+    }
+
+    #[test]
+    fn test_ip_ranges() {
+        assert!(ip_in_range("192.0.2.7".parse().unwrap(), "192.0.2.7"));
+        assert!(!ip_in_range("192.0.2.8".parse().unwrap(), "192.0.2.7"));
+        assert!(ip_in_range("192.0.2.200".parse().unwrap(), "192.0.2.0/24"));
+        assert!(ip_in_range("2001:db8::1".parse().unwrap(), "2001:db8::/32"));
+        assert!(!ip_in_range("192.0.2.1".parse().unwrap(), "2001:db8::/32"));
+        assert!(ip_in_range("203.0.113.1".parse().unwrap(), "0.0.0.0/0"));
+    }
+}
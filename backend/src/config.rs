@@ -1,5 +1,7 @@
-use crate::error::{AppError, AppResult};
-use clap::Parser;
+use crate::{
+    client_ip::parse_range,
+    error::{AppError, AppResult},
+};
 use serde::{Deserialize, Serialize};
 use std::{env, path::PathBuf};
 
@@ -17,20 +19,195 @@ pub struct ServerConfig {
     pub port: u16,
     pub host: String,
     pub log_level: String,
+    /// Addresses or CIDR ranges of the reverse proxies whose
+    /// `X-Forwarded-For` header is believed.
+    pub trusted_proxies: Vec<String>,
+    /// Origins other than Kagikanri's own that browsers may call the API
+    /// from, such as a separately hosted frontend.
+    pub allowed_origins: Vec<String>,
+    /// HTTPS served by Kagikanri itself; plain HTTP behind a proxy when
+    /// `None`.
+    pub tls: Option<TlsConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TlsConfig {
+    /// PEM certificate chain, server certificate first.
+    pub cert_path: PathBuf,
+    /// PEM private key of the server certificate.
+    pub key_path: PathBuf,
+    /// PEM certificates of the CAs that issue client certificates; client
+    /// certificates are not asked for when `None`.
+    pub client_ca_path: Option<PathBuf>,
+    /// Refuse connections without a valid client certificate, instead of
+    /// letting browsers log in as usual.
+    pub require_client_cert: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GitConfig {
+    /// Primary remote; empty for a local-only store that keeps history
+    /// without syncing anywhere.
     pub repo_url: String,
     pub access_token: String,
+    /// Name of the primary remote in the repository.
+    pub remote_name: String,
+    /// Branch to sync; the branch HEAD is on when `None`.
+    pub branch: Option<String>,
     pub sync_interval_minutes: u64,
+    /// Name recorded as author and committer of store changes.
+    pub author_name: String,
+    /// Email recorded as author and committer of store changes.
+    pub author_email: String,
+    /// GPG key used to sign Kagikanri's commits; unsigned when `None`.
+    pub signing_key: Option<String>,
+    /// Fingerprints allowed to sign pulled commits. Pulled history is not
+    /// verified while this is empty.
+    pub trusted_signing_keys: Vec<String>,
+    /// Additional push-only remotes kept as redundant backups.
+    pub mirrors: Vec<RemoteConfig>,
+    /// Shared secret for push webhooks from the git host; the webhook
+    /// endpoint is disabled when `None`.
+    pub webhook_secret: Option<String>,
+    /// Hours between scheduled store integrity checks; 0 disables them.
+    pub store_check_interval_hours: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RemoteRole {
+    /// Pulled from and pushed to.
+    Primary,
+    /// Only pushed to.
+    Mirror,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteConfig {
+    pub name: String,
+    pub url: String,
+    pub access_token: String,
+    pub role: RemoteRole,
+}
+
+impl GitConfig {
+    /// True when there is no primary remote to pull from.
+    pub fn is_local_only(&self) -> bool {
+        self.repo_url.is_empty()
+    }
+
+    /// All remotes to sync with: the primary remote, unless the store is
+    /// local-only, followed by the mirrors.
+    pub fn remotes(&self) -> Vec<RemoteConfig> {
+        let primary = (!self.is_local_only()).then(|| RemoteConfig {
+            name: self.remote_name.clone(),
+            url: self.repo_url.clone(),
+            access_token: self.access_token.clone(),
+            role: RemoteRole::Primary,
+        });
+        primary.into_iter().chain(self.mirrors.iter().cloned()).collect()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuthConfig {
     pub master_password_path: String,
     pub totp_path: String,
+    /// Absolute session lifetime, however active the session is.
     pub session_timeout_hours: u64,
+    /// Sessions unused for this long expire early.
+    pub session_idle_timeout_minutes: u64,
+    /// How long after a login or re-authentication sensitive operations
+    /// are allowed without authenticating again.
+    pub reauth_window_minutes: u64,
+    /// Failed logins from one client before it is locked out.
+    pub login_max_failures: u32,
+    /// Failed logins from all clients together before every login is
+    /// locked out.
+    pub login_global_max_failures: u32,
+    /// First lockout; each further failure while over the limit doubles it.
+    pub login_lockout_minutes: u64,
+    /// TOTP time steps before the current one whose codes are accepted.
+    pub totp_skew_past: u64,
+    /// TOTP time steps after the current one whose codes are accepted, for
+    /// authenticators with a fast clock.
+    pub totp_skew_future: u64,
+    /// WebAuthn relying party ID for logging in with a passkey; the domain
+    /// Kagikanri is served on.
+    pub webauthn_rp_id: String,
+    /// Origin the web UI is served from.
+    pub webauthn_origin: String,
+    pub passkey_login: PasskeyLogin,
+    /// Single sign-on through an OpenID Connect provider; off when `None`.
+    pub oidc: Option<OidcConfig>,
+    /// Logins asserted by an authenticating reverse proxy; off when `None`.
+    pub proxy_auth: Option<ProxyAuthConfig>,
+}
+
+/// What a passkey login replaces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PasskeyLogin {
+    /// The passkey stands in for the TOTP code; the master password is
+    /// still required.
+    SecondFactor,
+    /// The passkey alone logs in.
+    Passwordless,
+}
+
+/// Kagikanri as an OpenID Connect relying party, using the authorization
+/// code flow with PKCE.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OidcConfig {
+    /// Issuer URL; the provider is discovered from its
+    /// `.well-known/openid-configuration`.
+    pub issuer: String,
+    pub client_id: String,
+    /// Secret of confidential clients; public clients rely on PKCE alone.
+    pub client_secret: Option<String>,
+    /// Callback URL registered with the provider.
+    pub redirect_uri: String,
+    pub scopes: String,
+    pub login: OidcLogin,
+    /// ID token claim naming the account created on first login.
+    pub user_claim: String,
+    /// ID token claim listing the user's groups.
+    pub groups_claim: String,
+    /// Groups whose members are admins.
+    pub admin_groups: Vec<String>,
+    /// Groups whose members may log in as members; anyone the provider
+    /// authenticates when empty.
+    pub member_groups: Vec<String>,
+}
+
+/// What an OpenID Connect login replaces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OidcLogin {
+    /// The provider alone logs in, and accounts are created on first login.
+    Alternative,
+    /// The provider stands in for the master password; the TOTP code is
+    /// still required, so only existing accounts can log in.
+    FirstFactor,
+}
+
+/// Identity headers set by an authenticating reverse proxy such as
+/// Authelia or oauth2-proxy. The proxy replaces the login; the master
+/// password is still asked for before secrets are revealed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProxyAuthConfig {
+    /// Addresses or CIDR ranges of the proxies whose headers are believed.
+    /// The headers are removed from requests coming from anywhere else.
+    pub trusted_proxies: Vec<String>,
+    /// Header holding the Kagikanri user ID.
+    pub user_header: String,
+    /// Header holding the user's groups, separated by commas.
+    pub groups_header: String,
+    /// Groups whose members are admins.
+    pub admin_groups: Vec<String>,
+    /// Groups whose members may log in as members; anyone the proxy
+    /// authenticates when empty.
+    pub member_groups: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -58,16 +235,47 @@ impl Config {
                     .map_err(|e| AppError::ConfigError(format!("Invalid PORT: {}", e)))?,
                 host: env::var("HOST").unwrap_or_else(|_| "0.0.0.0".to_string()),
                 log_level: env::var("LOG_LEVEL").unwrap_or_else(|_| "info".to_string()),
+                trusted_proxies: load_list("TRUSTED_PROXIES"),
+                allowed_origins: env::var("ALLOWED_ORIGINS")
+                    .map(|origins| {
+                        origins.split(',')
+                            .map(|origin| origin.trim().trim_end_matches('/'))
+                            .filter(|origin| !origin.is_empty())
+                            .map(String::from)
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                tls: load_tls()?,
             },
             git: GitConfig {
-                repo_url: env::var("GIT_REPO_URL")
-                    .map_err(|_| AppError::ConfigError("GIT_REPO_URL is required".to_string()))?,
-                access_token: env::var("GIT_ACCESS_TOKEN")
-                    .map_err(|_| AppError::ConfigError("GIT_ACCESS_TOKEN is required".to_string()))?,
+                repo_url: env::var("GIT_REPO_URL").unwrap_or_default(),
+                access_token: env::var("GIT_ACCESS_TOKEN").unwrap_or_default(),
+                remote_name: env::var("GIT_REMOTE_NAME")
+                    .unwrap_or_else(|_| "origin".to_string()),
+                branch: env::var("GIT_BRANCH").ok().filter(|branch| !branch.is_empty()),
                 sync_interval_minutes: env::var("SYNC_INTERVAL_MINUTES")
                     .unwrap_or_else(|_| "5".to_string())
                     .parse()
                     .map_err(|e| AppError::ConfigError(format!("Invalid SYNC_INTERVAL_MINUTES: {}", e)))?,
+                author_name: env::var("GIT_AUTHOR_NAME")
+                    .unwrap_or_else(|_| "Kagikanri".to_string()),
+                author_email: env::var("GIT_AUTHOR_EMAIL")
+                    .unwrap_or_else(|_| "kagikanri@localhost".to_string()),
+                signing_key: env::var("GIT_SIGNING_KEY").ok(),
+                trusted_signing_keys: env::var("GIT_TRUSTED_KEYS")
+                    .map(|keys| {
+                        keys.split(',')
+                            .map(|key| key.trim().to_string())
+                            .filter(|key| !key.is_empty())
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                mirrors: load_mirrors()?,
+                webhook_secret: env::var("GIT_WEBHOOK_SECRET").ok().filter(|secret| !secret.is_empty()),
+                store_check_interval_hours: env::var("STORE_CHECK_INTERVAL_HOURS")
+                    .unwrap_or_else(|_| "24".to_string())
+                    .parse()
+                    .map_err(|e| AppError::ConfigError(format!("Invalid STORE_CHECK_INTERVAL_HOURS: {}", e)))?,
             },
             auth: AuthConfig {
                 master_password_path: env::var("MASTER_PASSWORD_PATH")
@@ -78,6 +286,50 @@ impl Config {
                     .unwrap_or_else(|_| "24".to_string())
                     .parse()
                     .map_err(|e| AppError::ConfigError(format!("Invalid SESSION_TIMEOUT_HOURS: {}", e)))?,
+                session_idle_timeout_minutes: env::var("SESSION_IDLE_TIMEOUT_MINUTES")
+                    .unwrap_or_else(|_| "120".to_string())
+                    .parse()
+                    .map_err(|e| AppError::ConfigError(format!("Invalid SESSION_IDLE_TIMEOUT_MINUTES: {}", e)))?,
+                reauth_window_minutes: env::var("REAUTH_WINDOW_MINUTES")
+                    .unwrap_or_else(|_| "5".to_string())
+                    .parse()
+                    .map_err(|e| AppError::ConfigError(format!("Invalid REAUTH_WINDOW_MINUTES: {}", e)))?,
+                login_max_failures: env::var("LOGIN_MAX_FAILURES")
+                    .unwrap_or_else(|_| "5".to_string())
+                    .parse()
+                    .map_err(|e| AppError::ConfigError(format!("Invalid LOGIN_MAX_FAILURES: {}", e)))?,
+                login_global_max_failures: env::var("LOGIN_GLOBAL_MAX_FAILURES")
+                    .unwrap_or_else(|_| "50".to_string())
+                    .parse()
+                    .map_err(|e| AppError::ConfigError(format!("Invalid LOGIN_GLOBAL_MAX_FAILURES: {}", e)))?,
+                login_lockout_minutes: env::var("LOGIN_LOCKOUT_MINUTES")
+                    .unwrap_or_else(|_| "15".to_string())
+                    .parse()
+                    .map_err(|e| AppError::ConfigError(format!("Invalid LOGIN_LOCKOUT_MINUTES: {}", e)))?,
+                totp_skew_past: env::var("TOTP_SKEW_PAST")
+                    .unwrap_or_else(|_| "1".to_string())
+                    .parse()
+                    .map_err(|e| AppError::ConfigError(format!("Invalid TOTP_SKEW_PAST: {}", e)))?,
+                totp_skew_future: env::var("TOTP_SKEW_FUTURE")
+                    .unwrap_or_else(|_| "1".to_string())
+                    .parse()
+                    .map_err(|e| AppError::ConfigError(format!("Invalid TOTP_SKEW_FUTURE: {}", e)))?,
+                webauthn_rp_id: env::var("WEBAUTHN_RP_ID")
+                    .unwrap_or_else(|_| "kagikanri.local".to_string()),
+                webauthn_origin: env::var("WEBAUTHN_ORIGIN")
+                    .unwrap_or_else(|_| "https://kagikanri.local".to_string()),
+                passkey_login: match env::var("PASSKEY_LOGIN").as_deref() {
+                    Err(_) | Ok("second-factor") => PasskeyLogin::SecondFactor,
+                    Ok("passwordless") => PasskeyLogin::Passwordless,
+                    Ok(other) => {
+                        return Err(AppError::ConfigError(format!(
+                            "Invalid PASSKEY_LOGIN '{}', expected second-factor or passwordless",
+                            other
+                        )))
+                    }
+                },
+                oidc: load_oidc()?,
+                proxy_auth: load_proxy_auth()?,
             },
             database: DatabaseConfig {
                 url: env::var("DATABASE_URL")
@@ -94,7 +346,7 @@ impl Config {
         };
 
         // If a config file path is provided, try to load and merge it
-        if let Some(path) = config_path {
+        if config_path.is_some() {
             // TODO: Implement config file loading
             tracing::warn!("Config file loading not yet implemented, using environment variables only");
         }
@@ -106,10 +358,102 @@ impl Config {
     }
 
     fn validate(&self) -> AppResult<()> {
-        // Validate Git repo URL
-        if !self.git.repo_url.starts_with("http") && !self.git.repo_url.starts_with("git@") {
+        // Validate Git repo URL; without one the store is local-only
+        if !self.git.is_local_only() {
+            if !self.git.repo_url.starts_with("http") && !self.git.repo_url.starts_with("git@") {
+                return Err(AppError::ConfigError(
+                    "GIT_REPO_URL must be a valid HTTP or SSH URL".to_string(),
+                ));
+            }
+            if self.git.access_token.is_empty() {
+                return Err(AppError::ConfigError(
+                    "GIT_ACCESS_TOKEN is required when GIT_REPO_URL is set".to_string(),
+                ));
+            }
+        }
+
+        for mirror in &self.git.mirrors {
+            if !mirror.url.starts_with("http") && !mirror.url.starts_with("git@") {
+                return Err(AppError::ConfigError(format!(
+                    "Mirror {} must have a valid HTTP or SSH URL",
+                    mirror.name
+                )));
+            }
+            if mirror.name == self.git.remote_name {
+                return Err(AppError::ConfigError(format!(
+                    "Mirror name '{}' is reserved for the primary remote",
+                    mirror.name
+                )));
+            }
+        }
+
+        for range in &self.server.trusted_proxies {
+            parse_range(range).map_err(|_| {
+                AppError::ConfigError(format!("Invalid TRUSTED_PROXIES entry '{}'", range))
+            })?;
+        }
+
+        for origin in &self.server.allowed_origins {
+            let valid = url::Url::parse(origin)
+                .is_ok_and(|url| url.origin().is_tuple() && url.origin().ascii_serialization() == *origin);
+            if !valid {
+                return Err(AppError::ConfigError(format!(
+                    "ALLOWED_ORIGINS entry '{}' must be an origin like https://example.com",
+                    origin
+                )));
+            }
+        }
+
+        if let Some(oidc) = &self.auth.oidc {
+            if url::Url::parse(&oidc.issuer).is_err() || url::Url::parse(&oidc.redirect_uri).is_err() {
+                return Err(AppError::ConfigError(
+                    "OIDC_ISSUER and OIDC_REDIRECT_URI must be URLs".to_string(),
+                ));
+            }
+            if !oidc.scopes.split_whitespace().any(|scope| scope == "openid") {
+                return Err(AppError::ConfigError("OIDC_SCOPES must include openid".to_string()));
+            }
+        }
+
+        if let Some(proxy_auth) = &self.auth.proxy_auth {
+            // Believing the headers from any client would let anyone log in
+            // as anyone
+            if proxy_auth.trusted_proxies.is_empty() {
+                return Err(AppError::ConfigError(
+                    "PROXY_AUTH requires PROXY_AUTH_TRUSTED_PROXIES".to_string(),
+                ));
+            }
+            for range in &proxy_auth.trusted_proxies {
+                parse_range(range).map_err(|_| {
+                    AppError::ConfigError(format!("Invalid PROXY_AUTH_TRUSTED_PROXIES entry '{}'", range))
+                })?;
+            }
+            for name in [&proxy_auth.user_header, &proxy_auth.groups_header] {
+                if axum::http::HeaderName::from_bytes(name.as_bytes()).is_err() {
+                    return Err(AppError::ConfigError(format!("Invalid proxy header name '{}'", name)));
+                }
+            }
+        }
+
+        if self.auth.totp_skew_past > 10 || self.auth.totp_skew_future > 10 {
             return Err(AppError::ConfigError(
-                "GIT_REPO_URL must be a valid HTTP or SSH URL".to_string(),
+                "TOTP_SKEW_PAST and TOTP_SKEW_FUTURE must be at most 10 steps".to_string(),
+            ));
+        }
+
+        if self.auth.session_timeout_hours == 0
+            || self.auth.session_idle_timeout_minutes == 0
+            || self.auth.reauth_window_minutes == 0
+        {
+            return Err(AppError::ConfigError(
+                "SESSION_TIMEOUT_HOURS, SESSION_IDLE_TIMEOUT_MINUTES and REAUTH_WINDOW_MINUTES must be at least 1"
+                    .to_string(),
+            ));
+        }
+
+        if self.auth.login_max_failures == 0 || self.auth.login_global_max_failures == 0 {
+            return Err(AppError::ConfigError(
+                "LOGIN_MAX_FAILURES and LOGIN_GLOBAL_MAX_FAILURES must be at least 1".to_string(),
             ));
         }
 
@@ -131,24 +475,131 @@ impl Config {
     }
 }
 
+/// Reads mirrors from `GIT_MIRRORS` (`name=url,name=url`), with each token in
+/// `GIT_MIRROR_<NAME>_TOKEN`.
+fn load_mirrors() -> AppResult<Vec<RemoteConfig>> {
+    let Ok(mirrors) = env::var("GIT_MIRRORS") else {
+        return Ok(Vec::new());
+    };
+
+    mirrors
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (name, url) = entry.split_once('=').ok_or_else(|| {
+                AppError::ConfigError(format!("Invalid GIT_MIRRORS entry '{}', expected name=url", entry))
+            })?;
+            let name = name.trim().to_string();
+            let token_var = format!("GIT_MIRROR_{}_TOKEN", name.to_uppercase().replace('-', "_"));
+
+            Ok(RemoteConfig {
+                access_token: env::var(token_var).unwrap_or_default(),
+                url: url.trim().to_string(),
+                role: RemoteRole::Mirror,
+                name,
+            })
+        })
+        .collect()
+}
+
+/// Reads the OpenID Connect settings, which are enabled by `OIDC_ISSUER`.
+fn load_oidc() -> AppResult<Option<OidcConfig>> {
+    let Ok(issuer) = env::var("OIDC_ISSUER") else {
+        return Ok(None);
+    };
+    Ok(Some(OidcConfig {
+        issuer: issuer.trim_end_matches('/').to_string(),
+        client_id: env::var("OIDC_CLIENT_ID")
+            .map_err(|_| AppError::ConfigError("OIDC_CLIENT_ID is required with OIDC_ISSUER".to_string()))?,
+        client_secret: env::var("OIDC_CLIENT_SECRET").ok(),
+        redirect_uri: env::var("OIDC_REDIRECT_URI")
+            .map_err(|_| AppError::ConfigError("OIDC_REDIRECT_URI is required with OIDC_ISSUER".to_string()))?,
+        scopes: env::var("OIDC_SCOPES").unwrap_or_else(|_| "openid email profile".to_string()),
+        login: match env::var("OIDC_LOGIN").as_deref() {
+            Err(_) | Ok("alternative") => OidcLogin::Alternative,
+            Ok("first-factor") => OidcLogin::FirstFactor,
+            Ok(other) => {
+                return Err(AppError::ConfigError(format!(
+                    "Invalid OIDC_LOGIN '{}', expected alternative or first-factor",
+                    other
+                )))
+            }
+        },
+        user_claim: env::var("OIDC_USER_CLAIM").unwrap_or_else(|_| "preferred_username".to_string()),
+        groups_claim: env::var("OIDC_GROUPS_CLAIM").unwrap_or_else(|_| "groups".to_string()),
+        admin_groups: load_list("OIDC_ADMIN_GROUPS"),
+        member_groups: load_list("OIDC_MEMBER_GROUPS"),
+    }))
+}
+
+/// Reads the TLS settings, which are enabled by `TLS_CERT_PATH`.
+fn load_tls() -> AppResult<Option<TlsConfig>> {
+    let Ok(cert_path) = env::var("TLS_CERT_PATH") else {
+        if env::var("TLS_CLIENT_CA_PATH").is_ok() {
+            return Err(AppError::ConfigError("TLS_CLIENT_CA_PATH requires TLS_CERT_PATH".to_string()));
+        }
+        return Ok(None);
+    };
+    let client_ca_path = env::var("TLS_CLIENT_CA_PATH").ok().map(PathBuf::from);
+    let require_client_cert = env::var("TLS_CLIENT_CERT_REQUIRED")
+        .map(|value| value.parse::<bool>())
+        .unwrap_or(Ok(false))
+        .map_err(|e| AppError::ConfigError(format!("Invalid TLS_CLIENT_CERT_REQUIRED: {}", e)))?;
+    if require_client_cert && client_ca_path.is_none() {
+        return Err(AppError::ConfigError(
+            "TLS_CLIENT_CERT_REQUIRED requires TLS_CLIENT_CA_PATH".to_string(),
+        ));
+    }
+
+    Ok(Some(TlsConfig {
+        cert_path: cert_path.into(),
+        key_path: env::var("TLS_KEY_PATH")
+            .map_err(|_| AppError::ConfigError("TLS_KEY_PATH is required with TLS_CERT_PATH".to_string()))?
+            .into(),
+        client_ca_path,
+        require_client_cert,
+    }))
+}
+
+/// Reads the reverse proxy authentication settings, which are enabled by
+/// `PROXY_AUTH=true`.
+fn load_proxy_auth() -> AppResult<Option<ProxyAuthConfig>> {
+    let enabled = env::var("PROXY_AUTH")
+        .map(|value| value.parse::<bool>())
+        .unwrap_or(Ok(false))
+        .map_err(|e| AppError::ConfigError(format!("Invalid PROXY_AUTH: {}", e)))?;
+    if !enabled {
+        return Ok(None);
+    }
+
+    Ok(Some(ProxyAuthConfig {
+        trusted_proxies: load_list("PROXY_AUTH_TRUSTED_PROXIES"),
+        user_header: env::var("PROXY_AUTH_USER_HEADER").unwrap_or_else(|_| "Remote-User".to_string()),
+        groups_header: env::var("PROXY_AUTH_GROUPS_HEADER").unwrap_or_else(|_| "Remote-Groups".to_string()),
+        admin_groups: load_list("PROXY_AUTH_ADMIN_GROUPS"),
+        member_groups: load_list("PROXY_AUTH_MEMBER_GROUPS"),
+    }))
+}
+
+/// Reads a comma-separated list, empty when the variable is unset.
+fn load_list(name: &str) -> Vec<String> {
+    env::var(name)
+        .map(|items| {
+            items.split(',')
+                .map(|item| item.trim().to_string())
+                .filter(|item| !item.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
 impl Default for Config {
     fn default() -> Self {
         Config {
-            server: ServerConfig {
-                port: 8080,
-                host: "0.0.0.0".to_string(),
-                log_level: "info".to_string(),
-            },
-            git: GitConfig {
-                repo_url: "".to_string(),
-                access_token: "".to_string(),
-                sync_interval_minutes: 5,
-            },
-            auth: AuthConfig {
-                master_password_path: "kagikanri/master-password".to_string(),
-                totp_path: "kagikanri/totp".to_string(),
-                session_timeout_hours: 24,
-            },
+            server: ServerConfig::default(),
+            git: GitConfig::default(),
+            auth: AuthConfig::default(),
             database: DatabaseConfig {
                 url: "sqlite:///data/passkeys.db".to_string(),
                 encryption_key: "".to_string(),
@@ -159,4 +610,58 @@ impl Default for Config {
             },
         }
     }
-}
\ No newline at end of file
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            port: 8080,
+            host: "0.0.0.0".to_string(),
+            log_level: "info".to_string(),
+            trusted_proxies: Vec::new(),
+            allowed_origins: Vec::new(),
+            tls: None,
+        }
+    }
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            master_password_path: "kagikanri/master-password".to_string(),
+            totp_path: "kagikanri/totp".to_string(),
+            session_timeout_hours: 24,
+            session_idle_timeout_minutes: 120,
+            reauth_window_minutes: 5,
+            login_max_failures: 5,
+            login_global_max_failures: 50,
+            login_lockout_minutes: 15,
+            totp_skew_past: 1,
+            totp_skew_future: 1,
+            webauthn_rp_id: "kagikanri.local".to_string(),
+            webauthn_origin: "https://kagikanri.local".to_string(),
+            passkey_login: PasskeyLogin::SecondFactor,
+            oidc: None,
+            proxy_auth: None,
+        }
+    }
+}
+
+impl Default for GitConfig {
+    fn default() -> Self {
+        GitConfig {
+            repo_url: "".to_string(),
+            access_token: "".to_string(),
+            remote_name: "origin".to_string(),
+            branch: None,
+            sync_interval_minutes: 5,
+            author_name: "Kagikanri".to_string(),
+            author_email: "kagikanri@localhost".to_string(),
+            signing_key: None,
+            trusted_signing_keys: Vec::new(),
+            mirrors: Vec::new(),
+            webhook_secret: None,
+            store_check_interval_hours: 24,
+        }
+    }
+}
@@ -0,0 +1,294 @@
+use crate::error::{AppError, AppResult};
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use rand::Rng;
+use ring::digest;
+use sqlx::{Row, SqlitePool};
+
+/// Shortest master password accepted when it is changed.
+pub const MIN_MASTER_PASSWORD_LENGTH: usize = 12;
+/// Recovery codes in a generated set.
+pub const RECOVERY_CODE_COUNT: usize = 10;
+/// Characters in a recovery code, not counting separators.
+pub const RECOVERY_CODE_LENGTH: usize = 16;
+
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// Login credentials kept in the local database, so verifying a login never
+/// needs the master password in plaintext or a GPG decrypt.
+#[derive(Debug, Clone)]
+pub struct CredentialStore {
+    pool: SqlitePool,
+}
+
+impl CredentialStore {
+    pub async fn new(pool: SqlitePool) -> AppResult<Self> {
+        let store = Self { pool };
+        store.init_schema().await?;
+        Ok(store)
+    }
+
+    async fn init_schema(&self) -> AppResult<()> {
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS master_passwords (
+                user_id TEXT PRIMARY KEY,
+                verifier TEXT NOT NULL,
+                updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+            );
+
+            CREATE TABLE IF NOT EXISTS used_totp_steps (
+                user_id TEXT NOT NULL,
+                step INTEGER NOT NULL,
+                PRIMARY KEY (user_id, step)
+            );
+
+            CREATE TABLE IF NOT EXISTS recovery_codes (
+                code_hash TEXT PRIMARY KEY,
+                user_id TEXT NOT NULL,
+                created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+            );
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Argon2id hash of `user_id`'s master password in PHC string format,
+    /// if one has been stored.
+    pub async fn master_password_verifier(&self, user_id: &str) -> AppResult<Option<String>> {
+        let row = sqlx::query("SELECT verifier FROM master_passwords WHERE user_id = ?1")
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.map(|row| row.get("verifier")))
+    }
+
+    pub async fn set_master_password_verifier(&self, user_id: &str, verifier: &str) -> AppResult<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO master_passwords (user_id, verifier, updated_at) VALUES (?1, ?2, CURRENT_TIMESTAMP)
+            ON CONFLICT (user_id) DO UPDATE SET verifier = excluded.verifier, updated_at = excluded.updated_at
+            "#,
+        )
+        .bind(user_id)
+        .bind(verifier)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Marks a TOTP time step of `user_id` as used. Returns false if it
+    /// already was, so each code logs in at most once. Steps before
+    /// `oldest_valid` can no longer match a code and are forgotten.
+    pub async fn consume_totp_step(&self, user_id: &str, step: u64, oldest_valid: u64) -> AppResult<bool> {
+        sqlx::query("DELETE FROM used_totp_steps WHERE user_id = ?1 AND step < ?2")
+            .bind(user_id)
+            .bind(oldest_valid as i64)
+            .execute(&self.pool)
+            .await?;
+
+        let result = sqlx::query("INSERT OR IGNORE INTO used_totp_steps (user_id, step) VALUES (?1, ?2)")
+            .bind(user_id)
+            .bind(step as i64)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Replaces `user_id`'s recovery codes with a new set and returns the
+    /// codes. Only their hashes are stored, so this is the one time they
+    /// are shown.
+    pub async fn regenerate_recovery_codes(&self, user_id: &str) -> AppResult<Vec<String>> {
+        let codes = generate_recovery_codes();
+
+        let mut transaction = self.pool.begin().await?;
+        sqlx::query("DELETE FROM recovery_codes WHERE user_id = ?1")
+            .bind(user_id)
+            .execute(&mut *transaction)
+            .await?;
+        for code in &codes {
+            sqlx::query("INSERT INTO recovery_codes (code_hash, user_id) VALUES (?1, ?2)")
+                .bind(hash_recovery_code(code))
+                .bind(user_id)
+                .execute(&mut *transaction)
+                .await?;
+        }
+        transaction.commit().await?;
+
+        Ok(codes)
+    }
+
+    /// Uses up `code`. Returns false if it is not an unused recovery code
+    /// of `user_id`.
+    pub async fn consume_recovery_code(&self, user_id: &str, code: &str) -> AppResult<bool> {
+        let result = sqlx::query("DELETE FROM recovery_codes WHERE code_hash = ?1 AND user_id = ?2")
+            .bind(hash_recovery_code(code))
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    pub async fn recovery_codes_remaining(&self, user_id: &str) -> AppResult<usize> {
+        let row = sqlx::query("SELECT COUNT(*) AS remaining FROM recovery_codes WHERE user_id = ?1")
+            .bind(user_id)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(row.get::<i64, _>("remaining") as usize)
+    }
+
+    /// Removes every credential of a deleted user.
+    pub async fn delete_user(&self, user_id: &str) -> AppResult<()> {
+        let mut transaction = self.pool.begin().await?;
+        for table in ["master_passwords", "used_totp_steps", "recovery_codes"] {
+            sqlx::query(&format!("DELETE FROM {} WHERE user_id = ?1", table))
+                .bind(user_id)
+                .execute(&mut *transaction)
+                .await?;
+        }
+        transaction.commit().await?;
+
+        Ok(())
+    }
+}
+
+/// Whether `code` has the shape of a recovery code rather than a TOTP code.
+pub fn is_recovery_code(code: &str) -> bool {
+    normalize_recovery_code(code).len() == RECOVERY_CODE_LENGTH
+}
+
+fn generate_recovery_codes() -> Vec<String> {
+    let mut rng = rand::rngs::OsRng;
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let characters: Vec<char> = (0..RECOVERY_CODE_LENGTH)
+                .map(|_| RECOVERY_CODE_ALPHABET[rng.gen_range(0..RECOVERY_CODE_ALPHABET.len())] as char)
+                .collect();
+            characters
+                .chunks(4)
+                .map(|group| group.iter().collect::<String>())
+                .collect::<Vec<_>>()
+                .join("-")
+        })
+        .collect()
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// Codes are random with 80 bits of entropy, so unlike passwords they need
+/// no slow hash, and a code can be looked up by its hash directly.
+fn hash_recovery_code(code: &str) -> String {
+    let code = normalize_recovery_code(code);
+    hex::encode(digest::digest(&digest::SHA256, code.as_bytes()))
+}
+
+/// Hashes `password` with Argon2id and a random salt. Hashing is
+/// deliberately slow, so it runs on the blocking pool.
+pub async fn hash_password(password: String) -> AppResult<String> {
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|e| AppError::InternalError(format!("Failed to hash password: {}", e)))
+    })
+    .await
+    .map_err(|e| AppError::InternalError(format!("Password hashing task failed: {}", e)))?
+}
+
+/// Checks `password` against a verifier from `hash_password`. The hash
+/// comparison is constant-time.
+pub async fn verify_password(password: String, verifier: String) -> AppResult<bool> {
+    tokio::task::spawn_blocking(move || {
+        let hash = PasswordHash::new(&verifier)
+            .map_err(|e| AppError::InternalError(format!("Stored password verifier is invalid: {}", e)))?;
+        Ok(Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+    })
+    .await
+    .map_err(|e| AppError::InternalError(format!("Password verification task failed: {}", e)))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn create_test_store() -> CredentialStore {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        CredentialStore::new(pool).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_hash_and_verify_password() {
+        let verifier = hash_password("correct horse battery".to_string()).await.unwrap();
+
+        assert!(verifier.starts_with("$argon2id$"));
+        assert!(verify_password("correct horse battery".to_string(), verifier.clone()).await.unwrap());
+        assert!(!verify_password("correct horse battery ".to_string(), verifier).await.unwrap());
+        assert!(verify_password("anything".to_string(), "not a hash".to_string()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_master_password_verifier_is_replaced() {
+        let store = create_test_store().await;
+        assert_eq!(store.master_password_verifier("alice").await.unwrap(), None);
+
+        store.set_master_password_verifier("alice", "first").await.unwrap();
+        store.set_master_password_verifier("alice", "second").await.unwrap();
+        assert_eq!(store.master_password_verifier("alice").await.unwrap().as_deref(), Some("second"));
+        assert_eq!(store.master_password_verifier("bob").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_totp_step_is_consumed_once() {
+        let store = create_test_store().await;
+
+        assert!(store.consume_totp_step("alice", 100, 99).await.unwrap());
+        assert!(!store.consume_totp_step("alice", 100, 99).await.unwrap());
+        assert!(store.consume_totp_step("alice", 101, 100).await.unwrap());
+        // Steps are tracked per user
+        assert!(store.consume_totp_step("bob", 101, 100).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_recovery_codes_are_single_use() {
+        let store = create_test_store().await;
+        assert_eq!(store.recovery_codes_remaining("alice").await.unwrap(), 0);
+
+        let codes = store.regenerate_recovery_codes("alice").await.unwrap();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        assert!(codes.iter().all(|code| is_recovery_code(code)));
+        assert!(!is_recovery_code("123456"));
+
+        // Another user's codes are not accepted
+        assert!(!store.consume_recovery_code("bob", &codes[0]).await.unwrap());
+
+        // Codes are accepted without separators and in any case
+        let typed = codes[0].replace('-', "").to_uppercase();
+        assert!(store.consume_recovery_code("alice", &typed).await.unwrap());
+        assert!(!store.consume_recovery_code("alice", &codes[0]).await.unwrap());
+        assert_eq!(store.recovery_codes_remaining("alice").await.unwrap(), RECOVERY_CODE_COUNT - 1);
+
+        // Regenerating invalidates the old set
+        store.regenerate_recovery_codes("alice").await.unwrap();
+        assert!(!store.consume_recovery_code("alice", &codes[1]).await.unwrap());
+        assert_eq!(store.recovery_codes_remaining("alice").await.unwrap(), RECOVERY_CODE_COUNT);
+
+        store.delete_user("alice").await.unwrap();
+        assert_eq!(store.recovery_codes_remaining("alice").await.unwrap(), 0);
+    }
+}
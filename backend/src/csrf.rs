@@ -0,0 +1,156 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderValue, Method},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use crate::{config::Config, error::AppError, state::AppState};
+
+/// CORS for the configured origins only. Kagikanri's own frontend is
+/// same-origin and needs none.
+pub fn cors_layer(config: &Config) -> CorsLayer {
+    let origins: Vec<HeaderValue> = config
+        .server
+        .allowed_origins
+        .iter()
+        .filter_map(|origin| origin.parse().ok())
+        .collect();
+
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+        .allow_headers([header::CONTENT_TYPE, header::AUTHORIZATION])
+        .allow_credentials(true)
+}
+
+/// Rejects state-changing requests authenticated by the session cookie
+/// unless the browser says they come from a trusted origin. Requests with
+/// an `Authorization` header cannot be forged cross-site, so they are
+/// exempt.
+pub async fn csrf_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let headers = request.headers();
+    let safe = matches!(*request.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    if safe || !has_session_cookie(headers) || headers.contains_key(header::AUTHORIZATION) {
+        return next.run(request).await;
+    }
+
+    if !from_trusted_origin(headers, &state.config) {
+        tracing::warn!("Rejected cross-site {} {}", request.method(), request.uri().path());
+        return AppError::AuthorizationFailed("Cross-site request rejected".to_string()).into_response();
+    }
+
+    next.run(request).await
+}
+
+fn has_session_cookie(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .any(|cookie| cookie.trim().starts_with("session="))
+}
+
+/// Checks `Sec-Fetch-Site` where browsers send it, and `Origin` otherwise.
+/// The origin is trusted if it is this host, the WebAuthn origin or one of
+/// the allowed origins. Requests with neither header are rejected.
+fn from_trusted_origin(headers: &HeaderMap, config: &Config) -> bool {
+    let fetch_site = headers.get("sec-fetch-site").and_then(|value| value.to_str().ok());
+    if matches!(fetch_site, Some("same-origin" | "none")) {
+        return true;
+    }
+
+    let Some(origin) = headers.get(header::ORIGIN).and_then(|value| value.to_str().ok()) else {
+        return false;
+    };
+    if config.auth.webauthn_origin.trim_end_matches('/') == origin
+        || config.server.allowed_origins.iter().any(|allowed| allowed == origin)
+    {
+        return true;
+    }
+
+    // A missing Sec-Fetch-Site means an older browser; compare the origin
+    // with the host it sent the request to
+    let host = headers.get(header::HOST).and_then(|value| value.to_str().ok());
+    fetch_site.is_none()
+        && host.is_some_and(|host| {
+            url::Url::parse(origin).is_ok_and(|url| {
+                let authority = match url.port() {
+                    Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+                    None => url.host_str().unwrap_or_default().to_string(),
+                };
+                authority.eq_ignore_ascii_case(host)
+            })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    fn config() -> Config {
+        let mut config = Config::default();
+        config.server.allowed_origins = vec!["https://app.example.com".to_string()];
+        config
+    }
+
+    #[test]
+    fn test_fetch_metadata_decides_when_present() {
+        let config = config();
+
+        assert!(from_trusted_origin(&headers(&[("sec-fetch-site", "same-origin")]), &config));
+        assert!(!from_trusted_origin(
+            &headers(&[("sec-fetch-site", "cross-site"), ("origin", "https://evil.example")]),
+            &config
+        ));
+        // Allowed origins are cross-site for the browser
+        assert!(from_trusted_origin(
+            &headers(&[("sec-fetch-site", "cross-site"), ("origin", "https://app.example.com")]),
+            &config
+        ));
+        // Browsers sending Sec-Fetch-Site are not trusted on the host alone
+        assert!(!from_trusted_origin(
+            &headers(&[
+                ("sec-fetch-site", "same-site"),
+                ("origin", "https://vault.example.com"),
+                ("host", "vault.example.com"),
+            ]),
+            &config
+        ));
+    }
+
+    #[test]
+    fn test_origin_is_checked_without_fetch_metadata() {
+        let config = config();
+
+        assert!(from_trusted_origin(
+            &headers(&[("origin", "https://vault.example.com:8443"), ("host", "vault.example.com:8443")]),
+            &config
+        ));
+        assert!(from_trusted_origin(&headers(&[("origin", "https://kagikanri.local")]), &config));
+        assert!(!from_trusted_origin(
+            &headers(&[("origin", "https://evil.example"), ("host", "vault.example.com")]),
+            &config
+        ));
+        assert!(!from_trusted_origin(&HeaderMap::new(), &config));
+    }
+
+    #[test]
+    fn test_only_cookie_sessions_are_checked() {
+        assert!(has_session_cookie(&headers(&[("cookie", "theme=dark; session=abc")])));
+        assert!(!has_session_cookie(&headers(&[("cookie", "theme=dark")])));
+    }
+}
@@ -14,6 +14,10 @@ pub enum AppError {
     #[error("Authorization failed: {0}")]
     AuthorizationFailed(String),
     
+    /// The session must re-authenticate before the operation.
+    #[error("Re-authentication required: {0}")]
+    ReauthRequired(String),
+    
     #[error("Password store error: {0}")]
     PassError(String),
     
@@ -32,6 +36,10 @@ pub enum AppError {
     #[error("WebAuthn error: {0}")]
     WebAuthnError(String),
     
+    /// The OpenID Connect provider could not be reached or misbehaved.
+    #[error("OpenID Connect provider error: {0}")]
+    OidcError(String),
+    
     #[error("Internal server error: {0}")]
     InternalError(String),
     
@@ -40,25 +48,38 @@ pub enum AppError {
     
     #[error("Conflict: {0}")]
     Conflict(String),
+    
+    #[error("Too many requests: {0}")]
+    RateLimited(String),
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        // Machine-readable reason for errors clients act on
+        let code = match self {
+            AppError::ReauthRequired(_) => Some("reauth_required"),
+            _ => None,
+        };
         let (status, error_message) = match self {
             AppError::AuthenticationFailed(_) => (StatusCode::UNAUTHORIZED, self.to_string()),
-            AppError::AuthorizationFailed(_) => (StatusCode::FORBIDDEN, self.to_string()),
+            AppError::AuthorizationFailed(_) | AppError::ReauthRequired(_) => (StatusCode::FORBIDDEN, self.to_string()),
             AppError::ValidationError(_) => (StatusCode::BAD_REQUEST, self.to_string()),
             AppError::NotFound(_) => (StatusCode::NOT_FOUND, self.to_string()),
             AppError::Conflict(_) => (StatusCode::CONFLICT, self.to_string()),
+            AppError::RateLimited(_) => (StatusCode::TOO_MANY_REQUESTS, self.to_string()),
+            AppError::OidcError(_) => (StatusCode::BAD_GATEWAY, self.to_string()),
             _ => (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error".to_string()),
         };
 
-        let body = Json(json!({
+        let mut body = json!({
             "error": error_message,
             "status": status.as_u16()
-        }));
+        });
+        if let Some(code) = code {
+            body["code"] = json!(code);
+        }
 
-        (status, body).into_response()
+        (status, Json(body)).into_response()
     }
 }
 
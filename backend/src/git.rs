@@ -1,79 +1,318 @@
 use crate::{
-    config::GitConfig,
+    config::{GitConfig, RemoteConfig, RemoteRole},
     error::{AppError, AppResult},
+    history::{self, EntryChange, HistoryPage, HistoryQuery, Restore},
+    integrity::{self, IntegrityReport},
+    purge::{self, PurgePlan, PurgePush, PurgeResult},
+    signing::{self, SignatureStatus},
 };
 use chrono::{DateTime, Utc};
 use git2::{
-    Cred, PushOptions, RemoteCallbacks, Repository, RepositoryInitOptions, Signature,
+    build::CheckoutBuilder, Commit, Cred, ErrorCode, ObjectType, Oid, PushOptions,
+    RemoteCallbacks, Repository, Signature, Sort, Tree,
 };
 use serde::{Deserialize, Serialize};
-use std::path::Path;
-use tracing::{error, info, warn};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tracing::{info, warn};
 
 #[derive(Debug, Clone)]
 pub struct GitSync {
     config: GitConfig,
     repo_path: std::path::PathBuf,
+    status: SyncStatus,
+    /// Serializes changes to HEAD, the index and the working tree between
+    /// clones of this GitSync. Network I/O happens outside of it, except
+    /// for the force-pushes of a purge: until they succeed the rewrite may
+    /// still be undone, which would drop anything committed on top of it.
+    repo_lock: Arc<Mutex<()>>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct SyncStatus {
     pub last_sync: Option<DateTime<Utc>>,
     pub last_commit: Option<String>,
     pub is_syncing: bool,
     pub error: Option<String>,
+    /// Local commits the primary remote has not received yet.
+    #[serde(default)]
+    pub pending_changes: usize,
+    #[serde(default)]
+    pub remotes: Vec<RemoteStatus>,
+}
+
+/// Outcome of the most recent sync with a single remote.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteStatus {
+    pub name: String,
+    pub role: RemoteRole,
+    pub last_success: Option<DateTime<Utc>>,
+    pub error: Option<String>,
+    /// Local commits this remote has not received yet.
+    #[serde(default)]
+    pub pending_changes: usize,
+}
+
+/// Signature state of a single commit, as shown in the signature report.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommitSignature {
+    pub commit: String,
+    pub author: String,
+    pub date: DateTime<Utc>,
+    pub summary: String,
+    pub status: SignatureStatus,
+    pub fingerprint: Option<String>,
+}
+
+/// Identity recorded as author and committer of a store change.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommitAuthor {
+    pub name: String,
+    pub email: String,
+    /// Kagikanri user the change was made by, recorded in a trailer.
+    pub user_id: Option<String>,
+}
+
+impl CommitAuthor {
+    fn signature(&self) -> AppResult<Signature<'static>> {
+        Ok(Signature::now(&self.name, &self.email)?)
+    }
+
+    /// `message` with the user trailer appended.
+    fn message(&self, message: &str) -> String {
+        match &self.user_id {
+            Some(user_id) => format!("{}\n\n{}: {}", message, history::USER_TRAILER, user_id),
+            None => message.to_string(),
+        }
+    }
+}
+
+/// A single write to the password store, committed on its own with the
+/// same message `pass` would use.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StoreChange {
+    /// Password inserted or overwritten at the given entry path.
+    Insert(String),
+    /// OTP secret inserted at the given entry path.
+    InsertOtp(String),
+    /// Entry removed from the given path.
+    Remove(String),
+}
+
+impl StoreChange {
+    fn entry(&self) -> &str {
+        match self {
+            StoreChange::Insert(path) | StoreChange::InsertOtp(path) | StoreChange::Remove(path) => {
+                path.trim_matches('/')
+            }
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            StoreChange::Insert(_) => format!("Add given password for {} to store.", self.entry()),
+            StoreChange::InsertOtp(_) => format!("Add OTP secret for {} to store.", self.entry()),
+            StoreChange::Remove(_) => format!("Remove {} from store.", self.entry()),
+        }
+    }
+
+    /// Store file touched by this change, relative to the repository root.
+    pub fn file(&self) -> String {
+        format!("{}.gpg", self.entry())
+    }
 }
 
 impl GitSync {
-    pub fn new(config: GitConfig) -> AppResult<Self> {
-        let repo_path = std::path::PathBuf::from("/data/password-store");
-        
+    /// Creates a GitSync for the repository at `repo_path`, which should be
+    /// the password store directory so pass and git see the same files.
+    pub fn new(config: GitConfig, repo_path: std::path::PathBuf) -> AppResult<Self> {
         Ok(Self {
             config,
             repo_path,
+            status: SyncStatus::default(),
+            repo_lock: Arc::new(Mutex::new(())),
         })
     }
 
-    pub async fn sync(&mut self) -> AppResult<SyncStatus> {
+    /// Pulls and pushes. This blocks on network I/O, so async callers go
+    /// through the sync actor instead of calling it directly.
+    pub fn sync(&mut self) -> AppResult<SyncStatus> {
+        let result = self.run_sync();
+
+        self.status.is_syncing = false;
+        match result {
+            Ok(last_commit) => {
+                self.status.last_sync = Some(Utc::now());
+                self.status.last_commit = last_commit;
+                self.status.error = None;
+                Ok(self.status.clone())
+            }
+            Err(e) => {
+                warn!("Git sync failed: {}", e);
+                self.status.error = Some(e.to_string());
+                Err(e)
+            }
+        }
+    }
+
+    /// Pulls from the primary remote and pushes to every remote. A failing
+    /// primary does not stop the mirrors from receiving local commits, but
+    /// it does fail the sync as a whole; mirror failures are only recorded
+    /// in their remote status.
+    fn run_sync(&mut self) -> AppResult<Option<String>> {
         info!("Starting Git sync");
         
-        // Ensure repository exists first
-        self.ensure_repository().await?;
-        
-        // Open repository for each operation to avoid holding across await
-        let repo = Repository::open(&self.repo_path)
-            .map_err(|e| AppError::GitError(format!("Failed to open repository: {}", e)))?;
-        
-        // Pull latest changes
-        self.pull(&repo)?;
-        
-        // Push any local changes
-        self.push(&repo)?;
-        
-        let last_commit = self.get_last_commit_hash(&repo)?;
-        
-        Ok(SyncStatus {
-            last_sync: Some(Utc::now()),
-            last_commit,
-            is_syncing: false,
-            error: None,
-        })
+        let remotes = self.config.remotes();
+        let repo = {
+            let _guard = self.lock_repo();
+
+            // Ensure repository exists first
+            self.ensure_repository()?;
+
+            let repo = self.open_repository()?;
+            self.ensure_remotes(&repo, &remotes)?;
+            self.ensure_branch(&repo)?;
+            repo
+        };
+
+        let mut primary_error = None;
+        for remote in &remotes {
+            let result = match remote.role {
+                // Pull latest changes, then push any local commits
+                RemoteRole::Primary => self.pull(&repo, remote).and_then(|_| self.push(&repo, remote)),
+                RemoteRole::Mirror => self.push(&repo, remote),
+            };
+
+            self.record_remote_result(remote, &result);
+            if let (RemoteRole::Primary, Err(e)) = (remote.role, result) {
+                primary_error = Some(e);
+            }
+        }
+
+        if let Some(e) = primary_error {
+            return Err(e);
+        }
+
+        self.get_last_commit_hash(&repo)
+    }
+
+    fn record_remote_result(&mut self, remote: &RemoteConfig, result: &AppResult<()>) {
+        let position = self.status.remotes.iter().position(|status| status.name == remote.name);
+        let status = match position {
+            Some(index) => &mut self.status.remotes[index],
+            None => {
+                self.status.remotes.push(RemoteStatus {
+                    name: remote.name.clone(),
+                    role: remote.role,
+                    last_success: None,
+                    error: None,
+                    pending_changes: 0,
+                });
+                self.status.remotes.last_mut().expect("remote status was just added")
+            }
+        };
+
+        status.role = remote.role;
+        match result {
+            Ok(()) => {
+                status.last_success = Some(Utc::now());
+                status.error = None;
+            }
+            Err(e) => {
+                warn!("Sync with remote {} failed: {}", remote.name, e);
+                status.error = Some(e.to_string());
+            }
+        }
+    }
+
+    /// Registers configured remotes in the repository and keeps their URLs
+    /// in step with the configuration.
+    fn ensure_remotes(&self, repo: &Repository, remotes: &[RemoteConfig]) -> AppResult<()> {
+        for remote in remotes {
+            match repo.find_remote(&remote.name) {
+                Ok(existing) if existing.url() == Some(remote.url.as_str()) => {}
+                Ok(_) => repo.remote_set_url(&remote.name, &remote.url)?,
+                Err(e) if e.code() == ErrorCode::NotFound => {
+                    repo.remote(&remote.name, &remote.url)?;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
     }
 
-    async fn ensure_repository(&self) -> AppResult<()> {
+    fn ensure_repository(&self) -> AppResult<()> {
         if self.repo_path.exists() && self.repo_path.join(".git").exists() {
             // Repository exists, just verify it can be opened
             Repository::open(&self.repo_path)
                 .map_err(|e| AppError::GitError(format!("Failed to open repository: {}", e)))?;
             Ok(())
+        } else if self.config.is_local_only() {
+            self.init_repository()
         } else {
             // Clone the repository
-            self.clone_repository().await?;
+            self.clone_repository()?;
             Ok(())
         }
     }
 
-    async fn clone_repository(&self) -> AppResult<()> {
+    /// Starts history for a local-only store. Entries already in the store
+    /// directory are recorded in an initial commit.
+    fn init_repository(&self) -> AppResult<()> {
+        info!("Initializing local repository at {}", self.repo_path.display());
+
+        std::fs::create_dir_all(&self.repo_path)?;
+        let repo = Repository::init(&self.repo_path)?;
+        let branch = self.config.branch.as_deref().unwrap_or("main");
+        repo.set_head(&format!("refs/heads/{}", branch))?;
+
+        let mut index = repo.index()?;
+        index.add_all(["*"], git2::IndexAddOption::DEFAULT, None)?;
+        index.write()?;
+        if index.is_empty() {
+            return Ok(());
+        }
+
+        let tree = repo.find_tree(index.write_tree()?)?;
+        self.create_commit(&repo, &self.default_author(), "Initialize password store history.", &tree, &[])?;
+        Ok(())
+    }
+
+    /// Moves HEAD to the configured branch, creating it from the primary
+    /// remote's branch of the same name, or from HEAD if the remote has none.
+    fn ensure_branch(&self, repo: &Repository) -> AppResult<()> {
+        let Some(branch) = &self.config.branch else {
+            return Ok(());
+        };
+        let refname = format!("refs/heads/{}", branch);
+
+        let head = match repo.head() {
+            Ok(head) if head.name() == Some(refname.as_str()) => return Ok(()),
+            Ok(head) => head.peel_to_commit()?,
+            // Nothing committed yet, so there is nothing to check out
+            Err(e) if e.code() == ErrorCode::UnbornBranch => {
+                repo.set_head(&refname)?;
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        if repo.find_reference(&refname).is_err() {
+            let remote_branch = format!("refs/remotes/{}/{}", self.config.remote_name, branch);
+            let start = match repo.find_reference(&remote_branch) {
+                Ok(reference) => reference.peel_to_commit()?,
+                Err(_) => head,
+            };
+            repo.branch(branch, &start, false)?;
+        }
+
+        info!("Switching to branch {}", branch);
+        let target = repo.find_reference(&refname)?.peel_to_commit()?;
+        repo.checkout_tree(target.as_object(), Some(CheckoutBuilder::new().safe()))?;
+        repo.set_head(&refname)?;
+        Ok(())
+    }
+
+    fn clone_repository(&self) -> AppResult<()> {
         info!("Cloning repository from {}", self.config.repo_url);
         
         // Ensure parent directory exists
@@ -81,19 +320,12 @@ impl GitSync {
             std::fs::create_dir_all(parent)?;
         }
 
-        let mut callbacks = RemoteCallbacks::new();
-        callbacks.credentials(|_url, username_from_url, _allowed_types| {
-            Cred::userpass_plaintext(
-                username_from_url.unwrap_or("git"),
-                &self.config.access_token,
-            )
-        });
-
         let mut fetch_options = git2::FetchOptions::new();
-        fetch_options.remote_callbacks(callbacks);
+        fetch_options.remote_callbacks(remote_callbacks(&self.config.access_token));
 
         let mut builder = git2::build::RepoBuilder::new();
         builder.fetch_options(fetch_options);
+        builder.remote_create(|repo, _name, url| repo.remote(&self.config.remote_name, url));
 
         builder
             .clone(&self.config.repo_url, &self.repo_path)
@@ -103,28 +335,23 @@ impl GitSync {
         Ok(())
     }
 
-    fn pull(&self, repo: &Repository) -> AppResult<()> {
-        info!("Pulling latest changes");
+    fn pull(&self, repo: &Repository, remote_config: &RemoteConfig) -> AppResult<()> {
+        info!("Pulling latest changes from {}", remote_config.name);
         
         let mut remote = repo
-            .find_remote("origin")
+            .find_remote(&remote_config.name)
             .map_err(|e| AppError::GitError(format!("Failed to find remote: {}", e)))?;
 
-        let mut callbacks = RemoteCallbacks::new();
-        callbacks.credentials(|_url, username_from_url, _allowed_types| {
-            Cred::userpass_plaintext(
-                username_from_url.unwrap_or("git"),
-                &self.config.access_token,
-            )
-        });
-
         let mut fetch_options = git2::FetchOptions::new();
-        fetch_options.remote_callbacks(callbacks);
+        fetch_options.remote_callbacks(remote_callbacks(&remote_config.access_token));
 
+        let refspec = format!("refs/heads/*:refs/remotes/{}/*", remote_config.name);
         remote
-            .fetch(&["refs/heads/*:refs/remotes/origin/*"], Some(&mut fetch_options), None)
+            .fetch(&[&refspec], Some(&mut fetch_options), None)
             .map_err(|e| AppError::GitError(format!("Failed to fetch: {}", e)))?;
 
+        let _guard = self.lock_repo();
+
         // Get the current branch
         let head = repo.head()?;
         let branch_name = head
@@ -132,95 +359,522 @@ impl GitSync {
             .ok_or_else(|| AppError::GitError("Failed to get branch name".to_string()))?;
 
         // Get remote branch reference
-        let remote_branch_name = format!("refs/remotes/origin/{}", branch_name);
+        let remote_branch_name = format!("refs/remotes/{}/{}", remote_config.name, branch_name);
         let remote_ref = repo
             .find_reference(&remote_branch_name)
             .map_err(|e| AppError::GitError(format!("Failed to find remote branch: {}", e)))?;
 
         let remote_commit = remote_ref.peel_to_commit()?;
-        
-        // Fast-forward merge if possible
-        let local_commit = head.peel_to_commit()?;
-        
-        if local_commit.id() != remote_commit.id() {
-            info!("Updating local branch to match remote");
-            
-            // Reset to remote commit (this is a force update)
-            repo.reset(
-                remote_commit.as_object(),
-                git2::ResetType::Hard,
-                None,
-            )?;
-            
+        self.verify_incoming(repo, head.target(), remote_commit.id())?;
+
+        let remote_annotated = repo.find_annotated_commit(remote_commit.id())?;
+        let (analysis, _) = repo.merge_analysis(&[&remote_annotated])?;
+
+        if analysis.is_up_to_date() {
+            info!("Local branch is up to date with remote");
+        } else if analysis.is_fast_forward() {
+            info!("Fast-forwarding local branch to remote");
+
+            let head_name = head
+                .name()
+                .ok_or_else(|| AppError::GitError("Failed to get HEAD reference".to_string()))?
+                .to_string();
+            // Update the working tree while HEAD still points at the old
+            // commit, so locally modified files are detected and kept
+            repo.checkout_tree(remote_commit.as_object(), Some(CheckoutBuilder::new().safe()))?;
+            repo.find_reference(&head_name)?
+                .set_target(remote_commit.id(), "Kagikanri: fast-forward")?;
+
             info!("Successfully updated to latest remote changes");
         } else {
-            info!("Local branch is up to date with remote");
+            // Local commits that have not been pushed yet must survive the
+            // pull, so diverged histories are merged rather than reset.
+            let local_commit = head.peel_to_commit()?;
+            let message = format!(
+                "Merge remote-tracking branch '{}/{}'",
+                remote_config.name, branch_name
+            );
+            self.merge(repo, &local_commit, &remote_commit, &message)?;
         }
 
         Ok(())
     }
 
-    fn push(&self, repo: &Repository) -> AppResult<()> {
-        // Check if there are any local changes to push
-        let statuses = repo.statuses(None)?;
-        
-        if !statuses.is_empty() {
-            info!("Found local changes, committing and pushing");
-            
-            // Stage all changes
-            let mut index = repo.index()?;
-            index.add_all(["*"].iter(), git2::IndexAddOption::DEFAULT, None)?;
-            index.write()?;
-            
-            // Create commit
-            let signature = Signature::now("Kagikanri", "kagikanri@localhost")?;
-            let tree_id = index.write_tree()?;
-            let tree = repo.find_tree(tree_id)?;
-            
-            let head = repo.head()?;
-            let parent_commit = head.peel_to_commit()?;
-            
-            let commit_id = repo.commit(
-                Some("HEAD"),
-                &signature,
-                &signature,
-                "Auto-commit from Kagikanri",
-                &tree,
-                &[&parent_commit],
-            )?;
-            
-            info!("Created commit: {}", commit_id);
-        }
-
-        // Push to remote
-        let mut remote = repo.find_remote("origin")?;
-        
-        let mut callbacks = RemoteCallbacks::new();
-        callbacks.credentials(|_url, username_from_url, _allowed_types| {
-            Cred::userpass_plaintext(
-                username_from_url.unwrap_or("git"),
-                &self.config.access_token,
-            )
-        });
+    fn merge(
+        &self,
+        repo: &Repository,
+        local_commit: &Commit,
+        remote_commit: &Commit,
+        message: &str,
+    ) -> AppResult<()> {
+        info!("Merging remote changes into local branch");
 
-        let mut push_options = PushOptions::new();
-        push_options.remote_callbacks(callbacks);
+        let mut index = repo.merge_commits(local_commit, remote_commit, None)?;
+        if index.has_conflicts() {
+            return Err(AppError::GitError(
+                "Local and remote changes conflict and need a manual merge".to_string(),
+            ));
+        }
+
+        let tree = repo.find_tree(index.write_tree_to(repo)?)?;
+        repo.checkout_tree(tree.as_object(), Some(CheckoutBuilder::new().safe()))?;
 
+        let commit_id = self.create_commit(
+            repo,
+            &self.default_author(),
+            message,
+            &tree,
+            &[local_commit, remote_commit],
+        )?;
+
+        info!("Created merge commit: {}", commit_id);
+        Ok(())
+    }
+
+    fn push(&self, repo: &Repository, remote_config: &RemoteConfig) -> AppResult<()> {
         let head = repo.head()?;
         let branch_name = head
             .shorthand()
             .ok_or_else(|| AppError::GitError("Failed to get branch name".to_string()))?;
 
         let refspec = format!("refs/heads/{}:refs/heads/{}", branch_name, branch_name);
-        
+        self.push_refspec(repo, remote_config, &refspec)
+    }
+
+    fn push_refspec(&self, repo: &Repository, remote_config: &RemoteConfig, refspec: &str) -> AppResult<()> {
+        let mut remote = repo.find_remote(&remote_config.name)?;
+
+        let mut push_options = PushOptions::new();
+        push_options.remote_callbacks(remote_callbacks(&remote_config.access_token));
+
         remote
-            .push(&[&refspec], Some(&mut push_options))
+            .push(&[refspec], Some(&mut push_options))
             .map_err(|e| AppError::GitError(format!("Failed to push: {}", e)))?;
 
-        info!("Successfully pushed changes to remote");
+        info!("Successfully pushed changes to {}", remote_config.name);
+        Ok(())
+    }
+
+    /// Identity used for commits that are not made on behalf of a user,
+    /// such as merges of remote changes.
+    pub fn default_author(&self) -> CommitAuthor {
+        CommitAuthor {
+            name: self.config.author_name.clone(),
+            email: self.config.author_email.clone(),
+            user_id: None,
+        }
+    }
+
+    /// Commits a single store change. Only the file touched by the change
+    /// is staged, so unrelated local modifications stay out of the commit.
+    /// Returns `None` when the change left the tree untouched.
+    pub fn commit_change(&self, change: &StoreChange, author: &CommitAuthor) -> AppResult<Option<String>> {
+        let _guard = self.lock_repo();
+        let repo = self.open_repository()?;
+
+        let file = change.file();
+        let workdir_file = self.repo_path.join(&file);
+        let blob = if workdir_file.is_file() {
+            Some(repo.blob_path(&workdir_file)?)
+        } else {
+            None
+        };
+
+        let parent = match repo.head() {
+            Ok(head) => Some(head.peel_to_commit()?),
+            Err(e) if e.code() == ErrorCode::UnbornBranch => None,
+            Err(e) => return Err(e.into()),
+        };
+        let base_tree = parent.as_ref().map(|commit| commit.tree()).transpose()?;
+
+        let components: Vec<&str> = file.split('/').collect();
+        let tree_id = match update_tree(&repo, base_tree.as_ref(), &components, blob)? {
+            Some(tree_id) => tree_id,
+            None => repo.treebuilder(None)?.write()?,
+        };
+
+        if base_tree.as_ref().map(|tree| tree.id()) == Some(tree_id) {
+            info!("No changes to commit for {}", file);
+            return Ok(None);
+        }
+
+        // Keep the index in step with the new HEAD for this path only
+        let mut index = repo.index()?;
+        let file_path = std::path::Path::new(&file);
+        if blob.is_some() {
+            index.add_path(file_path)?;
+        } else if index.get_path(file_path, 0).is_some() {
+            index.remove_path(file_path)?;
+        }
+        index.write()?;
+
+        let tree = repo.find_tree(tree_id)?;
+        let parents: Vec<&Commit> = parent.iter().collect();
+        let commit_id = self.create_commit(&repo, author, &change.message(), &tree, &parents)?;
+
+        info!("Created commit {}: {}", commit_id, change.message());
+        Ok(Some(commit_id.to_string()))
+    }
+
+    /// Entries that reverting `commit` would change.
+    pub fn preview_revert(&self, commit: &str) -> AppResult<Vec<EntryChange>> {
+        let repo = self.open_repository()?;
+        let head = repo.head()?.peel_to_commit()?;
+        let target = history::find_commit(&repo, commit, &head)?;
+
+        let tree = repo.find_tree(history::revert_tree(&repo, &target, &head)?)?;
+        let changes = history::diff_trees(&repo, Some(&head.tree()?), &tree)?;
+        Ok(changes)
+    }
+
+    /// Undoes a single commit with a new commit on top of HEAD.
+    pub fn revert(&self, commit: &str, author: &CommitAuthor) -> AppResult<Restore> {
+        let _guard = self.lock_repo();
+        let repo = self.open_repository()?;
+        let head = repo.head()?.peel_to_commit()?;
+        let target = history::find_commit(&repo, commit, &head)?;
+
+        let tree = repo.find_tree(history::revert_tree(&repo, &target, &head)?)?;
+        let message = format!(
+            "Revert \"{}\"\n\nThis reverts commit {}.",
+            target.summary().unwrap_or_default(),
+            target.id()
+        );
+        let restore = self.commit_restore(&repo, &head, &tree, &message, author)?;
+        Ok(restore)
+    }
+
+    /// Entries that rolling the store back to `commit` would change.
+    pub fn preview_rollback(&self, commit: &str) -> AppResult<Vec<EntryChange>> {
+        let repo = self.open_repository()?;
+        let head = repo.head()?.peel_to_commit()?;
+        let target = history::find_commit(&repo, commit, &head)?;
+
+        let changes = history::diff_trees(&repo, Some(&head.tree()?), &target.tree()?)?;
+        Ok(changes)
+    }
+
+    /// Restores the whole store to its state at `commit`. History is never
+    /// rewritten: the old tree is recorded as a new commit on top of HEAD,
+    /// so the rollback pushes like any other change and can itself be reverted.
+    pub fn rollback(&self, commit: &str, author: &CommitAuthor) -> AppResult<Restore> {
+        let _guard = self.lock_repo();
+        let repo = self.open_repository()?;
+        let head = repo.head()?.peel_to_commit()?;
+        let target = history::find_commit(&repo, commit, &head)?;
+
+        let message = format!("Roll back store to {}.", target.id());
+        let restore = self.commit_restore(&repo, &head, &target.tree()?, &message, author)?;
+        Ok(restore)
+    }
+
+    /// Dry run of `purge`.
+    pub fn plan_purge(&self, paths: &[String]) -> AppResult<PurgePlan> {
+        let repo = self.open_repository()?;
+        let plan = purge::plan(&repo, paths)?;
+        Ok(plan)
+    }
+
+    /// Rewrites history so no commit contains `paths`, then force-pushes the
+    /// branch to every remote. `confirmation_token` must come from a plan of
+    /// the current HEAD. The primary remote is pushed first; if that fails
+    /// the local branch is put back, since the next pull would otherwise
+    /// merge the purged history in again. Failed mirror pushes are reported
+    /// and can be retried by confirming the purge again.
+    pub fn purge(&self, paths: &[String], confirmation_token: &str) -> AppResult<PurgeResult> {
+        // Held through the pushes, so writes wait until the rewrite is final
+        let _guard = self.lock_repo();
+        let repo = self.open_repository()?;
+
+        let plan = purge::plan(&repo, paths)?;
+        if plan.confirmation_token != confirmation_token {
+            return Err(AppError::Conflict(
+                "The store changed since the purge was previewed; preview it again".to_string(),
+            ));
+        }
+        if !plan.blocking_refs.is_empty() {
+            return Err(AppError::Conflict(format!(
+                "{} still reach the purged files; delete them before purging",
+                plan.blocking_refs.join(", ")
+            )));
+        }
+
+        let head = repo.find_reference("HEAD")?;
+        let branch_ref = head
+            .symbolic_target()
+            .ok_or_else(|| AppError::GitError("HEAD is not on a branch".to_string()))?
+            .to_string();
+        let branch = branch_ref.trim_start_matches("refs/heads/").to_string();
+        let old_head = repo.head()?.peel_to_commit()?.id();
+
+        let (new_head, commits_rewritten) = purge::rewrite(&repo, &plan.paths, |original, tree, parents| {
+            self.write_rewritten_commit(&repo, original, tree, parents)
+        })?;
+        repo.reference(&branch_ref, new_head, true, "purge: rewrite history")?;
+
+        let refspec = format!("+refs/heads/{}:refs/heads/{}", branch, branch);
+        let mut remotes = Vec::new();
+        for remote_config in self.config.remotes() {
+            let result = self.push_refspec(&repo, &remote_config, &refspec);
+            if let (Err(e), RemoteRole::Primary) = (&result, remote_config.role) {
+                repo.reference(&branch_ref, old_head, true, "purge: restore after failed push")?;
+                return Err(AppError::GitError(format!(
+                    "Purge aborted, force-push to {} failed: {}",
+                    remote_config.name, e
+                )));
+            }
+            remotes.push(PurgePush {
+                remote: remote_config.name.clone(),
+                error: result.err().map(|e| e.to_string()),
+            });
+        }
+
+        self.prune_unreachable();
+
+        warn!(
+            "Purged {} from history, rewrote {} commits",
+            plan.paths.join(", "),
+            commits_rewritten
+        );
+        Ok(PurgeResult {
+            new_head: (new_head != old_head).then(|| new_head.to_string()),
+            commits_rewritten,
+            remotes,
+        })
+    }
+
+    /// Writes a copy of `original` with a new tree and parents, keeping its
+    /// author, committer and message. It is re-signed with the configured
+    /// key, as the old signature no longer matches.
+    fn write_rewritten_commit(
+        &self,
+        repo: &Repository,
+        original: &Commit,
+        tree: &Tree,
+        parents: &[&Commit],
+    ) -> AppResult<Oid> {
+        let message = original.message_raw().unwrap_or_default();
+        let buffer = repo.commit_create_buffer(&original.author(), &original.committer(), message, tree, parents)?;
+        let buffer = buffer
+            .as_str()
+            .ok_or_else(|| AppError::GitError("Commit buffer is not valid UTF-8".to_string()))?;
+
+        match &self.config.signing_key {
+            Some(key_id) => {
+                let gpg_signature = signing::sign(buffer, key_id)?;
+                Ok(repo.commit_signed(buffer, &gpg_signature, None)?)
+            }
+            None => Ok(repo.odb()?.write(ObjectType::Commit, buffer.as_bytes())?),
+        }
+    }
+
+    /// Drops reflog entries and unreachable objects so purged blobs leave
+    /// the local object database. libgit2 has no garbage collection, so this
+    /// uses the git CLI and only warns when it is unavailable.
+    fn prune_unreachable(&self) {
+        let commands: [&[&str]; 2] = [
+            &["reflog", "expire", "--expire=now", "--all"],
+            &["gc", "--prune=now", "--quiet"],
+        ];
+        for args in commands {
+            let output = std::process::Command::new("git")
+                .arg("-C")
+                .arg(&self.repo_path)
+                .args(args)
+                .output();
+            match output {
+                Ok(output) if output.status.success() => {}
+                Ok(output) => warn!(
+                    "git {} failed after purge: {}",
+                    args[0],
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+                Err(e) => warn!("Could not run git {} after purge: {}", args[0], e),
+            }
+        }
+    }
+
+    /// Commits `tree` on top of `head`. Callers hold the repository lock
+    /// from reading HEAD on, so no store change can land in between.
+    fn commit_restore(
+        &self,
+        repo: &Repository,
+        head: &Commit,
+        tree: &Tree,
+        message: &str,
+        author: &CommitAuthor,
+    ) -> AppResult<Restore> {
+        let changes = history::diff_trees(repo, Some(&head.tree()?), tree)?;
+        if changes.is_empty() {
+            return Ok(Restore { commit: None, changes });
+        }
+
+        // Update the working tree first so pass sees the restored entries
+        repo.checkout_tree(tree.as_object(), Some(CheckoutBuilder::new().safe()))?;
+
+        let commit_id = self.create_commit(repo, author, message, tree, &[head])?;
+
+        info!("Created commit {}: {}", commit_id, message.lines().next().unwrap_or_default());
+        Ok(Restore {
+            commit: Some(commit_id.to_string()),
+            changes,
+        })
+    }
+
+    fn lock_repo(&self) -> MutexGuard<'_, ()> {
+        // The guarded data is (), so a panicked holder leaves nothing inconsistent
+        self.repo_lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn open_repository(&self) -> AppResult<Repository> {
+        Repository::open(&self.repo_path)
+            .map_err(|e| AppError::GitError(format!("Failed to open repository: {}", e)))
+    }
+
+    /// Creates a commit on HEAD, signed with the configured GPG key if any.
+    fn create_commit(
+        &self,
+        repo: &Repository,
+        author: &CommitAuthor,
+        message: &str,
+        tree: &Tree,
+        parents: &[&Commit],
+    ) -> AppResult<Oid> {
+        let signature = author.signature()?;
+        let message = author.message(message);
+        let Some(key_id) = &self.config.signing_key else {
+            return Ok(repo.commit(Some("HEAD"), &signature, &signature, &message, tree, parents)?);
+        };
+
+        let buffer = repo.commit_create_buffer(&signature, &signature, &message, tree, parents)?;
+        let buffer = buffer
+            .as_str()
+            .ok_or_else(|| AppError::GitError("Commit buffer is not valid UTF-8".to_string()))?;
+        let gpg_signature = signing::sign(buffer, key_id)?;
+        let commit_id = repo.commit_signed(buffer, &gpg_signature, None)?;
+
+        // commit_signed does not move any reference, so advance HEAD's
+        // branch, but only from the parent the commit was made on, as
+        // repo.commit does for unsigned commits
+        let head = repo.find_reference("HEAD")?;
+        let branch = head
+            .symbolic_target()
+            .ok_or_else(|| AppError::GitError("HEAD is not on a branch".to_string()))?;
+        let log_message = format!("commit: {}", message);
+        let moved = match parents.first() {
+            Some(parent) => repo.reference_matching(branch, commit_id, true, parent.id(), &log_message),
+            None => repo.reference(branch, commit_id, false, &log_message),
+        };
+        moved.map_err(|e| {
+            AppError::Conflict(format!(
+                "The branch moved while committing, commit {} was not applied: {}",
+                commit_id, e
+            ))
+        })?;
+
+        Ok(commit_id)
+    }
+
+    /// Refuses remote history that introduces commits not signed by a
+    /// trusted key. Verification is off while the allowlist is empty.
+    fn verify_incoming(&self, repo: &Repository, local: Option<Oid>, remote: Oid) -> AppResult<()> {
+        if self.config.trusted_signing_keys.is_empty() {
+            return Ok(());
+        }
+
+        let mut revwalk = repo.revwalk()?;
+        revwalk.push(remote)?;
+        if let Some(local) = local {
+            revwalk.hide(local)?;
+        }
+
+        for oid in revwalk {
+            let oid = oid?;
+            let status = self.check_signature(repo, oid)?.status;
+            if status != SignatureStatus::Trusted {
+                return Err(AppError::GitError(format!(
+                    "Refusing to merge remote commit {}: signature is {:?}",
+                    oid, status
+                )));
+            }
+        }
+
         Ok(())
     }
 
+    /// Scans the store for plaintext secrets and damaged entries, and the
+    /// repository for missing objects and unexpected recipient changes.
+    pub fn check_integrity(&self) -> AppResult<IntegrityReport> {
+        if !self.repo_path.exists() {
+            return Ok(IntegrityReport::new(Vec::new()));
+        }
+
+        let mut findings = integrity::scan_store_files(&self.repo_path)?;
+        if let Ok(repo) = Repository::open(&self.repo_path) {
+            findings.extend(integrity::check_repository(&repo)?);
+            findings.extend(integrity::check_recipient_changes(&repo, |commit| {
+                self.is_trusted_signer(&repo, commit)
+            })?);
+        }
+
+        Ok(IntegrityReport::new(findings))
+    }
+
+    /// Only commits signed by a trusted key are trusted to change
+    /// recipients. Author emails are not, as anyone who can push sets them.
+    fn is_trusted_signer(&self, repo: &Repository, commit: &Commit) -> bool {
+        !self.config.trusted_signing_keys.is_empty()
+            && self
+                .check_signature(repo, commit.id())
+                .is_ok_and(|check| check.status == SignatureStatus::Trusted)
+    }
+
+    fn check_signature(&self, repo: &Repository, oid: Oid) -> AppResult<signing::SignatureCheck> {
+        match repo.extract_signature(&oid, None) {
+            Ok((signature, signed_data)) => {
+                signing::verify(&signature, &signed_data, &self.config.trusted_signing_keys)
+            }
+            Err(e) if e.code() == ErrorCode::NotFound => Ok(signing::SignatureCheck {
+                status: SignatureStatus::Unsigned,
+                fingerprint: None,
+            }),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Lists the signature state of the most recent commits on HEAD.
+    pub fn signature_report(&self, limit: usize) -> AppResult<Vec<CommitSignature>> {
+        let repo = Repository::open(&self.repo_path)
+            .map_err(|e| AppError::GitError(format!("Failed to open repository: {}", e)))?;
+
+        let mut revwalk = repo.revwalk()?;
+        revwalk.set_sorting(Sort::TIME)?;
+        revwalk.push_head()?;
+
+        let mut report = Vec::new();
+        for oid in revwalk.take(limit) {
+            let commit = repo.find_commit(oid?)?;
+            let check = self.check_signature(&repo, commit.id())?;
+
+            report.push(CommitSignature {
+                commit: commit.id().to_string(),
+                author: commit.author().name().unwrap_or_default().to_string(),
+                date: DateTime::from_timestamp(commit.time().seconds(), 0).unwrap_or_default(),
+                summary: commit.summary().unwrap_or_default().to_string(),
+                status: check.status,
+                fingerprint: check.fingerprint,
+            });
+        }
+
+        Ok(report)
+    }
+
+    /// Pages through store history with the entries each commit changed.
+    pub fn history(&self, query: &HistoryQuery) -> AppResult<HistoryPage> {
+        let repo = self.open_repository()?;
+        history::list_commits(&repo, query)
+    }
+
     fn get_last_commit_hash(&self, repo: &Repository) -> AppResult<Option<String>> {
         match repo.head() {
             Ok(head) => {
@@ -231,12 +885,620 @@ impl GitSync {
         }
     }
 
+    /// Current sync status. Pending counts come straight from the commit
+    /// graph: local commits are the durable push queue, and anything not
+    /// yet on a remote's tracking branch is still waiting to be pushed.
     pub fn get_status(&self) -> SyncStatus {
-        SyncStatus {
-            last_sync: None, // TODO: Store this in state
-            last_commit: None,
-            is_syncing: false,
-            error: None,
+        self.with_pending_counts(self.status.clone())
+    }
+
+    /// Fills in the pending counts of `status` from the current commit graph.
+    pub fn with_pending_counts(&self, mut status: SyncStatus) -> SyncStatus {
+        if let Ok(repo) = Repository::open(&self.repo_path) {
+            status.pending_changes = if self.config.is_local_only() {
+                0
+            } else {
+                count_unpushed(&repo, &self.config.remote_name).unwrap_or(0)
+            };
+            for remote in &mut status.remotes {
+                remote.pending_changes = count_unpushed(&repo, &remote.name).unwrap_or(0);
+            }
         }
+
+        status
     }
-}
\ No newline at end of file
+}
+
+/// Counts commits on the current branch that `remote` does not have yet,
+/// as far as its remote-tracking branch knows.
+fn count_unpushed(repo: &Repository, remote: &str) -> AppResult<usize> {
+    let head = repo.head()?;
+    let (Some(branch_name), Some(local)) = (head.shorthand(), head.target()) else {
+        return Ok(0);
+    };
+
+    match repo.find_reference(&format!("refs/remotes/{}/{}", remote, branch_name)) {
+        Ok(tracking) => {
+            let upstream = tracking.peel_to_commit()?.id();
+            Ok(repo.graph_ahead_behind(local, upstream)?.0)
+        }
+        Err(e) if e.code() == ErrorCode::NotFound => {
+            // Never pushed: the whole branch is pending
+            let mut revwalk = repo.revwalk()?;
+            revwalk.push(local)?;
+            Ok(revwalk.count())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn remote_callbacks(access_token: &str) -> RemoteCallbacks<'_> {
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.credentials(move |_url, username_from_url, _allowed_types| {
+        Cred::userpass_plaintext(username_from_url.unwrap_or("git"), access_token)
+    });
+    callbacks
+}
+
+/// Writes a copy of `base` with the entry at `path` replaced by `blob`, or
+/// removed when `blob` is `None`. Trees left empty are pruned, as git does.
+fn update_tree(
+    repo: &Repository,
+    base: Option<&Tree>,
+    path: &[&str],
+    blob: Option<Oid>,
+) -> AppResult<Option<Oid>> {
+    let (name, rest) = path
+        .split_first()
+        .ok_or_else(|| AppError::GitError("Empty path in store change".to_string()))?;
+    let mut builder = repo.treebuilder(base)?;
+
+    let entry_id = if rest.is_empty() {
+        blob
+    } else {
+        let subtree = match builder.get(name)? {
+            Some(entry) if entry.kind() == Some(ObjectType::Tree) => Some(repo.find_tree(entry.id())?),
+            _ => None,
+        };
+        update_tree(repo, subtree.as_ref(), rest, blob)?
+    };
+
+    match entry_id {
+        Some(id) if rest.is_empty() => builder.insert(name, id, 0o100644).map(|_| ())?,
+        Some(id) => builder.insert(name, id, 0o040000).map(|_| ())?,
+        None => {
+            if builder.get(name)?.is_some() {
+                builder.remove(name)?;
+            }
+        }
+    }
+
+    if builder.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(builder.write()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn create_test_git_sync(repo_path: &std::path::Path) -> GitSync {
+        GitSync::new(
+            GitConfig {
+                author_name: "Test Author".to_string(),
+                author_email: "author@example.com".to_string(),
+                ..GitConfig::default()
+            },
+            repo_path.to_path_buf(),
+        )
+        .unwrap()
+    }
+
+    fn head_commit(repo: &Repository) -> Commit<'_> {
+        repo.head().unwrap().peel_to_commit().unwrap()
+    }
+
+    fn commit_file(repo: &Repository, name: &str, content: &str, message: &str) -> Oid {
+        let workdir = repo.workdir().unwrap().to_path_buf();
+        fs::write(workdir.join(name), content).unwrap();
+
+        let mut index = repo.index().unwrap();
+        index.add_path(std::path::Path::new(name)).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+
+        let signature = Signature::now("Other Device", "other@example.com").unwrap();
+        let parent = repo.head().ok().map(|head| head.peel_to_commit().unwrap());
+        let parents: Vec<&Commit> = parent.iter().collect();
+        repo.commit(Some("HEAD"), &signature, &signature, message, &tree, &parents).unwrap()
+    }
+
+    fn push_head(repo: &Repository) {
+        let head = repo.head().unwrap();
+        let refname = head.name().unwrap();
+        repo.find_remote("origin")
+            .unwrap()
+            .push(&[format!("{}:{}", refname, refname)], None)
+            .unwrap();
+    }
+
+    fn remote_url(temp_dir: &TempDir) -> String {
+        temp_dir.path().join("remote.git").to_string_lossy().to_string()
+    }
+
+    /// Sets up a bare remote, a second device that pushes to it, and a
+    /// local clone for GitSync.
+    fn create_remote_and_clones(temp_dir: &TempDir) -> (Repository, Repository) {
+        Repository::init_bare(temp_dir.path().join("remote.git")).unwrap();
+
+        let other = Repository::init(temp_dir.path().join("other")).unwrap();
+        other.remote("origin", &remote_url(temp_dir)).unwrap();
+        commit_file(&other, "github.com.gpg", "ciphertext", "Add given password for github.com to store.");
+        push_head(&other);
+
+        let local = Repository::clone(&remote_url(temp_dir), temp_dir.path().join("local")).unwrap();
+        (other, local)
+    }
+
+    #[test]
+    fn test_store_change_messages() {
+        assert_eq!(
+            StoreChange::Insert("github.com".to_string()).message(),
+            "Add given password for github.com to store."
+        );
+        assert_eq!(
+            StoreChange::Remove("/work/vpn".to_string()).message(),
+            "Remove work/vpn from store."
+        );
+        assert_eq!(StoreChange::Remove("/work/vpn".to_string()).file(), "work/vpn.gpg");
+    }
+
+    #[test]
+    fn test_commit_change_commits_only_the_changed_entry() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let git_sync = create_test_git_sync(temp_dir.path());
+
+        fs::create_dir_all(temp_dir.path().join("work")).unwrap();
+        fs::write(temp_dir.path().join("work/vpn.gpg"), "ciphertext").unwrap();
+        fs::write(temp_dir.path().join("unrelated.txt"), "local edit").unwrap();
+
+        let author = git_sync.default_author();
+        let commit_id = git_sync
+            .commit_change(&StoreChange::Insert("work/vpn".to_string()), &author)
+            .unwrap();
+        assert!(commit_id.is_some());
+
+        let commit = head_commit(&repo);
+        assert_eq!(commit.message(), Some("Add given password for work/vpn to store."));
+        assert_eq!(commit.author().name(), Some("Test Author"));
+        assert_eq!(commit.author().email(), Some("author@example.com"));
+
+        let tree = commit.tree().unwrap();
+        assert!(tree.get_path(std::path::Path::new("work/vpn.gpg")).is_ok());
+        assert!(tree.get_path(std::path::Path::new("unrelated.txt")).is_err());
+    }
+
+    #[test]
+    fn test_commit_change_records_removal() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let git_sync = create_test_git_sync(temp_dir.path());
+        let author = CommitAuthor {
+            name: "Alice".to_string(),
+            email: "alice@example.com".to_string(),
+            user_id: Some("alice".to_string()),
+        };
+
+        fs::create_dir_all(temp_dir.path().join("work")).unwrap();
+        fs::write(temp_dir.path().join("work/vpn.gpg"), "ciphertext").unwrap();
+        fs::write(temp_dir.path().join("github.com.gpg"), "ciphertext").unwrap();
+        git_sync.commit_change(&StoreChange::Insert("work/vpn".to_string()), &author).unwrap();
+        git_sync.commit_change(&StoreChange::Insert("github.com".to_string()), &author).unwrap();
+
+        fs::remove_file(temp_dir.path().join("work/vpn.gpg")).unwrap();
+        git_sync.commit_change(&StoreChange::Remove("work/vpn".to_string()), &author).unwrap();
+
+        let commit = head_commit(&repo);
+        assert_eq!(commit.message(), Some("Remove work/vpn from store.\n\nKagikanri-User: alice"));
+        assert_eq!(commit.summary(), Some("Remove work/vpn from store."));
+        assert_eq!(commit.author().name(), Some("Alice"));
+        assert_eq!(commit.parent_count(), 1);
+
+        // The emptied folder is pruned while other entries are kept
+        let tree = commit.tree().unwrap();
+        assert!(tree.get_name("work").is_none());
+        assert!(tree.get_name("github.com.gpg").is_some());
+        assert!(repo.statuses(None).unwrap().iter().all(|s| s.path() != Some("work/vpn.gpg")));
+    }
+
+    #[test]
+    fn test_commit_change_without_changes_is_skipped() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let git_sync = create_test_git_sync(temp_dir.path());
+        let author = git_sync.default_author();
+
+        fs::write(temp_dir.path().join("github.com.gpg"), "ciphertext").unwrap();
+        git_sync.commit_change(&StoreChange::Insert("github.com".to_string()), &author).unwrap();
+        let first = head_commit(&repo).id();
+
+        let result = git_sync
+            .commit_change(&StoreChange::Insert("github.com".to_string()), &author)
+            .unwrap();
+        assert_eq!(result, None);
+        assert_eq!(head_commit(&repo).id(), first);
+    }
+
+    #[test]
+    fn test_sync_rejects_unsigned_remote_commits() {
+        let temp_dir = TempDir::new().unwrap();
+        let (other, local) = create_remote_and_clones(&temp_dir);
+        let local_head = head_commit(&local).id();
+
+        commit_file(&other, "gitlab.com.gpg", "ciphertext", "Add given password for gitlab.com to store.");
+        push_head(&other);
+
+        let mut git_sync = create_test_git_sync(local.workdir().unwrap());
+        git_sync.config.repo_url = remote_url(&temp_dir);
+        git_sync.config.trusted_signing_keys = vec!["0123456789ABCDEF0123456789ABCDEF01234567".to_string()];
+
+        let result = git_sync.sync();
+        assert!(matches!(result, Err(AppError::GitError(ref e)) if e.contains("Unsigned")));
+        assert!(git_sync.get_status().error.is_some());
+        assert_eq!(head_commit(&local).id(), local_head);
+    }
+
+    #[test]
+    fn test_sync_fast_forwards_without_allowlist() {
+        let temp_dir = TempDir::new().unwrap();
+        let (other, local) = create_remote_and_clones(&temp_dir);
+
+        let remote_head = commit_file(&other, "gitlab.com.gpg", "ciphertext", "Add given password for gitlab.com to store.");
+        push_head(&other);
+
+        let mut git_sync = create_test_git_sync(local.workdir().unwrap());
+        git_sync.config.repo_url = remote_url(&temp_dir);
+        let status = git_sync.sync().unwrap();
+
+        assert_eq!(status.last_commit, Some(remote_head.to_string()));
+        assert!(git_sync.get_status().error.is_none());
+        assert!(local.workdir().unwrap().join("gitlab.com.gpg").exists());
+
+        let report = git_sync.signature_report(10).unwrap();
+        assert_eq!(report.len(), 2);
+        assert!(report.iter().all(|commit| commit.status == SignatureStatus::Unsigned));
+    }
+
+    #[test]
+    fn test_signed_commits_are_reported_as_trusted() {
+        let gnupg_home = TempDir::new().unwrap();
+        std::env::set_var("GNUPGHOME", gnupg_home.path());
+        let generated = std::process::Command::new("gpg")
+            .args(["--batch", "--passphrase", "", "--quick-gen-key", "Kagikanri Test <kagikanri@example.com>", "ed25519", "sign", "never"])
+            .output();
+        if !matches!(generated, Ok(ref output) if output.status.success()) {
+            println!("Skipping signed commit test - gpg unavailable");
+            return;
+        }
+        let listing = std::process::Command::new("gpg")
+            .args(["--batch", "--with-colons", "--list-secret-keys"])
+            .output()
+            .unwrap();
+        let fingerprint = String::from_utf8_lossy(&listing.stdout)
+            .lines()
+            .find(|line| line.starts_with("fpr:"))
+            .and_then(|line| line.split(':').nth(9))
+            .unwrap()
+            .to_string();
+
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let mut git_sync = create_test_git_sync(temp_dir.path());
+        git_sync.config.signing_key = Some(fingerprint.clone());
+        git_sync.config.trusted_signing_keys = vec![fingerprint.clone()];
+
+        fs::write(temp_dir.path().join("github.com.gpg"), "ciphertext").unwrap();
+        let author = git_sync.default_author();
+        let commit_id = git_sync
+            .commit_change(&StoreChange::Insert("github.com".to_string()), &author)
+            .unwrap()
+            .unwrap();
+        assert_eq!(head_commit(&repo).id().to_string(), commit_id);
+
+        // A commit made on a stale parent must not move the branch
+        let stale = head_commit(&repo);
+        fs::write(temp_dir.path().join("gitlab.com.gpg"), "ciphertext").unwrap();
+        git_sync
+            .commit_change(&StoreChange::Insert("gitlab.com".to_string()), &author)
+            .unwrap();
+        let current = head_commit(&repo).id();
+        let tree = stale.tree().unwrap();
+        assert!(git_sync.create_commit(&repo, &author, "Stale write", &tree, &[&stale]).is_err());
+        assert_eq!(head_commit(&repo).id(), current);
+
+        let report = git_sync.signature_report(10).unwrap();
+        assert_eq!(report.len(), 2);
+        assert_eq!(report[0].status, SignatureStatus::Trusted);
+        assert_eq!(report[0].fingerprint.as_deref(), Some(fingerprint.as_str()));
+
+        git_sync.config.trusted_signing_keys = vec!["FFFFFFFFFFFFFFFF".to_string()];
+        assert_eq!(git_sync.signature_report(10).unwrap()[0].status, SignatureStatus::Untrusted);
+    }
+
+    #[test]
+    fn test_mirrors_receive_updates_while_primary_is_down() {
+        let temp_dir = TempDir::new().unwrap();
+        let (_other, local) = create_remote_and_clones(&temp_dir);
+        let mirror_path = temp_dir.path().join("mirror.git");
+        let mirror = Repository::init_bare(&mirror_path).unwrap();
+
+        let mut git_sync = create_test_git_sync(local.workdir().unwrap());
+        git_sync.config.repo_url = temp_dir.path().join("missing.git").to_string_lossy().to_string();
+        git_sync.config.mirrors = vec![RemoteConfig {
+            name: "backup".to_string(),
+            url: mirror_path.to_string_lossy().to_string(),
+            access_token: String::new(),
+            role: RemoteRole::Mirror,
+        }];
+
+        fs::write(local.workdir().unwrap().join("gitlab.com.gpg"), "ciphertext").unwrap();
+        let author = git_sync.default_author();
+        let commit_id = git_sync
+            .commit_change(&StoreChange::Insert("gitlab.com".to_string()), &author)
+            .unwrap()
+            .unwrap();
+
+        // The primary failure fails the sync, but the mirror is still updated
+        assert!(git_sync.sync().is_err());
+        let mirror_head = mirror.find_reference(local.head().unwrap().name().unwrap()).unwrap();
+        assert_eq!(mirror_head.target().unwrap().to_string(), commit_id);
+
+        let status = git_sync.get_status();
+        assert!(status.error.is_some());
+        assert_eq!(status.remotes.len(), 2);
+        assert_eq!(status.remotes[0].name, "origin");
+        assert_eq!(status.remotes[0].role, RemoteRole::Primary);
+        assert!(status.remotes[0].error.is_some());
+        assert!(status.remotes[0].last_success.is_none());
+        assert_eq!(status.remotes[1].name, "backup");
+        assert!(status.remotes[1].error.is_none());
+        assert!(status.remotes[1].last_success.is_some());
+    }
+
+    #[test]
+    fn test_offline_commits_stay_pending_until_pushed() {
+        let temp_dir = TempDir::new().unwrap();
+        let (_other, local) = create_remote_and_clones(&temp_dir);
+
+        let mut git_sync = create_test_git_sync(local.workdir().unwrap());
+        git_sync.config.repo_url = temp_dir.path().join("missing.git").to_string_lossy().to_string();
+
+        let author = git_sync.default_author();
+        for entry in ["gitlab.com", "work/vpn"] {
+            let file = local.workdir().unwrap().join(format!("{}.gpg", entry));
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(file, "ciphertext").unwrap();
+            git_sync.commit_change(&StoreChange::Insert(entry.to_string()), &author).unwrap();
+        }
+
+        assert!(git_sync.sync().is_err());
+        let status = git_sync.get_status();
+        assert_eq!(status.pending_changes, 2);
+        assert_eq!(status.remotes[0].pending_changes, 2);
+
+        // Once the remote is reachable again the queue drains
+        git_sync.config.repo_url = remote_url(&temp_dir);
+        git_sync.sync().unwrap();
+        let status = git_sync.get_status();
+        assert_eq!(status.pending_changes, 0);
+        assert!(status.error.is_none());
+    }
+
+    #[test]
+    fn test_rollback_restores_tree_as_new_commit() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let git_sync = create_test_git_sync(temp_dir.path());
+
+        let good = commit_file(&repo, "github.com.gpg", "v1", "Add given password for github.com to store.");
+        commit_file(&repo, "github.com.gpg", "damaged", "Bad import");
+        commit_file(&repo, "gitlab.com.gpg", "imported", "Bad import");
+        let before = head_commit(&repo).id();
+
+        let preview = git_sync.preview_rollback(&good.to_string()).unwrap();
+        assert_eq!(preview.len(), 2);
+        assert_eq!(head_commit(&repo).id(), before);
+
+        let author = git_sync.default_author();
+        let restore = git_sync.rollback(&good.to_string()[..10], &author).unwrap();
+        assert_eq!(restore.changes, preview);
+
+        let head = head_commit(&repo);
+        assert_eq!(restore.commit, Some(head.id().to_string()));
+        assert_eq!(head.parent_id(0).unwrap(), before);
+        assert_eq!(head.tree_id(), repo.find_commit(good).unwrap().tree_id());
+        assert_eq!(fs::read_to_string(temp_dir.path().join("github.com.gpg")).unwrap(), "v1");
+        assert!(!temp_dir.path().join("gitlab.com.gpg").exists());
+
+        // Already at the target state: nothing to commit
+        let again = git_sync.rollback(&good.to_string(), &author).unwrap();
+        assert_eq!(again.commit, None);
+    }
+
+    #[test]
+    fn test_revert_undoes_single_commit() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let git_sync = create_test_git_sync(temp_dir.path());
+
+        commit_file(&repo, "github.com.gpg", "v1", "Add given password for github.com to store.");
+        let bad = commit_file(&repo, "gitlab.com.gpg", "oops", "Add given password for gitlab.com to store.");
+        commit_file(&repo, "github.com.gpg", "v2", "Add given password for github.com to store.");
+
+        let preview = git_sync.preview_revert(&bad.to_string()).unwrap();
+        assert_eq!(preview.len(), 1);
+        assert_eq!(preview[0].path, "gitlab.com");
+
+        let restore = git_sync.revert(&bad.to_string(), &git_sync.default_author()).unwrap();
+        assert!(restore.commit.is_some());
+        assert_eq!(
+            head_commit(&repo).message().unwrap(),
+            format!("Revert \"Add given password for gitlab.com to store.\"\n\nThis reverts commit {}.", bad)
+        );
+        assert!(!temp_dir.path().join("gitlab.com.gpg").exists());
+        assert_eq!(fs::read_to_string(temp_dir.path().join("github.com.gpg")).unwrap(), "v2");
+    }
+
+    #[test]
+    fn test_restore_rejects_unknown_commits() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let git_sync = create_test_git_sync(temp_dir.path());
+        commit_file(&repo, "github.com.gpg", "v1", "Add given password for github.com to store.");
+
+        let result = git_sync.preview_rollback("0123456789abcdef0123456789abcdef01234567");
+        assert!(matches!(result, Err(AppError::NotFound(_))));
+
+        // Revspecs are not commit ids
+        for revspec in ["HEAD", "master", "HEAD~1", "@{0}"] {
+            let result = git_sync.preview_rollback(revspec);
+            assert!(matches!(result, Err(AppError::ValidationError(_))), "{}", revspec);
+        }
+    }
+
+    #[test]
+    fn test_unsigned_recipient_changes_are_reported() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let git_sync = create_test_git_sync(temp_dir.path());
+
+        // Even under Kagikanri's own author email, which anyone can set
+        let mut index = repo.index().unwrap();
+        fs::write(temp_dir.path().join(".gpg-id"), "ABCDEF0123456789\n").unwrap();
+        index.add_path(std::path::Path::new(".gpg-id")).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("Test Author", "author@example.com").unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, "Set recipients.", &tree, &[]).unwrap();
+
+        let report = git_sync.check_integrity().unwrap();
+        assert!(report
+            .findings
+            .iter()
+            .any(|finding| finding.check == integrity::CheckKind::RecipientChange));
+    }
+
+    #[test]
+    fn test_local_only_store_keeps_history_without_remote() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = temp_dir.path().join("password-store");
+        fs::create_dir_all(&store).unwrap();
+        fs::write(store.join("github.com.gpg"), "ciphertext").unwrap();
+
+        let mut git_sync = create_test_git_sync(&store);
+        let status = git_sync.sync().unwrap();
+        assert!(status.error.is_none());
+        assert!(status.remotes.is_empty());
+
+        // Existing entries are recorded when history starts
+        let repo = Repository::open(&store).unwrap();
+        assert_eq!(repo.head().unwrap().shorthand(), Some("main"));
+        assert!(head_commit(&repo).tree().unwrap().get_name("github.com.gpg").is_some());
+
+        fs::write(store.join("gitlab.com.gpg"), "ciphertext").unwrap();
+        let author = git_sync.default_author();
+        git_sync
+            .commit_change(&StoreChange::Insert("gitlab.com".to_string()), &author)
+            .unwrap();
+        assert_eq!(git_sync.get_status().pending_changes, 0);
+        assert!(git_sync.sync().is_ok());
+    }
+
+    #[test]
+    fn test_sync_uses_configured_remote_and_branch() {
+        let temp_dir = TempDir::new().unwrap();
+        let (other, _local) = create_remote_and_clones(&temp_dir);
+
+        // Publish a second branch on the remote
+        let head = head_commit(&other);
+        other.branch("store", &head, false).unwrap();
+        other.set_head("refs/heads/store").unwrap();
+        commit_file(&other, "gitlab.com.gpg", "ciphertext", "Add given password for gitlab.com to store.");
+        other
+            .find_remote("origin")
+            .unwrap()
+            .push(&["refs/heads/store:refs/heads/store"], None)
+            .unwrap();
+
+        let store = temp_dir.path().join("store-clone");
+        let mut git_sync = GitSync::new(
+            GitConfig {
+                repo_url: remote_url(&temp_dir),
+                remote_name: "upstream".to_string(),
+                branch: Some("store".to_string()),
+                ..GitConfig::default()
+            },
+            store.clone(),
+        )
+        .unwrap();
+        git_sync.sync().unwrap();
+
+        let repo = Repository::open(&store).unwrap();
+        assert!(repo.find_remote("upstream").is_ok());
+        assert!(repo.find_remote("origin").is_err());
+        assert_eq!(repo.head().unwrap().shorthand(), Some("store"));
+        assert!(store.join("gitlab.com.gpg").exists());
+
+        fs::write(store.join("vpn.gpg"), "ciphertext").unwrap();
+        let author = git_sync.default_author();
+        git_sync
+            .commit_change(&StoreChange::Insert("vpn".to_string()), &author)
+            .unwrap();
+        assert_eq!(git_sync.get_status().pending_changes, 1);
+
+        git_sync.sync().unwrap();
+        assert_eq!(git_sync.get_status().pending_changes, 0);
+        assert_eq!(git_sync.get_status().remotes[0].name, "upstream");
+    }
+
+    #[test]
+    fn test_purge_rewrites_and_force_pushes_history() {
+        let temp_dir = TempDir::new().unwrap();
+        let (_other, local) = create_remote_and_clones(&temp_dir);
+        let mut git_sync = create_test_git_sync(local.workdir().unwrap());
+        git_sync.config.repo_url = remote_url(&temp_dir);
+
+        commit_file(&local, "leaked.gpg", "ciphertext", "Add given password for leaked to store.");
+        fs::remove_file(local.workdir().unwrap().join("leaked.gpg")).unwrap();
+        let author = git_sync.default_author();
+        git_sync.commit_change(&StoreChange::Remove("leaked".to_string()), &author).unwrap();
+        git_sync.sync().unwrap();
+
+        let paths = vec!["leaked".to_string()];
+        let plan = git_sync.plan_purge(&paths).unwrap();
+        assert_eq!(plan.files, vec!["leaked.gpg".to_string()]);
+        assert!(matches!(git_sync.purge(&paths, "stale"), Err(AppError::Conflict(_))));
+
+        let result = git_sync.purge(&paths, &plan.confirmation_token).unwrap();
+        assert_eq!(result.commits_rewritten, 2);
+        assert_eq!(result.remotes.len(), 1);
+        assert!(result.remotes[0].error.is_none());
+
+        let remote = Repository::open_bare(temp_dir.path().join("remote.git")).unwrap();
+        let remote_head = remote.head().unwrap().target().unwrap();
+        assert_eq!(Some(remote_head.to_string()), result.new_head);
+        assert_eq!(head_commit(&local).id(), remote_head);
+
+        let mut revwalk = remote.revwalk().unwrap();
+        revwalk.push(remote_head).unwrap();
+        for oid in revwalk {
+            let tree = remote.find_commit(oid.unwrap()).unwrap().tree().unwrap();
+            assert!(tree.get_name("leaked.gpg").is_none());
+        }
+        assert!(git_sync.plan_purge(&paths).unwrap().commits_affected.is_empty());
+    }
+}
@@ -0,0 +1,227 @@
+use axum::{
+    extract::{Path, Query, State},
+    response::IntoResponse,
+    Extension, Json,
+};
+use serde::Deserialize;
+use crate::{
+    api_tokens::ApiToken,
+    audit::{AuditAction, AuditContext},
+    error::{ApiResponse, AppError},
+    state::{AppState, Session},
+    users::InviteRequest,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct PurgeRequest {
+    pub paths: Vec<String>,
+    /// Token from the preview of the same paths.
+    pub confirmation_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OidcLinkRequest {
+    /// `sub` claim of the user at the configured provider.
+    pub subject: String,
+}
+
+pub async fn preview_purge(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    Json(request): Json<PurgeRequest>,
+) -> impl IntoResponse {
+    ApiResponse::from(async move {
+        state.require_admin(&session).await?;
+        
+        let plan = state
+            .with_repository(move |git_sync| git_sync.plan_purge(&request.paths))
+            .await?;
+        
+        Ok(Json(plan))
+    }.await)
+}
+
+pub async fn purge(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    api_token: Option<Extension<ApiToken>>,
+    audit: AuditContext,
+    Json(request): Json<PurgeRequest>,
+) -> impl IntoResponse {
+    ApiResponse::from(async move {
+        state.require_admin(&session).await?;
+        state.require_interactive_auth(&session, api_token.as_deref())?;
+        
+        let token = request.confirmation_token.ok_or_else(|| {
+            AppError::ValidationError(
+                "Preview the purge and pass its confirmation_token to confirm it".to_string(),
+            )
+        })?;
+        let paths = request.paths.join(", ");
+        let result = state.purge_history(request.paths, token, &session).await?;
+        audit.record(AuditAction::Admin, Some(&paths), Some("history purged")).await?;
+        
+        Ok(Json(result))
+    }.await)
+}
+
+pub async fn lockouts(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+) -> impl IntoResponse {
+    ApiResponse::from(async move {
+        state.require_admin(&session).await?;
+        
+        let failures = state.login_throttle.list().await?;
+        
+        Ok(Json(failures))
+    }.await)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClearLockoutsQuery {
+    /// Client address, or `*` for the global counter; all when omitted.
+    pub client: Option<String>,
+}
+
+pub async fn clear_lockouts(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    audit: AuditContext,
+    Query(query): Query<ClearLockoutsQuery>,
+) -> impl IntoResponse {
+    ApiResponse::from(async move {
+        state.require_admin(&session).await?;
+        
+        let cleared = state.login_throttle.clear(query.client.as_deref()).await?;
+        audit.record(AuditAction::Admin, query.client.as_deref(), Some("lockouts cleared")).await?;
+        
+        Ok(Json(serde_json::json!({"cleared": cleared})))
+    }.await)
+}
+
+pub async fn users(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+) -> impl IntoResponse {
+    ApiResponse::from(async move {
+        state.require_admin(&session).await?;
+        
+        let users = state.users.list().await?;
+        
+        Ok(Json(users))
+    }.await)
+}
+
+pub async fn invite_user(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    audit: AuditContext,
+    Json(request): Json<InviteRequest>,
+) -> impl IntoResponse {
+    ApiResponse::from(async move {
+        state.require_admin(&session).await?;
+        state.require_recent_auth(&session)?;
+        
+        let (user, token) = state.users.invite(&request).await?;
+        tracing::info!("User {} invited by {}", user.id, session.user_id);
+        audit.record(AuditAction::Admin, Some(&user.id), Some("user invited")).await?;
+        
+        // The token is only shown here; the admin passes it on to the user
+        Ok(Json(serde_json::json!({"user": user, "invite_token": token})))
+    }.await)
+}
+
+pub async fn disable_user(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    audit: AuditContext,
+    Path(user_id): Path<String>,
+) -> impl IntoResponse {
+    ApiResponse::from(async move {
+        state.require_admin(&session).await?;
+        state.require_recent_auth(&session)?;
+        
+        let user = state.users.set_disabled(&user_id, true).await?;
+        state.remove_user_sessions(&user_id).await?;
+        tracing::warn!("User {} disabled by {}", user_id, session.user_id);
+        audit.record(AuditAction::Admin, Some(&user_id), Some("user disabled")).await?;
+        
+        Ok(Json(user))
+    }.await)
+}
+
+pub async fn enable_user(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    audit: AuditContext,
+    Path(user_id): Path<String>,
+) -> impl IntoResponse {
+    ApiResponse::from(async move {
+        state.require_admin(&session).await?;
+        state.require_recent_auth(&session)?;
+        
+        let user = state.users.set_disabled(&user_id, false).await?;
+        tracing::info!("User {} enabled by {}", user_id, session.user_id);
+        audit.record(AuditAction::Admin, Some(&user_id), Some("user enabled")).await?;
+        
+        Ok(Json(user))
+    }.await)
+}
+
+/// Lets an existing account log in with an OpenID Connect identity.
+pub async fn link_oidc(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    audit: AuditContext,
+    Path(user_id): Path<String>,
+    Json(request): Json<OidcLinkRequest>,
+) -> impl IntoResponse {
+    ApiResponse::from(async move {
+        state.require_admin(&session).await?;
+        state.require_recent_auth(&session)?;
+        let oidc = state.config.auth.oidc.as_ref()
+            .ok_or_else(|| AppError::NotFound("OpenID Connect is not configured".to_string()))?;
+        
+        let user = state.users.link_oidc(&user_id, &oidc.issuer, request.subject.trim()).await?;
+        tracing::info!("User {} linked to OpenID Connect subject {} by {}", user_id, request.subject, session.user_id);
+        audit.record(AuditAction::Admin, Some(&user_id), Some(&format!("linked to OpenID Connect subject {}", request.subject))).await?;
+        
+        Ok(Json(user))
+    }.await)
+}
+
+pub async fn unlink_oidc(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    audit: AuditContext,
+    Path(user_id): Path<String>,
+) -> impl IntoResponse {
+    ApiResponse::from(async move {
+        state.require_admin(&session).await?;
+        state.require_recent_auth(&session)?;
+        
+        let user = state.users.unlink_oidc(&user_id).await?;
+        tracing::info!("User {} unlinked from OpenID Connect by {}", user_id, session.user_id);
+        audit.record(AuditAction::Admin, Some(&user_id), Some("OpenID Connect identity unlinked")).await?;
+        
+        Ok(Json(user))
+    }.await)
+}
+
+pub async fn delete_user(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    audit: AuditContext,
+    Path(user_id): Path<String>,
+) -> impl IntoResponse {
+    ApiResponse::from(async move {
+        state.require_admin(&session).await?;
+        state.require_recent_auth(&session)?;
+        
+        state.delete_user(&user_id, &session).await?;
+        audit.record(AuditAction::Admin, Some(&user_id), Some("user deleted")).await?;
+        
+        Ok(Json(serde_json::json!({"success": true, "deleted": user_id})))
+    }.await)
+}
@@ -0,0 +1,84 @@
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderMap},
+    response::IntoResponse,
+    Extension, Json,
+};
+use serde::Deserialize;
+use crate::{
+    audit::{AuditAction, AuditContext, AuditQuery, ExportFormat},
+    error::{ApiResponse, AppResult},
+    state::{AppState, Session},
+};
+
+pub async fn list(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    Query(query): Query<AuditQuery>,
+) -> impl IntoResponse {
+    ApiResponse::from(async move {
+        state.require_admin(&session).await?;
+
+        let page = state.audit.list(&query).await?;
+
+        Ok(Json(page))
+    }.await)
+}
+
+pub async fn verify(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+) -> impl IntoResponse {
+    ApiResponse::from(async move {
+        state.require_admin(&session).await?;
+
+        let verification = state.audit.verify().await?;
+        if !verification.valid {
+            tracing::error!(
+                "Audit log chain broken at record {:?}: {:?}",
+                verification.broken_at,
+                verification.reason
+            );
+        }
+
+        Ok(Json(verification))
+    }.await)
+}
+
+/// Read alongside the filters of an [`AuditQuery`].
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    pub format: ExportFormat,
+}
+
+/// Downloads the matching records, oldest first, for archiving or a SIEM.
+/// The export is itself recorded.
+pub async fn export(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    audit: AuditContext,
+    Query(query): Query<ExportQuery>,
+    Query(filter): Query<AuditQuery>,
+) -> AppResult<impl IntoResponse> {
+    state.require_admin(&session).await?;
+    state.require_recent_auth(&session)?;
+
+    let (extension, content_type, format_name) = match query.format {
+        ExportFormat::Jsonl => ("jsonl", "application/jsonl", "JSON Lines"),
+        ExportFormat::Syslog => ("log", "text/plain; charset=utf-8", "syslog"),
+    };
+    audit.record(AuditAction::Export, Some("audit-log"), Some(format_name)).await?;
+    let body = state
+        .audit
+        .export(&filter, query.format, &state.config.auth.webauthn_rp_id)
+        .await?;
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, content_type.parse().unwrap());
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        format!("attachment; filename=\"kagikanri-audit.{}\"", extension).parse().unwrap(),
+    );
+
+    Ok((headers, body))
+}
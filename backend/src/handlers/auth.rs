@@ -1,55 +1,284 @@
 use axum::{
-    extract::State,
-    http::{header, HeaderMap, StatusCode},
-    response::IntoResponse,
+    extract::{ConnectInfo, Path, Extension, Query, State},
+    http::{header, HeaderMap},
+    response::{IntoResponse, Redirect},
     Json,
 };
+use serde::{Deserialize, Serialize};
+use std::{
+    future::Future,
+    net::{IpAddr, SocketAddr},
+};
 use crate::{
-    auth::{AuthService, LoginRequest, LoginResponse},
+    api_tokens::{ApiToken, CreateTokenRequest},
+    audit::{AuditAction, AuditContext},
+    auth::{
+        AcceptInviteRequest, ChangeMasterPasswordRequest, LoginRequest, LoginResponse, OidcOutcome, OidcTotpRequest,
+        ReauthRequest,
+    },
+    client_ip::client_ip,
     error::{AppError, AppResult},
-    state::AppState,
+    git::StoreChange,
+    login_passkeys::{AuthenticationFinish, LoginPasskey, RegistrationFinish},
+    state::{AppState, Session},
+    users::BOOTSTRAP_ADMIN,
 };
 
 pub async fn login(
     State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    audit: AuditContext,
     Json(request): Json<LoginRequest>,
 ) -> AppResult<impl IntoResponse> {
-    let auth_service = AuthService::new(state.config.auth.clone(), state.pass.clone());
-    let response = auth_service.authenticate(request).await?;
+    let client = login_client(&state, connect_info, &headers);
+    let audit = audit.claiming(request.username.as_deref().unwrap_or(BOOTSTRAP_ADMIN));
+    let response = throttled(&state, &audit, "password", client, state.auth_service().authenticate(request)).await?;
     
-    // Create session in state
-    let session_id = state.create_session("user").await;
+    start_session(&state, &audit, "password", &headers, client, response).await
+}
+
+pub async fn passkey_login_start(
+    State(state): State<AppState>,
+) -> AppResult<impl IntoResponse> {
+    let start = state.login_passkeys.start_authentication().await?;
     
-    // Set session cookie
-    let cookie = format!(
-        "session={}; HttpOnly; Secure; SameSite=Strict; Max-Age={}",
-        session_id,
-        state.config.auth.session_timeout_hours * 3600
+    Ok(Json(start))
+}
+
+pub async fn passkey_login_finish(
+    State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    audit: AuditContext,
+    Json(request): Json<AuthenticationFinish>,
+) -> AppResult<impl IntoResponse> {
+    let client = login_client(&state, connect_info, &headers);
+    let response = throttled(&state, &audit, "passkey", client, async {
+        let user_id = state
+            .login_passkeys
+            .finish_authentication(&request.ceremony_id, &request.credential)
+            .await?;
+        state
+            .auth_service()
+            .authenticate_passkey(user_id, request.master_password.as_deref())
+            .await
+    })
+    .await?;
+    
+    start_session(&state, &audit, "passkey", &headers, client, response).await
+}
+
+/// Cookie binding an OpenID Connect login to the browser that started it.
+const OIDC_STATE_COOKIE: &str = "oidc_state";
+/// Cookie carrying a first-factor OpenID Connect login to its TOTP step.
+const OIDC_TICKET_COOKIE: &str = "oidc_ticket";
+
+#[derive(Debug, Deserialize)]
+pub struct OidcCallback {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Sends the browser to the OpenID Connect provider.
+pub async fn oidc_login_start(
+    State(state): State<AppState>,
+) -> AppResult<impl IntoResponse> {
+    let start = state.oidc()?.start().await?;
+    
+    // The provider redirects back cross-site, so this cookie is Lax
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::SET_COOKIE,
+        oidc_cookie(OIDC_STATE_COOKIE, &start.state, "Lax", 600).parse().unwrap(),
+    );
+    
+    Ok((headers, Redirect::to(&start.authorization_url)))
+}
+
+/// Where the provider sends the browser back to. Logs in, or in
+/// first-factor mode, sends the browser on to enter the TOTP code.
+pub async fn oidc_login_callback(
+    State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    audit: AuditContext,
+    Query(callback): Query<OidcCallback>,
+) -> AppResult<impl IntoResponse> {
+    let oidc = state.oidc()?;
+    if let Some(error) = callback.error {
+        return Err(AppError::AuthenticationFailed(format!("The provider refused the login: {}", error)));
+    }
+    let (Some(code), Some(login_state)) = (callback.code, callback.state) else {
+        return Err(AppError::ValidationError("code and state are required".to_string()));
+    };
+    // Otherwise an attacker could complete their own login in a victim's
+    // browser
+    if cookie(&headers, OIDC_STATE_COOKIE).as_deref() != Some(login_state.as_str()) {
+        return Err(AppError::AuthenticationFailed("The login was started in another browser".to_string()));
+    }
+    
+    let client = login_client(&state, connect_info, &headers);
+    let outcome = throttled(&state, &audit, "oidc", client, async {
+        let identity = oidc.finish(&login_state, &code).await?;
+        state.auth_service().authenticate_oidc(&identity).await
+    })
+    .await?;
+    
+    let mut response_headers = HeaderMap::new();
+    response_headers.append(
+        header::SET_COOKIE,
+        oidc_cookie(OIDC_STATE_COOKIE, "", "Lax", 0).parse().unwrap(),
     );
+    let location = match outcome {
+        OidcOutcome::LoggedIn(mut response) => {
+            let token = open_session(&state, &audit, "oidc", &headers, client, &mut response).await?;
+            response_headers.append(header::SET_COOKIE, state.session_cookie(&token).parse().unwrap());
+            "/"
+        }
+        OidcOutcome::TotpRequired(user_id) => {
+            let ticket = oidc.issue_ticket(&user_id);
+            response_headers.append(
+                header::SET_COOKIE,
+                oidc_cookie(OIDC_TICKET_COOKIE, &ticket, "Strict", 300).parse().unwrap(),
+            );
+            "/?oidc=totp"
+        }
+    };
+    
+    Ok((response_headers, Redirect::to(location)))
+}
+
+/// TOTP step of a first-factor OpenID Connect login.
+pub async fn oidc_login_totp(
+    State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    audit: AuditContext,
+    Json(request): Json<OidcTotpRequest>,
+) -> AppResult<impl IntoResponse> {
+    let oidc = state.oidc()?;
+    let ticket = cookie(&headers, OIDC_TICKET_COOKIE)
+        .ok_or_else(|| AppError::AuthenticationFailed("Log in with the provider first".to_string()))?;
+    
+    let client = login_client(&state, connect_info, &headers);
+    let response = throttled(&state, &audit, "oidc", client, async {
+        let user_id = oidc.ticket_user(&ticket)?;
+        state.auth_service().authenticate_oidc_totp(&user_id, &request.totp_code).await
+    })
+    .await?;
+    oidc.redeem_ticket(&ticket);
+    
+    start_session(&state, &audit, "oidc", &headers, client, response).await
+}
+
+/// Cookie scoped to the OpenID Connect login endpoints.
+fn oidc_cookie(name: &str, value: &str, same_site: &str, max_age: u64) -> String {
+    format!(
+        "{}={}; HttpOnly; Secure; SameSite={}; Path=/api/auth/login/oidc; Max-Age={}",
+        name, value, same_site, max_age
+    )
+}
+
+fn login_client(state: &AppState, connect_info: Option<ConnectInfo<SocketAddr>>, headers: &HeaderMap) -> Option<IpAddr> {
+    let peer = connect_info.map(|ConnectInfo(address)| address.ip());
+    client_ip(peer, headers, &state.config.server.trusted_proxies)
+}
+
+/// Runs a login or re-authentication attempt under the throttle, counting
+/// failed credentials against `client`. Failures and lockouts are audited
+/// with the `method` of the attempt.
+async fn throttled<T>(
+    state: &AppState,
+    audit: &AuditContext,
+    method: &str,
+    client: Option<IpAddr>,
+    attempt: impl Future<Output = AppResult<T>>,
+) -> AppResult<T> {
+    let client = client
+        .map(|ip| ip.to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    let _attempt = match state.login_throttle.begin(&client).await {
+        Ok(attempt) => attempt,
+        Err(e) => {
+            audit.record(AuditAction::LoginFailed, Some(method), Some(&e.to_string())).await?;
+            return Err(e);
+        }
+    };
+    
+    let response = match attempt.await {
+        Ok(response) => response,
+        Err(e @ AppError::AuthenticationFailed(_)) => {
+            state.login_throttle.record_failure(&client).await?;
+            audit.record(AuditAction::LoginFailed, Some(method), Some(&e.to_string())).await?;
+            return Err(e);
+        }
+        Err(e) => return Err(e),
+    };
+    state.login_throttle.record_success(&client).await?;
+    
+    Ok(response)
+}
+
+async fn start_session(
+    state: &AppState,
+    audit: &AuditContext,
+    method: &str,
+    request_headers: &HeaderMap,
+    client: Option<IpAddr>,
+    mut response: LoginResponse,
+) -> AppResult<impl IntoResponse> {
+    let token = open_session(state, audit, method, request_headers, client, &mut response).await?;
     
     let mut headers = HeaderMap::new();
-    headers.insert(header::SET_COOKIE, cookie.parse().unwrap());
+    headers.insert(header::SET_COOKIE, state.session_cookie(&token).parse().unwrap());
     
     Ok((headers, Json(response)))
 }
 
+/// Creates the session of a successful login and returns its token.
+async fn open_session(
+    state: &AppState,
+    audit: &AuditContext,
+    method: &str,
+    request_headers: &HeaderMap,
+    client: Option<IpAddr>,
+    response: &mut LoginResponse,
+) -> AppResult<String> {
+    // Record the device, so users can recognize their sessions
+    let user_agent = request_headers
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok());
+    let ip_address = client.map(|ip| ip.to_string());
+    let (token, session) = state
+        .create_session(&response.user_id, user_agent, ip_address.as_deref())
+        .await?;
+    response.expires_at = session.expires_at;
+    audit.record_as(Some(&response.user_id), AuditAction::Login, Some(method), None).await?;
+    
+    Ok(token)
+}
+
 pub async fn status(
     State(state): State<AppState>,
     headers: HeaderMap,
+    session: Option<Extension<Session>>,
 ) -> Json<serde_json::Value> {
-    let auth_service = AuthService::new(state.config.auth.clone(), state.pass.clone());
+    let auth_service = state.auth_service();
     
-    // Extract session from cookie or Authorization header
-    let session_id = extract_session(&headers);
-    let is_authenticated = match &session_id {
-        Some(id) => state.is_authenticated(id).await,
-        None => false,
+    // Sessions opened for the reverse proxy have no cookie yet, so prefer
+    // the one the middleware found
+    let session = match (session, extract_session(&headers)) {
+        (Some(Extension(session)), _) => Some(session),
+        (None, Some(token)) => state.get_session(&token).await.ok().flatten(),
+        (None, None) => None,
     };
     
-    let auth_status = auth_service.get_auth_status(session_id).await;
+    let auth_status = auth_service.get_auth_status(session.as_ref()).await;
     
     Json(serde_json::json!({
-        "authenticated": is_authenticated,
+        "authenticated": session.is_some(),
         "user_id": auth_status.user_id,
         "expires_at": auth_status.expires_at
     }))
@@ -58,31 +287,279 @@ pub async fn status(
 pub async fn logout(
     State(state): State<AppState>,
     headers: HeaderMap,
+    audit: AuditContext,
 ) -> impl IntoResponse {
-    if let Some(session_id) = extract_session(&headers) {
-        state.remove_session(&session_id).await;
+    if let Some(token) = extract_session(&headers) {
+        if let Err(e) = state.remove_session(&token).await {
+            tracing::warn!("Failed to remove session on logout: {}", e);
+        }
+    }
+    if let Err(e) = audit.record(AuditAction::Logout, None, None).await {
+        tracing::warn!("Failed to audit logout: {}", e);
     }
     
     let mut response_headers = HeaderMap::new();
     response_headers.insert(
         header::SET_COOKIE,
-        "session=; HttpOnly; Secure; SameSite=Strict; Max-Age=0".parse().unwrap(),
+        "session=; HttpOnly; Secure; SameSite=Strict; Path=/; Max-Age=0".parse().unwrap(),
     );
     
     (response_headers, Json(serde_json::json!({"success": true})))
 }
 
+/// Step-up re-authentication: proves the identity of the session's user
+/// again, allowing sensitive operations for the configured window.
+/// Passkeys use a ceremony from `/auth/login/passkey/start`.
+pub async fn reauthenticate(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    audit: AuditContext,
+    Json(request): Json<ReauthRequest>,
+) -> AppResult<impl IntoResponse> {
+    let client = login_client(&state, connect_info, &headers);
+    throttled(&state, &audit, "reauth", client, async {
+        let Some(passkey) = &request.passkey else {
+            return state.auth_service().reauthenticate(&session.user_id, &request).await;
+        };
+        let user_id = state
+            .login_passkeys
+            .finish_authentication(&passkey.ceremony_id, &passkey.credential)
+            .await?;
+        if user_id != session.user_id {
+            return Err(AppError::AuthenticationFailed("Passkey belongs to another account".to_string()));
+        }
+        Ok(())
+    })
+    .await?;
+    
+    let authenticated_at = state.sessions.mark_authenticated(&session.id).await?;
+    let factor = match (&request.passkey, &request.master_password) {
+        (Some(_), _) => "passkey",
+        (None, Some(_)) => "master_password",
+        (None, None) => "totp",
+    };
+    audit.record(AuditAction::Reauth, None, Some(factor)).await?;
+    let window = chrono::Duration::minutes(state.config.auth.reauth_window_minutes as i64);
+    
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "authenticated_at": authenticated_at,
+        "reauth_expires_at": authenticated_at + window
+    })))
+}
+
+pub async fn change_master_password(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    audit: AuditContext,
+    Json(request): Json<ChangeMasterPasswordRequest>,
+) -> AppResult<impl IntoResponse> {
+    state.auth_service().change_master_password(&session.user_id, request).await?;
+    audit.record(AuditAction::Account, None, Some("master password changed")).await?;
+    
+    // Every session of the user, including this one, must log in with the
+    // new password
+    state.remove_user_sessions(&session.user_id).await?;
+    
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::SET_COOKIE,
+        "session=; HttpOnly; Secure; SameSite=Strict; Path=/; Max-Age=0".parse().unwrap(),
+    );
+    
+    Ok((headers, Json(serde_json::json!({"success": true}))))
+}
+
+pub async fn recovery_codes(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+) -> AppResult<impl IntoResponse> {
+    let remaining = state.credentials.recovery_codes_remaining(&session.user_id).await?;
+    
+    Ok(Json(serde_json::json!({"remaining": remaining})))
+}
+
+pub async fn regenerate_recovery_codes(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    audit: AuditContext,
+) -> AppResult<impl IntoResponse> {
+    // The new codes each log in on their own
+    state.require_recent_auth(&session)?;
+    
+    let codes = state.auth_service().regenerate_recovery_codes(&session.user_id).await?;
+    audit.record(AuditAction::Account, None, Some("recovery codes regenerated")).await?;
+    
+    Ok(Json(serde_json::json!({"codes": codes})))
+}
+
+/// Activates an invited account. Public, since the user has no session
+/// yet; the invitation token authenticates the request.
+pub async fn accept_invite(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Json(request): Json<AcceptInviteRequest>,
+) -> AppResult<impl IntoResponse> {
+    let accepted = state.auth_service().accept_invite(request).await?;
+    audit
+        .record_as(Some(&accepted.user_id), AuditAction::Account, None, Some("invitation accepted"))
+        .await?;
+    
+    // The TOTP entry is committed as the new user
+    let change = StoreChange::Insert(accepted.totp_path.clone());
+    if let Err(e) = state.commit_change_as(change, &accepted.user_id).await {
+        tracing::warn!("Failed to commit TOTP enrollment: {}", e);
+    }
+    state.request_sync();
+    
+    Ok(Json(accepted))
+}
+
+pub async fn login_passkeys(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+) -> AppResult<Json<Vec<LoginPasskey>>> {
+    let passkeys = state.login_passkeys.list(&session.user_id).await?;
+    
+    Ok(Json(passkeys))
+}
+
+pub async fn register_login_passkey_start(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+) -> AppResult<impl IntoResponse> {
+    // A passkey logs in on its own, so only a recent login may add one
+    state.require_recent_auth(&session)?;
+    let start = state.login_passkeys.start_registration(&session.user_id).await?;
+    
+    Ok(Json(start))
+}
+
+pub async fn register_login_passkey_finish(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    audit: AuditContext,
+    Json(request): Json<RegistrationFinish>,
+) -> AppResult<Json<LoginPasskey>> {
+    state.require_recent_auth(&session)?;
+    let passkey = state
+        .login_passkeys
+        .finish_registration(&session.user_id, &request.ceremony_id, &request.name, &request.credential)
+        .await?;
+    audit.record(AuditAction::Account, Some(&passkey.id), Some("login passkey registered")).await?;
+    
+    Ok(Json(passkey))
+}
+
+pub async fn delete_login_passkey(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    audit: AuditContext,
+    Path(id): Path<String>,
+) -> AppResult<Json<serde_json::Value>> {
+    state.require_recent_auth(&session)?;
+    state.login_passkeys.delete(&session.user_id, &id).await?;
+    audit.record(AuditAction::Account, Some(&id), Some("login passkey deleted")).await?;
+    
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "deleted": id
+    })))
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionInfo {
+    #[serde(flatten)]
+    pub session: Session,
+    /// Whether this is the session making the request.
+    pub current: bool,
+}
+
+pub async fn sessions(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+) -> AppResult<Json<Vec<SessionInfo>>> {
+    let sessions = state
+        .sessions
+        .list(&session.user_id)
+        .await?
+        .into_iter()
+        .map(|other| SessionInfo {
+            current: other.id == session.id,
+            session: other,
+        })
+        .collect();
+    
+    Ok(Json(sessions))
+}
+
+pub async fn revoke_session(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    audit: AuditContext,
+    Path(id): Path<String>,
+) -> AppResult<impl IntoResponse> {
+    state.sessions.revoke(&session.user_id, &id).await?;
+    audit.record(AuditAction::Account, Some(&id), Some("session revoked")).await?;
+    
+    Ok(Json(serde_json::json!({"success": true, "revoked": id})))
+}
+
+/// Ends every other session of the user; logging out ends this one.
+pub async fn revoke_other_sessions(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    audit: AuditContext,
+) -> AppResult<impl IntoResponse> {
+    let revoked = state.sessions.revoke_user(&session.user_id, Some(&session.id)).await?;
+    audit.record(AuditAction::Account, None, Some("other sessions revoked")).await?;
+    
+    Ok(Json(serde_json::json!({"success": true, "revoked": revoked})))
+}
+
+pub async fn api_tokens(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+) -> AppResult<Json<Vec<ApiToken>>> {
+    let tokens = state.api_tokens.list(&session.user_id).await?;
+    
+    Ok(Json(tokens))
+}
+
+/// Creates a personal access token. The token is shown only in this
+/// response.
+pub async fn create_api_token(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    audit: AuditContext,
+    Json(request): Json<CreateTokenRequest>,
+) -> AppResult<impl IntoResponse> {
+    state.require_recent_auth(&session)?;
+    let (token, api_token) = state.api_tokens.create(&session.user_id, request).await?;
+    tracing::info!("API token {} created by {}", api_token.id, session.user_id);
+    audit.record(AuditAction::Account, Some(&api_token.id), Some("API token created")).await?;
+    
+    Ok(Json(serde_json::json!({"token": token, "api_token": api_token})))
+}
+
+pub async fn revoke_api_token(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    audit: AuditContext,
+    Path(id): Path<String>,
+) -> AppResult<impl IntoResponse> {
+    state.api_tokens.revoke(&session.user_id, &id).await?;
+    audit.record(AuditAction::Account, Some(&id), Some("API token revoked")).await?;
+    
+    Ok(Json(serde_json::json!({"success": true, "revoked": id})))
+}
+
 fn extract_session(headers: &HeaderMap) -> Option<String> {
     // Try to get session from cookie first
-    if let Some(cookie_header) = headers.get(header::COOKIE) {
-        if let Ok(cookie_str) = cookie_header.to_str() {
-            for cookie in cookie_str.split(';') {
-                let cookie = cookie.trim();
-                if let Some(session_value) = cookie.strip_prefix("session=") {
-                    return Some(session_value.to_string());
-                }
-            }
-        }
+    if let Some(session) = cookie(headers, "session") {
+        return Some(session);
     }
     
     // Fall back to Authorization header
@@ -95,4 +572,60 @@ fn extract_session(headers: &HeaderMap) -> Option<String> {
     }
     
     None
-}
\ No newline at end of file
+}
+
+fn cookie(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .find_map(|cookie| cookie.trim().strip_prefix(name)?.strip_prefix('='))
+        .map(str::to_string)
+}
+#[cfg(test)]
+mod tests {
+    use crate::state::tests::{create_test_app_state_with, TestBrowser};
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_login_passkey_enrollment_needs_recent_auth() {
+        let (state, _temp_dir) = create_test_app_state_with(|_| {}).await.unwrap();
+        let browser = TestBrowser::login(&state, "user").await;
+        browser.request(Method::POST, "/api/auth/passkeys/register/start").await.assert_status_ok();
+
+        // Once the window has passed, a session cookie alone cannot enroll
+        let (state, _temp_dir) =
+            create_test_app_state_with(|config| config.auth.reauth_window_minutes = 0).await.unwrap();
+        let browser = TestBrowser::login(&state, "user").await;
+        let finish = json!({
+            "ceremony_id": "unknown",
+            "credential": {
+                "id": "AAAA",
+                "rawId": "AAAA",
+                "response": {"attestationObject": "AAAA", "clientDataJSON": "AAAA"},
+                "type": "public-key",
+                "extensions": {},
+            },
+        });
+        for response in [
+            browser.request(Method::POST, "/api/auth/passkeys/register/start").await,
+            browser.request(Method::POST, "/api/auth/passkeys/register/finish").json(&finish).await,
+        ] {
+            response.assert_status(StatusCode::FORBIDDEN);
+            assert_eq!(response.json::<serde_json::Value>()["code"], "reauth_required");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_recovery_code_regeneration_needs_recent_auth() {
+        let (state, _temp_dir) =
+            create_test_app_state_with(|config| config.auth.reauth_window_minutes = 0).await.unwrap();
+        let browser = TestBrowser::login(&state, "user").await;
+
+        let response = browser.request(Method::POST, "/api/auth/recovery-codes").await;
+        response.assert_status(StatusCode::FORBIDDEN);
+        assert_eq!(response.json::<serde_json::Value>()["code"], "reauth_required");
+    }
+}
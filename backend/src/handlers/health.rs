@@ -1,11 +1,56 @@
 use axum::{extract::State, Json};
 use serde_json::{json, Value};
-use crate::state::AppState;
+use crate::{integrity::Severity, state::AppState};
+
+/// Public liveness check. Details that could reveal remote URLs or the
+/// state of the store stay behind authentication, in `/api/sync/status`
+/// and `/api/store/check`; only the counts of the last store check are
+/// shown here, for monitoring.
+pub async fn check(State(state): State<AppState>) -> Json<Value> {
+    let sync = state.sync_status();
+    let store_check = state.store_check.read().await.as_ref().map(|report| json!({
+        "checked_at": report.checked_at,
+        "critical": report.count(Severity::Critical),
+        "warnings": report.count(Severity::Warning)
+    }));
+
+    // Degraded: serving from the local store while the remote is unreachable
+    let status = if sync.error.is_some() { "degraded" } else { "healthy" };
 
-pub async fn check(State(_state): State<AppState>) -> Json<Value> {
     Json(json!({
-        "status": "healthy",
+        "status": status,
         "timestamp": chrono::Utc::now(),
-        "service": "kagikanri"
+        "service": "kagikanri",
+        "store_check": store_check
     }))
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        integrity::{CheckKind, Finding, IntegrityReport},
+        state::tests::create_test_app_state_with,
+    };
+
+    #[tokio::test]
+    async fn test_health_reports_store_check_counts_without_paths() {
+        let (state, _temp_dir) = create_test_app_state_with(|_| {}).await.unwrap();
+        let Json(health) = check(State(state.clone())).await;
+        assert!(health["store_check"].is_null());
+
+        *state.store_check.write().await = Some(IntegrityReport::new(vec![Finding {
+            check: CheckKind::PlaintextLeak,
+            severity: Severity::Critical,
+            path: Some("leak.txt".to_string()),
+            message: "Unencrypted file looks like a secret".to_string(),
+            suggestion: "Encrypt it with pass".to_string(),
+        }]));
+
+        let Json(health) = check(State(state)).await;
+        let store_check = health["store_check"].as_object().unwrap();
+        assert!(store_check.contains_key("checked_at"));
+        assert_eq!(store_check["critical"], 1);
+        assert!(!health.to_string().contains("leak.txt"));
+    }
+}
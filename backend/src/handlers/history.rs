@@ -0,0 +1,102 @@
+use axum::{
+    extract::{Path, Query, State},
+    response::IntoResponse,
+    Extension, Json,
+};
+use serde::Deserialize;
+use crate::{
+    api_tokens::ApiToken,
+    audit::{AuditAction, AuditContext},
+    error::ApiResponse,
+    history::HistoryQuery,
+    state::{AppState, Session},
+};
+
+pub async fn list(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    Query(query): Query<HistoryQuery>,
+) -> impl IntoResponse {
+    ApiResponse::from(async move {
+        state.require_admin(&session).await?;
+        
+        let page = state.with_repository(move |git_sync| git_sync.history(&query)).await?;
+        
+        Ok(Json(page))
+    }.await)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RollbackRequest {
+    pub commit: String,
+}
+
+pub async fn preview_revert(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    Path(commit): Path<String>,
+) -> impl IntoResponse {
+    ApiResponse::from(async move {
+        state.require_admin(&session).await?;
+        
+        let changes = state
+            .with_repository(move |git_sync| git_sync.preview_revert(&commit))
+            .await?;
+        
+        Ok(Json(changes))
+    }.await)
+}
+
+pub async fn revert(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    api_token: Option<Extension<ApiToken>>,
+    audit: AuditContext,
+    Path(commit): Path<String>,
+) -> impl IntoResponse {
+    ApiResponse::from(async move {
+        state.require_admin(&session).await?;
+        state.require_interactive_auth(&session, api_token.as_deref())?;
+        
+        let restore = state.revert_commit(&commit, &session).await?;
+        audit.record(AuditAction::Admin, Some(&commit), Some("commit reverted")).await?;
+        state.request_sync();
+        
+        Ok(Json(restore))
+    }.await)
+}
+
+pub async fn preview_rollback(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    Query(request): Query<RollbackRequest>,
+) -> impl IntoResponse {
+    ApiResponse::from(async move {
+        state.require_admin(&session).await?;
+        
+        let changes = state
+            .with_repository(move |git_sync| git_sync.preview_rollback(&request.commit))
+            .await?;
+        
+        Ok(Json(changes))
+    }.await)
+}
+
+pub async fn rollback(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    api_token: Option<Extension<ApiToken>>,
+    audit: AuditContext,
+    Json(request): Json<RollbackRequest>,
+) -> impl IntoResponse {
+    ApiResponse::from(async move {
+        state.require_admin(&session).await?;
+        state.require_interactive_auth(&session, api_token.as_deref())?;
+        
+        let restore = state.rollback_to(&request.commit, &session).await?;
+        audit.record(AuditAction::Admin, Some(&request.commit), Some("history rolled back")).await?;
+        state.request_sync();
+        
+        Ok(Json(restore))
+    }.await)
+}
@@ -0,0 +1,46 @@
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::HeaderMap,
+    response::IntoResponse,
+    Json,
+};
+use tracing::info;
+use crate::{
+    audit::AuditContext,
+    error::{ApiResponse, AppError},
+    state::AppState,
+    webhook,
+};
+
+pub async fn git(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    audit: AuditContext,
+    body: Bytes,
+) -> impl IntoResponse {
+    ApiResponse::from(async move {
+        let Some(secret) = &state.config.git.webhook_secret else {
+            return Err(AppError::NotFound("Git webhooks are not enabled".to_string()));
+        };
+        
+        // Verify before charging the limiter, so forged deliveries cannot
+        // use up the budget of the real git host
+        let source = webhook::verify_request(&headers, &body, secret)?;
+        if !state.webhook_limiter.lock().unwrap().check() {
+            return Err(AppError::RateLimited("Too many webhook deliveries".to_string()));
+        }
+        
+        let push = webhook::is_push_event(&headers, source);
+        if push {
+            info!("Received push webhook from {:?}, requesting sync", source);
+            state.sync.request_now("webhook", audit.event())?;
+        }
+        
+        Ok(Json(serde_json::json!({
+            "success": true,
+            "source": source,
+            "sync_requested": push,
+        })))
+    }.await)
+}
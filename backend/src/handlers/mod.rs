@@ -1,6 +1,11 @@
+pub mod admin;
+pub mod audit;
 pub mod auth;
 pub mod health;
+pub mod history;
+pub mod hooks;
 pub mod otp;
 pub mod passkeys;
 pub mod passwords;
+pub mod store;
 pub mod sync;
\ No newline at end of file
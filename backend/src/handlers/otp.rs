@@ -1,12 +1,14 @@
 use axum::{
     extract::{Path, State},
     response::IntoResponse,
-    Json,
+    Extension, Json,
 };
 use serde::{Deserialize, Serialize};
 use crate::{
-    error::{ApiResponse, AppResult},
-    state::AppState,
+    audit::{AuditAction, AuditContext},
+    error::ApiResponse,
+    git::StoreChange,
+    state::{AppState, Session},
 };
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -22,10 +24,15 @@ pub struct OtpCreateRequest {
 
 pub async fn get(
     State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    audit: AuditContext,
     Path(path): Path<String>,
 ) -> impl IntoResponse {
     ApiResponse::from(async move {
-        let code = state.pass.get_otp(&path).await?;
+        state.require_recent_auth(&session)?;
+        let store_path = state.current_user(&session).await?.store_path(&path)?;
+        let code = state.pass.get_otp(&store_path).await?;
+        audit.record(AuditAction::Reveal, Some(&store_path), Some("otp")).await?;
         
         // Calculate expires_in (OTP codes typically refresh every 30 seconds)
         let current_time = std::time::SystemTime::now()
@@ -43,21 +50,45 @@ pub async fn get(
 
 pub async fn create(
     State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    audit: AuditContext,
     Path(path): Path<String>,
     Json(request): Json<OtpCreateRequest>,
 ) -> impl IntoResponse {
     ApiResponse::from(async move {
-        state.pass.create_otp(&path, &request.secret).await?;
+        let store_path = state.current_user(&session).await?.store_path(&path)?;
+        // Recorded first, so no change goes unaudited
+        audit.record(AuditAction::Write, Some(&store_path), Some("otp")).await?;
+        state.pass.create_otp(&store_path, &request.secret).await?;
         
-        // Trigger git sync after OTP creation
-        if let Err(e) = state.sync_git().await {
-            tracing::warn!("Failed to sync git after OTP creation: {}", e);
+        if let Err(e) = state.commit_change(StoreChange::InsertOtp(store_path), &session).await {
+            tracing::warn!("Failed to commit OTP creation: {}", e);
         }
         
+        // Push in the background so the write succeeds while offline
+        state.request_sync();
+        
         Ok(Json(serde_json::json!({
             "success": true,
             "path": path,
             "message": "OTP secret added successfully"
         })))
     }.await)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::state::tests::{create_test_app_state_with, TestBrowser};
+    use axum::http::{Method, StatusCode};
+
+    #[tokio::test]
+    async fn test_revealing_codes_needs_recent_auth() {
+        let (state, _temp_dir) =
+            create_test_app_state_with(|config| config.auth.reauth_window_minutes = 0).await.unwrap();
+        let browser = TestBrowser::login(&state, "user").await;
+
+        let response = browser.request(Method::GET, "/api/otp/github.com").await;
+        response.assert_status(StatusCode::FORBIDDEN);
+        assert_eq!(response.json::<serde_json::Value>()["code"], "reauth_required");
+    }
+}
@@ -1,52 +1,117 @@
 use axum::{
     extract::{Path, State},
-    Json,
+    Extension, Json,
 };
 use crate::{
+    audit::{AuditAction, AuditContext},
     error::{AppError, AppResult},
     passkey::{PasskeyRegistrationFinish, PasskeyRegistrationStart, StoredPasskey},
-    state::AppState,
+    state::{AppState, Session},
 };
 
+/// Whose passkeys a session works with: its own, or everyone's for admins.
+async fn passkey_owner(state: &AppState, session: &Session) -> AppResult<Option<String>> {
+    let user = state.current_user(session).await?;
+    Ok((!user.is_admin()).then_some(user.id))
+}
+
 pub async fn list(
     State(state): State<AppState>,
+    Extension(session): Extension<Session>,
 ) -> AppResult<Json<Vec<StoredPasskey>>> {
-    let passkeys = state.passkey_store.list_passkeys().await?;
+    let owner = passkey_owner(&state, &session).await?;
+    let passkeys = state.passkey_store.list_passkeys(owner.as_deref()).await?;
     Ok(Json(passkeys))
 }
 
 pub async fn register_start(
     State(state): State<AppState>,
+    Extension(session): Extension<Session>,
     Json(request): Json<serde_json::Value>,
 ) -> AppResult<Json<PasskeyRegistrationStart>> {
     let domain = request["domain"]
         .as_str()
         .ok_or_else(|| AppError::ValidationError("Domain is required".to_string()))?;
     
-    let user_id = request["user_id"]
-        .as_str()
-        .ok_or_else(|| AppError::ValidationError("User ID is required".to_string()))?;
-    
-    let registration = state.passkey_store.start_registration(domain, user_id).await?;
+    let registration = state.passkey_store.start_registration(domain, &session.user_id).await?;
     Ok(Json(registration))
 }
 
 pub async fn register_finish(
     State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    audit: AuditContext,
     Json(request): Json<PasskeyRegistrationFinish>,
 ) -> AppResult<Json<StoredPasskey>> {
-    let passkey = state.passkey_store.finish_registration(request).await?;
+    let passkey = state.passkey_store.finish_registration(&session.user_id, request).await?;
+    audit.record(AuditAction::Write, Some(&passkey.domain), Some("passkey")).await?;
     Ok(Json(passkey))
 }
 
 pub async fn delete(
     State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    audit: AuditContext,
     Path(id): Path<String>,
 ) -> AppResult<Json<serde_json::Value>> {
-    state.passkey_store.delete_passkey(&id).await?;
+    state.require_recent_auth(&session)?;
+    let owner = passkey_owner(&state, &session).await?;
+    state.passkey_store.delete_passkey(&id, owner.as_deref()).await?;
+    audit.record(AuditAction::Delete, Some(&id), Some("passkey")).await?;
     
     Ok(Json(serde_json::json!({
         "success": true,
         "deleted": id
     })))
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        state::tests::{create_test_app_state_with, TestBrowser},
+        users::{Role, BOOTSTRAP_ADMIN},
+    };
+    use axum::http::{Method, StatusCode};
+    use serde_json::{json, Value};
+
+    #[tokio::test]
+    async fn test_members_only_see_and_delete_their_own_passkeys() {
+        let (state, _temp_dir) = create_test_app_state_with(|_| {}).await.unwrap();
+        for user_id in ["alice", "bob"] {
+            state.users.provision(user_id, None, Role::Member, None).await.unwrap();
+        }
+        let alice = TestBrowser::login(&state, "alice").await;
+        let bob = TestBrowser::login(&state, "bob").await;
+        let admin = TestBrowser::login(&state, BOOTSTRAP_ADMIN).await;
+        let ids = |response: axum_test::TestResponse| -> Vec<String> {
+            let passkeys: Vec<Value> = response.json();
+            passkeys.iter().map(|passkey| passkey["id"].as_str().unwrap().to_string()).collect()
+        };
+
+        // Registrations belong to the session's user, whatever the body says
+        let start = bob
+            .request(Method::POST, "/api/passkeys/register/start")
+            .json(&json!({"domain": "example.com", "user_id": "alice"}))
+            .await;
+        assert_eq!(start.json::<Value>()["user_id"], "bob");
+
+        let passkey: Value = alice
+            .request(Method::POST, "/api/passkeys/register/finish")
+            .json(&json!({"challenge": "challenge", "response": "{}"}))
+            .await
+            .json();
+        let id = passkey["id"].as_str().unwrap().to_string();
+        assert_eq!(passkey["user_id"], "alice");
+
+        assert!(ids(bob.request(Method::GET, "/api/passkeys").await).is_empty());
+        bob.request(Method::DELETE, &format!("/api/passkeys/{}", id))
+            .await
+            .assert_status(StatusCode::NOT_FOUND);
+        assert_eq!(ids(alice.request(Method::GET, "/api/passkeys").await), vec![id.clone()]);
+
+        // Admins manage everyone's
+        assert_eq!(ids(admin.request(Method::GET, "/api/passkeys").await), vec![id.clone()]);
+        admin.request(Method::DELETE, &format!("/api/passkeys/{}", id)).await.assert_status_ok();
+        assert!(ids(alice.request(Method::GET, "/api/passkeys").await).is_empty());
+    }
+}
@@ -1,29 +1,52 @@
 use axum::{
     extract::{Path, State},
     response::IntoResponse,
-    Json,
+    Extension, Json,
 };
 use crate::{
-    error::{ApiResponse, AppResult},
+    api_tokens::ApiToken,
+    audit::{AuditAction, AuditContext},
+    error::ApiResponse,
+    git::StoreChange,
     pass::{PasswordEntry, PasswordList},
-    state::AppState,
+    state::{AppState, Session},
 };
 
 pub async fn list(
     State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    api_token: Option<Extension<ApiToken>>,
 ) -> impl IntoResponse {
     ApiResponse::from(async move {
-        let passwords = state.pass.list_passwords().await?;
+        // Members only see their home folder, tokens only their folders
+        let user = state.current_user(&session).await?;
+        let folders = api_token.map(|Extension(token)| token.folders).unwrap_or_default();
+        let passwords = if !folders.is_empty() {
+            let mut entries = Vec::new();
+            for folder in &folders {
+                entries.extend(state.pass.list_folder(&user.store_path(folder)?).await?.entries);
+            }
+            PasswordList { entries }
+        } else if user.is_admin() {
+            state.pass.list_passwords().await?
+        } else {
+            state.pass.list_folder(&user.home_dir()).await?
+        };
         Ok(Json(passwords))
     }.await)
 }
 
 pub async fn get(
     State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    audit: AuditContext,
     Path(path): Path<String>,
 ) -> impl IntoResponse {
     ApiResponse::from(async move {
-        let password = state.pass.get_password(&path).await?;
+        state.require_recent_auth(&session)?;
+        let store_path = state.current_user(&session).await?.store_path(&path)?;
+        let password = state.pass.get_password(&store_path).await?;
+        audit.record(AuditAction::Reveal, Some(&store_path), None).await?;
         Ok(Json(password))
     }.await)
 }
@@ -31,33 +54,48 @@ pub async fn get(
 #[axum::debug_handler]
 pub async fn create_or_update(
     State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    audit: AuditContext,
     Path(path): Path<String>,
     Json(entry): Json<PasswordEntry>,
 ) -> impl IntoResponse {
     ApiResponse::from(async move {
-        state.pass.create_or_update_password(&path, &entry).await?;
+        let store_path = state.current_user(&session).await?.store_path(&path)?;
+        // Recorded first, so no change goes unaudited
+        audit.record(AuditAction::Write, Some(&store_path), None).await?;
+        state.pass.create_or_update_password(&store_path, &entry).await?;
         
-        // Trigger git sync after password change
-        if let Err(e) = state.sync_git().await {
-            tracing::warn!("Failed to sync git after password update: {}", e);
+        if let Err(e) = state.commit_change(StoreChange::Insert(store_path), &session).await {
+            tracing::warn!("Failed to commit password update: {}", e);
         }
         
+        // Push in the background so the write succeeds while offline
+        state.request_sync();
+        
         Ok(Json(serde_json::json!({"success": true, "path": path})))
     }.await)
 }
 
 pub async fn delete(
     State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    audit: AuditContext,
     Path(path): Path<String>,
 ) -> impl IntoResponse {
     ApiResponse::from(async move {
-        state.pass.delete_password(&path).await?;
+        state.require_recent_auth(&session)?;
+        let store_path = state.current_user(&session).await?.store_path(&path)?;
+        // Recorded first, so no change goes unaudited
+        audit.record(AuditAction::Delete, Some(&store_path), None).await?;
+        state.pass.delete_password(&store_path).await?;
         
-        // Trigger git sync after password deletion
-        if let Err(e) = state.sync_git().await {
-            tracing::warn!("Failed to sync git after password deletion: {}", e);
+        if let Err(e) = state.commit_change(StoreChange::Remove(store_path), &session).await {
+            tracing::warn!("Failed to commit password deletion: {}", e);
         }
         
+        // Push in the background so the write succeeds while offline
+        state.request_sync();
+        
         Ok(Json(serde_json::json!({"success": true, "deleted": path})))
     }.await)
 }
\ No newline at end of file
@@ -0,0 +1,47 @@
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Extension, Json,
+};
+use serde::Deserialize;
+use serde_json::json;
+use crate::{
+    error::{ApiResponse, AppError},
+    integrity::Severity,
+    state::{AppState, Session},
+};
+
+#[derive(Debug, Deserialize)]
+pub struct StoreCheckQuery {
+    /// Return the result of the last scheduled check instead of running one.
+    #[serde(default)]
+    pub latest: bool,
+}
+
+pub async fn check(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    Query(query): Query<StoreCheckQuery>,
+) -> impl IntoResponse {
+    ApiResponse::from(async move {
+        state.require_admin(&session).await?;
+        
+        let report = if query.latest {
+            state
+                .store_check
+                .read()
+                .await
+                .clone()
+                .ok_or_else(|| AppError::NotFound("The store has not been checked yet".to_string()))?
+        } else {
+            state.run_store_check().await?
+        };
+        
+        Ok(Json(json!({
+            "checked_at": report.checked_at,
+            "critical": report.count(Severity::Critical),
+            "warnings": report.count(Severity::Warning),
+            "findings": report.findings
+        })))
+    }.await)
+}
@@ -1,33 +1,75 @@
 use axum::{
-    extract::State,
+    extract::{Query, State},
     response::IntoResponse,
-    Json,
+    Extension, Json,
 };
+use serde::Deserialize;
 use crate::{
-    error::{ApiResponse, AppResult},
-    git::SyncStatus,
-    state::AppState,
+    audit::AuditContext,
+    error::ApiResponse,
+    state::{AppState, Session},
 };
 
 pub async fn trigger(
     State(state): State<AppState>,
+    audit: AuditContext,
 ) -> impl IntoResponse {
     ApiResponse::from(async move {
-        // Perform git sync
-        let mut git_sync = state.git_sync.write().await;
-        let status = git_sync.sync().await?;
+        // Runs on the sync actor, which audits it; only this request waits
+        // for the result
+        state.sync.sync_now("manual", audit.event()).await?;
         
-        Ok(Json(status))
+        Ok(Json(state.sync_status()))
     }.await)
 }
 
 pub async fn status(
     State(state): State<AppState>,
+) -> impl IntoResponse {
+    Json(state.sync_status())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SignatureReportQuery {
+    pub limit: Option<usize>,
+}
+
+pub async fn signatures(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    Query(query): Query<SignatureReportQuery>,
 ) -> impl IntoResponse {
     ApiResponse::from(async move {
-        let git_sync = state.git_sync.read().await;
-        let status = git_sync.get_status();
+        // Lists commit authors and paths of the whole store
+        state.require_admin(&session).await?;
         
-        Ok(Json(status))
+        let limit = query.limit.unwrap_or(50).min(500);
+        // One gpg call per commit, so keep it off the async runtime
+        let report = state
+            .with_repository(move |git_sync| git_sync.signature_report(limit))
+            .await?;
+        
+        Ok(Json(report))
     }.await)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        state::tests::{create_test_app_state_with, TestBrowser},
+        users::Role,
+    };
+    use axum::http::{Method, StatusCode};
+
+    #[tokio::test]
+    async fn test_signature_report_is_admin_only() {
+        let (state, _temp_dir) = create_test_app_state_with(|_| {}).await.unwrap();
+        state.users.provision("alice", None, Role::Member, None).await.unwrap();
+        let alice = TestBrowser::login(&state, "alice").await;
+
+        alice
+            .request(Method::GET, "/api/sync/signatures")
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,517 @@
+use crate::error::{AppError, AppResult};
+use chrono::{DateTime, Utc};
+use git2::{Commit, Delta, DiffFindOptions, ErrorCode, Oid, Repository, Sort, Tree};
+use serde::{Deserialize, Serialize};
+
+/// Trailer naming the Kagikanri user a store commit was made by.
+pub const USER_TRAILER: &str = "Kagikanri-User";
+
+const DEFAULT_PER_PAGE: usize = 50;
+const MAX_PER_PAGE: usize = 200;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct HistoryQuery {
+    /// Only commits touching entries under this folder.
+    pub folder: Option<String>,
+    /// Case-insensitive match against author name, email or user ID.
+    pub author: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    /// 1-based page number.
+    pub page: Option<usize>,
+    pub per_page: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryPage {
+    pub commits: Vec<HistoryCommit>,
+    pub page: usize,
+    pub per_page: usize,
+    pub has_more: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryCommit {
+    pub id: String,
+    pub author_name: String,
+    pub author_email: String,
+    /// Kagikanri user from the commit's trailer, if it was made through it.
+    pub user_id: Option<String>,
+    pub date: DateTime<Utc>,
+    pub message: String,
+    pub changes: Vec<EntryChange>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Added,
+    Modified,
+    Deleted,
+    Renamed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntryChange {
+    pub kind: ChangeKind,
+    /// Entry path as pass shows it, without the `.gpg` extension.
+    pub path: String,
+    /// Previous path of a renamed entry.
+    pub old_path: Option<String>,
+}
+
+/// Outcome of a revert or rollback. `commit` is `None` when the store
+/// already matched the requested state.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Restore {
+    pub commit: Option<String>,
+    pub changes: Vec<EntryChange>,
+}
+
+/// Walks history from HEAD, newest first, and returns one page of commits
+/// matching the query along with the entries each of them changed.
+pub fn list_commits(repo: &Repository, query: &HistoryQuery) -> AppResult<HistoryPage> {
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query
+        .per_page
+        .unwrap_or(DEFAULT_PER_PAGE)
+        .clamp(1, MAX_PER_PAGE);
+    let skip = (page - 1) * per_page;
+
+    let mut commits = Vec::new();
+    let mut has_more = false;
+
+    if repo.head().is_ok() {
+        let mut revwalk = repo.revwalk()?;
+        revwalk.set_sorting(Sort::TIME)?;
+        revwalk.push_head()?;
+
+        let mut matched = 0;
+        for oid in revwalk {
+            let commit = repo.find_commit(oid?)?;
+            let Some(entry) = describe_commit(repo, &commit, query)? else {
+                continue;
+            };
+
+            matched += 1;
+            if matched <= skip {
+                continue;
+            }
+            if commits.len() == per_page {
+                has_more = true;
+                break;
+            }
+            commits.push(entry);
+        }
+    }
+
+    Ok(HistoryPage {
+        commits,
+        page,
+        per_page,
+        has_more,
+    })
+}
+
+/// Builds the history record for `commit`, or `None` if the query filters it out.
+fn describe_commit(
+    repo: &Repository,
+    commit: &Commit,
+    query: &HistoryQuery,
+) -> AppResult<Option<HistoryCommit>> {
+    let author = commit.author();
+    let author_name = author.name().unwrap_or_default().to_string();
+    let author_email = author.email().unwrap_or_default().to_string();
+    let message = commit.message().unwrap_or_default().trim_end().to_string();
+    let user_id = commit_user(&message);
+    let date = DateTime::from_timestamp(commit.time().seconds(), 0).unwrap_or_default();
+
+    if query.since.is_some_and(|since| date < since)
+        || query.until.is_some_and(|until| date > until)
+    {
+        return Ok(None);
+    }
+    if let Some(wanted) = &query.author {
+        let wanted = wanted.to_lowercase();
+        if !author_name.to_lowercase().contains(&wanted)
+            && !author_email.to_lowercase().contains(&wanted)
+            && !user_id.as_ref().is_some_and(|user_id| user_id.to_lowercase().contains(&wanted))
+        {
+            return Ok(None);
+        }
+    }
+
+    let mut changes = commit_changes(repo, commit)?;
+    if let Some(folder) = &query.folder {
+        let folder = folder.trim_matches('/');
+        changes.retain(|change| {
+            is_in_folder(&change.path, folder)
+                || change
+                    .old_path
+                    .as_deref()
+                    .is_some_and(|old| is_in_folder(old, folder))
+        });
+        if changes.is_empty() {
+            return Ok(None);
+        }
+    }
+
+    Ok(Some(HistoryCommit {
+        id: commit.id().to_string(),
+        author_name,
+        author_email,
+        user_id,
+        date,
+        message,
+        changes,
+    }))
+}
+
+/// User named by the last trailer paragraph of `message`.
+fn commit_user(message: &str) -> Option<String> {
+    let trailers = message.rsplit("\n\n").next()?;
+    trailers.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        (key == USER_TRAILER).then(|| value.trim().to_string())
+    })
+}
+
+/// Entries changed by `commit` relative to its first parent, with renames detected.
+pub fn commit_changes(repo: &Repository, commit: &Commit) -> AppResult<Vec<EntryChange>> {
+    let parent_tree = match commit.parents().next() {
+        Some(parent) => Some(parent.tree()?),
+        None => None,
+    };
+
+    diff_trees(repo, parent_tree.as_ref(), &commit.tree()?)
+}
+
+/// Entries that differ between two trees, with renames detected.
+pub fn diff_trees(
+    repo: &Repository,
+    old: Option<&Tree>,
+    new: &Tree,
+) -> AppResult<Vec<EntryChange>> {
+    let mut diff = repo.diff_tree_to_tree(old, Some(new), None)?;
+    diff.find_similar(Some(DiffFindOptions::new().renames(true)))?;
+
+    let changes = diff
+        .deltas()
+        .filter_map(|delta| {
+            let kind = match delta.status() {
+                Delta::Added | Delta::Copied => ChangeKind::Added,
+                Delta::Modified | Delta::Typechange => ChangeKind::Modified,
+                Delta::Deleted => ChangeKind::Deleted,
+                Delta::Renamed => ChangeKind::Renamed,
+                _ => return None,
+            };
+            let new_path = delta.new_file().path().map(entry_path);
+            let old_path = delta.old_file().path().map(entry_path);
+
+            Some(match kind {
+                ChangeKind::Deleted => EntryChange {
+                    kind,
+                    path: old_path?,
+                    old_path: None,
+                },
+                ChangeKind::Renamed => EntryChange {
+                    kind,
+                    path: new_path?,
+                    old_path,
+                },
+                _ => EntryChange {
+                    kind,
+                    path: new_path?,
+                    old_path: None,
+                },
+            })
+        })
+        .collect();
+
+    Ok(changes)
+}
+
+/// Resolves a commit id (full or abbreviated) that is part of `head`'s history.
+/// Only hex ids are accepted, not revspecs such as branch names or `HEAD~2`.
+pub fn find_commit<'r>(repo: &'r Repository, id: &str, head: &Commit) -> AppResult<Commit<'r>> {
+    if !(4..=40).contains(&id.len()) || !id.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Err(AppError::ValidationError(format!("{} is not a commit id", id)));
+    }
+    let commit = repo.find_commit_by_prefix(id).map_err(|e| match e.code() {
+        ErrorCode::Ambiguous => AppError::ValidationError(format!("Commit id {} is ambiguous", id)),
+        _ => AppError::NotFound(format!("Commit {} not found", id)),
+    })?;
+
+    if commit.id() != head.id() && !repo.graph_descendant_of(head.id(), commit.id())? {
+        return Err(AppError::ValidationError(format!(
+            "Commit {} is not part of the store history",
+            id
+        )));
+    }
+
+    Ok(commit)
+}
+
+/// Tree that results from undoing `commit` on top of `onto`.
+pub fn revert_tree(repo: &Repository, commit: &Commit, onto: &Commit) -> AppResult<Oid> {
+    // Merges are reverted relative to their first parent, like `git revert -m 1`
+    let mainline = if commit.parent_count() > 1 { 1 } else { 0 };
+    let mut index = repo.revert_commit(commit, onto, mainline, None)?;
+
+    if index.has_conflicts() {
+        return Err(AppError::Conflict(format!(
+            "Reverting {} conflicts with later changes",
+            commit.id()
+        )));
+    }
+
+    Ok(index.write_tree_to(repo)?)
+}
+
+/// Maps a store file to the entry name pass shows for it.
+pub fn entry_path(file: &std::path::Path) -> String {
+    let file = file.to_string_lossy();
+    file.strip_suffix(".gpg").unwrap_or(&file).to_string()
+}
+
+fn is_in_folder(path: &str, folder: &str) -> bool {
+    folder.is_empty()
+        || path
+            .strip_prefix(folder)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::{Oid, Signature, Time};
+    use pretty_assertions::assert_eq;
+    use std::{fs, path::Path};
+    use tempfile::TempDir;
+
+    /// Writes or removes store files and commits them with a fixed timestamp.
+    fn commit(
+        repo: &Repository,
+        writes: &[(&str, Option<&str>)],
+        author: &str,
+        seconds: i64,
+        message: &str,
+    ) -> Oid {
+        let workdir = repo.workdir().unwrap().to_path_buf();
+        let mut index = repo.index().unwrap();
+        for (file, content) in writes {
+            let path = workdir.join(file);
+            match content {
+                Some(content) => {
+                    fs::create_dir_all(path.parent().unwrap()).unwrap();
+                    fs::write(&path, content).unwrap();
+                    index.add_path(Path::new(file)).unwrap();
+                }
+                None => {
+                    fs::remove_file(&path).unwrap();
+                    index.remove_path(Path::new(file)).unwrap();
+                }
+            }
+        }
+        index.write().unwrap();
+
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::new(
+            author,
+            &format!("{}@example.com", author.to_lowercase()),
+            &Time::new(seconds, 0),
+        )
+        .unwrap();
+        let parent = repo.head().ok().map(|head| head.peel_to_commit().unwrap());
+        let parents: Vec<&Commit> = parent.iter().collect();
+        repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            message,
+            &tree,
+            &parents,
+        )
+        .unwrap()
+    }
+
+    fn create_test_repo() -> (TempDir, Repository) {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+
+        commit(
+            &repo,
+            &[
+                ("github.com.gpg", Some("github")),
+                ("work/vpn.gpg", Some("vpn secret")),
+            ],
+            "Alice",
+            1_700_000_000,
+            "Initial import",
+        );
+        commit(
+            &repo,
+            &[("work/vpn.gpg", Some("rotated vpn secret"))],
+            "Bob",
+            1_700_000_100,
+            "Add given password for work/vpn to store.",
+        );
+        commit(
+            &repo,
+            &[("github.com.gpg", None)],
+            "Alice",
+            1_700_000_200,
+            "Remove github.com from store.",
+        );
+        (temp_dir, repo)
+    }
+
+    #[test]
+    fn test_list_commits_reports_changes() {
+        let (_temp_dir, repo) = create_test_repo();
+        let page = list_commits(&repo, &HistoryQuery::default()).unwrap();
+
+        assert_eq!(page.commits.len(), 3);
+        assert!(!page.has_more);
+
+        let removal = &page.commits[0];
+        assert_eq!(removal.message, "Remove github.com from store.");
+        assert_eq!(removal.author_name, "Alice");
+        assert_eq!(
+            removal.changes,
+            vec![EntryChange {
+                kind: ChangeKind::Deleted,
+                path: "github.com".to_string(),
+                old_path: None
+            }]
+        );
+
+        assert_eq!(page.commits[1].changes[0].kind, ChangeKind::Modified);
+        assert_eq!(page.commits[2].changes.len(), 2);
+        assert!(page.commits[2]
+            .changes
+            .iter()
+            .all(|change| change.kind == ChangeKind::Added));
+    }
+
+    #[test]
+    fn test_list_commits_detects_renames() {
+        let (_temp_dir, repo) = create_test_repo();
+        commit(
+            &repo,
+            &[
+                ("work/vpn.gpg", None),
+                ("archive/vpn.gpg", Some("rotated vpn secret")),
+            ],
+            "Bob",
+            1_700_000_300,
+            "Rename work/vpn to archive/vpn.",
+        );
+
+        let page = list_commits(
+            &repo,
+            &HistoryQuery {
+                per_page: Some(1),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            page.commits[0].changes,
+            vec![EntryChange {
+                kind: ChangeKind::Renamed,
+                path: "archive/vpn".to_string(),
+                old_path: Some("work/vpn".to_string()),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_list_commits_filters() {
+        let (_temp_dir, repo) = create_test_repo();
+
+        let by_folder = list_commits(
+            &repo,
+            &HistoryQuery {
+                folder: Some("work".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(by_folder.commits.len(), 2);
+        assert!(by_folder
+            .commits
+            .iter()
+            .all(|c| c.changes.iter().all(|change| change.path == "work/vpn")));
+
+        let by_author = list_commits(
+            &repo,
+            &HistoryQuery {
+                author: Some("BOB".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(by_author.commits.len(), 1);
+        assert_eq!(by_author.commits[0].author_email, "bob@example.com");
+
+        let by_date = list_commits(
+            &repo,
+            &HistoryQuery {
+                since: DateTime::from_timestamp(1_700_000_050, 0),
+                until: DateTime::from_timestamp(1_700_000_150, 0),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(by_date.commits.len(), 1);
+        assert_eq!(by_date.commits[0].author_name, "Bob");
+    }
+
+    #[test]
+    fn test_list_commits_pagination() {
+        let (_temp_dir, repo) = create_test_repo();
+
+        let first = list_commits(
+            &repo,
+            &HistoryQuery {
+                per_page: Some(2),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(first.commits.len(), 2);
+        assert!(first.has_more);
+
+        let second = list_commits(
+            &repo,
+            &HistoryQuery {
+                page: Some(2),
+                per_page: Some(2),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(second.commits.len(), 1);
+        assert!(!second.has_more);
+        assert_eq!(second.commits[0].message, "Initial import");
+    }
+
+    #[test]
+    fn test_folder_matching_respects_path_boundaries() {
+        assert!(is_in_folder("work/vpn", "work"));
+        assert!(!is_in_folder("workshop/vpn", "work"));
+        assert!(is_in_folder("anything", ""));
+    }
+
+    #[test]
+    fn test_commit_user_reads_trailer() {
+        assert_eq!(
+            commit_user("Add given password for work/vpn to store.\n\nKagikanri-User: alice"),
+            Some("alice".to_string())
+        );
+        // Only the final paragraph holds trailers
+        assert_eq!(commit_user("Kagikanri-User: alice\n\nImported by hand"), None);
+        assert_eq!(commit_user("Initial import"), None);
+    }
+}
@@ -1,12 +1,31 @@
+pub mod api_tokens;
+pub mod audit;
 pub mod auth;
 pub mod auth_middleware;
+pub mod client_ip;
 pub mod config;
+pub mod credentials;
+pub mod csrf;
 pub mod error;
 pub mod git;
 pub mod handlers;
+pub mod history;
+pub mod integrity;
+pub mod login_passkeys;
+pub mod login_throttle;
+pub mod oidc;
 pub mod pass;
 pub mod passkey;
+pub mod proxy_auth;
+pub mod purge;
+pub mod sessions;
+pub mod signing;
 pub mod state;
+pub mod sync_actor;
+pub mod tls;
+pub mod totp;
+pub mod users;
+pub mod webhook;
 
 // Re-export commonly used items
 pub use config::Config;
@@ -17,11 +36,14 @@ pub use state::AppState;
 use axum::{
     middleware,
     response::IntoResponse,
-    routing::{delete, get, post},
+    routing::{delete, get, post, put},
     Router,
 };
 use tower::ServiceBuilder;
-use tower_http::{cors::CorsLayer, trace::TraceLayer};
+use tower_http::{
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    trace::TraceLayer,
+};
 
 pub fn create_router(state: AppState) -> Router {
     let api_routes = Router::new()
@@ -29,6 +51,24 @@ pub fn create_router(state: AppState) -> Router {
         .route("/auth/login", post(handlers::auth::login))
         .route("/auth/status", get(handlers::auth::status))
         .route("/auth/logout", post(handlers::auth::logout))
+        .route("/auth/reauth", post(handlers::auth::reauthenticate))
+        .route("/auth/sessions", get(handlers::auth::sessions).delete(handlers::auth::revoke_other_sessions))
+        .route("/auth/sessions/:id", delete(handlers::auth::revoke_session))
+        .route("/auth/tokens", get(handlers::auth::api_tokens).post(handlers::auth::create_api_token))
+        .route("/auth/tokens/:id", delete(handlers::auth::revoke_api_token))
+        .route("/auth/master-password", post(handlers::auth::change_master_password))
+        .route("/auth/recovery-codes", get(handlers::auth::recovery_codes)
+            .post(handlers::auth::regenerate_recovery_codes))
+        .route("/auth/invite/accept", post(handlers::auth::accept_invite))
+        .route("/auth/login/passkey/start", post(handlers::auth::passkey_login_start))
+        .route("/auth/login/passkey/finish", post(handlers::auth::passkey_login_finish))
+        .route("/auth/login/oidc/start", get(handlers::auth::oidc_login_start))
+        .route("/auth/login/oidc/callback", get(handlers::auth::oidc_login_callback))
+        .route("/auth/login/oidc/totp", post(handlers::auth::oidc_login_totp))
+        .route("/auth/passkeys", get(handlers::auth::login_passkeys))
+        .route("/auth/passkeys/register/start", post(handlers::auth::register_login_passkey_start))
+        .route("/auth/passkeys/register/finish", post(handlers::auth::register_login_passkey_finish))
+        .route("/auth/passkeys/:id", delete(handlers::auth::delete_login_passkey))
         
         // Password management routes
         .route("/passwords", get(handlers::passwords::list))
@@ -49,10 +89,40 @@ pub fn create_router(state: AppState) -> Router {
         // Sync routes
         .route("/sync", post(handlers::sync::trigger))
         .route("/sync/status", get(handlers::sync::status))
+        .route("/sync/signatures", get(handlers::sync::signatures))
+        
+        // Store integrity
+        .route("/store/check", get(handlers::store::check))
+        
+        // History routes
+        .route("/history", get(handlers::history::list))
+        .route("/history/rollback", post(handlers::history::rollback))
+        .route("/history/rollback/preview", get(handlers::history::preview_rollback))
+        .route("/history/:commit/revert", post(handlers::history::revert))
+        .route("/history/:commit/revert/preview", get(handlers::history::preview_revert))
+        
+        // Admin routes
+        .route("/admin/history/purge", post(handlers::admin::purge))
+        .route("/admin/history/purge/preview", post(handlers::admin::preview_purge))
+        .route("/admin/lockouts", get(handlers::admin::lockouts).delete(handlers::admin::clear_lockouts))
+        .route("/admin/users", get(handlers::admin::users).post(handlers::admin::invite_user))
+        .route("/admin/users/:id", delete(handlers::admin::delete_user))
+        .route("/admin/users/:id/disable", post(handlers::admin::disable_user))
+        .route("/admin/users/:id/enable", post(handlers::admin::enable_user))
+        .route("/admin/users/:id/oidc", put(handlers::admin::link_oidc).delete(handlers::admin::unlink_oidc))
+        
+        // Audit log routes
+        .route("/audit-log", get(handlers::audit::list))
+        .route("/audit-log/verify", get(handlers::audit::verify))
+        .route("/audit-log/export", get(handlers::audit::export))
         
         // Health check
         .route("/health", get(handlers::health::check))
         .route_layer(middleware::from_fn_with_state(state.clone(), auth_middleware::auth_middleware))
+        
+        // Git host webhooks authenticate with their own signature, so they
+        // are added after the session auth layer
+        .route("/hooks/git", post(handlers::hooks::git))
         .with_state(state.clone());
 
     Router::new()
@@ -66,8 +136,11 @@ pub fn create_router(state: AppState) -> Router {
         .fallback(serve_spa)
         .layer(
             ServiceBuilder::new()
+                .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
                 .layer(TraceLayer::new_for_http())
-                .layer(CorsLayer::permissive())
+                .layer(PropagateRequestIdLayer::x_request_id())
+                .layer(csrf::cors_layer(&state.config))
+                .layer(middleware::from_fn_with_state(state.clone(), csrf::csrf_middleware))
         )
         .with_state(state)
 }
@@ -132,7 +205,7 @@ async fn serve_assets(axum::extract::Path(file): axum::extract::Path<String>) ->
     match std::fs::read(&asset_path) {
         Ok(content) => {
             // Determine content type from file extension
-            let content_type = match file.split('.').last() {
+            let content_type = match file.split('.').next_back() {
                 Some("js") => "application/javascript",
                 Some("css") => "text/css",
                 Some("html") => "text/html",
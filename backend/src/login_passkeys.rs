@@ -0,0 +1,407 @@
+use crate::{
+    config::AuthConfig,
+    error::{AppError, AppResult},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Row, SqlitePool};
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+use webauthn_rs::{prelude::*, Webauthn, WebauthnBuilder};
+
+/// How long a started registration or login can be finished.
+const CEREMONY_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Passkeys for logging into Kagikanri itself, unlike `PasskeyStore`
+/// which keeps passkeys for other sites.
+pub struct LoginPasskeys {
+    webauthn: Webauthn,
+    pool: SqlitePool,
+    /// Ceremony state stays on the server; clients only get an ID.
+    registrations: Mutex<HashMap<String, Pending<PasskeyRegistration>>>,
+    authentications: Mutex<HashMap<String, Pending<PasskeyAuthentication>>>,
+}
+
+struct Pending<T> {
+    user_id: Option<String>,
+    state: T,
+    expires_at: Instant,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LoginPasskey {
+    pub id: String,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+/// First half of a ceremony: options for `navigator.credentials` and the
+/// ID to finish it with.
+#[derive(Debug, Serialize)]
+pub struct CeremonyStart<T> {
+    pub ceremony_id: String,
+    pub options: T,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RegistrationFinish {
+    pub ceremony_id: String,
+    #[serde(default)]
+    pub name: String,
+    pub credential: RegisterPublicKeyCredential,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuthenticationFinish {
+    pub ceremony_id: String,
+    pub credential: PublicKeyCredential,
+    /// Required unless passkeys are configured for passwordless login.
+    pub master_password: Option<String>,
+}
+
+/// A passkey assertion proving the identity of an already logged-in user.
+#[derive(Debug, Deserialize)]
+pub struct PasskeyAssertion {
+    pub ceremony_id: String,
+    pub credential: PublicKeyCredential,
+}
+
+impl LoginPasskeys {
+    pub async fn new(config: &AuthConfig, pool: SqlitePool) -> AppResult<Self> {
+        let origin = url::Url::parse(&config.webauthn_origin)
+            .map_err(|e| AppError::ConfigError(format!("Invalid WEBAUTHN_ORIGIN: {}", e)))?;
+        let webauthn = WebauthnBuilder::new(&config.webauthn_rp_id, &origin)
+            .map_err(|e| AppError::ConfigError(format!("Invalid WebAuthn configuration: {}", e)))?
+            .rp_name("Kagikanri")
+            .build()
+            .map_err(|e| AppError::ConfigError(format!("Failed to initialize WebAuthn: {}", e)))?;
+
+        let passkeys = Self {
+            webauthn,
+            pool,
+            registrations: Mutex::new(HashMap::new()),
+            authentications: Mutex::new(HashMap::new()),
+        };
+        passkeys.init_schema().await?;
+        Ok(passkeys)
+    }
+
+    async fn init_schema(&self) -> AppResult<()> {
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS webauthn_users (
+                user_id TEXT PRIMARY KEY,
+                user_handle TEXT NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS login_passkeys (
+                id TEXT PRIMARY KEY,
+                user_id TEXT NOT NULL,
+                name TEXT NOT NULL,
+                credential_id TEXT NOT NULL UNIQUE,
+                passkey TEXT NOT NULL,
+                created_at TIMESTAMP NOT NULL,
+                last_used_at TIMESTAMP
+            );
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn start_registration(&self, user_id: &str) -> AppResult<CeremonyStart<CreationChallengeResponse>> {
+        let user_handle = self.user_handle(user_id).await?;
+        let existing = self
+            .passkeys(Some(user_id))
+            .await?
+            .into_iter()
+            .map(|(_, passkey)| passkey.cred_id().clone())
+            .collect();
+
+        let (options, state) = self
+            .webauthn
+            .start_passkey_registration(user_handle, user_id, user_id, Some(existing))?;
+
+        let ceremony_id = insert_pending(&self.registrations, Some(user_id), state);
+        Ok(CeremonyStart { ceremony_id, options })
+    }
+
+    pub async fn finish_registration(
+        &self,
+        user_id: &str,
+        ceremony_id: &str,
+        name: &str,
+        credential: &RegisterPublicKeyCredential,
+    ) -> AppResult<LoginPasskey> {
+        let pending = take_pending(&self.registrations, ceremony_id)?;
+        if pending.user_id.as_deref() != Some(user_id) {
+            return Err(AppError::NotFound("Unknown or expired passkey registration".to_string()));
+        }
+
+        let passkey = self
+            .webauthn
+            .finish_passkey_registration(credential, &pending.state)
+            .map_err(|e| AppError::ValidationError(format!("Passkey registration failed: {}", e)))?;
+
+        let name = name.trim();
+        let stored = LoginPasskey {
+            id: Uuid::new_v4().to_string(),
+            name: if name.is_empty() { "Passkey" } else { name }.to_string(),
+            created_at: Utc::now(),
+            last_used_at: None,
+        };
+        sqlx::query(
+            r#"
+            INSERT INTO login_passkeys (id, user_id, name, credential_id, passkey, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+            "#,
+        )
+        .bind(&stored.id)
+        .bind(user_id)
+        .bind(&stored.name)
+        .bind(hex::encode(passkey.cred_id().as_ref()))
+        .bind(serde_json::to_string(&passkey).map_err(|e| AppError::InternalError(e.to_string()))?)
+        .bind(stored.created_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(stored)
+    }
+
+    /// Starts a login with any registered passkey.
+    pub async fn start_authentication(&self) -> AppResult<CeremonyStart<RequestChallengeResponse>> {
+        let passkeys: Vec<Passkey> = self.passkeys(None).await?.into_iter().map(|(_, passkey)| passkey).collect();
+        if passkeys.is_empty() {
+            return Err(AppError::NotFound("No passkeys are registered for login".to_string()));
+        }
+
+        let (options, state) = self.webauthn.start_passkey_authentication(&passkeys)?;
+
+        let ceremony_id = insert_pending(&self.authentications, None, state);
+        Ok(CeremonyStart { ceremony_id, options })
+    }
+
+    /// Verifies the assertion for a started login and returns the user the
+    /// passkey belongs to.
+    pub async fn finish_authentication(&self, ceremony_id: &str, credential: &PublicKeyCredential) -> AppResult<String> {
+        let pending = take_pending(&self.authentications, ceremony_id)
+            .map_err(|_| AppError::AuthenticationFailed("Unknown or expired passkey login".to_string()))?;
+
+        let result = self
+            .webauthn
+            .finish_passkey_authentication(credential, &pending.state)
+            .map_err(|e| AppError::AuthenticationFailed(format!("Passkey login failed: {}", e)))?;
+
+        let row = sqlx::query("SELECT id, user_id, passkey FROM login_passkeys WHERE credential_id = ?1")
+            .bind(hex::encode(result.cred_id().as_ref()))
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or_else(|| AppError::AuthenticationFailed("Passkey is no longer registered".to_string()))?;
+
+        // Record the new signature counter, which lets cloned
+        // authenticators be detected
+        let mut passkey = parse_passkey(row.get("passkey"))?;
+        passkey.update_credential(&result);
+        sqlx::query("UPDATE login_passkeys SET passkey = ?1, last_used_at = ?2 WHERE id = ?3")
+            .bind(serde_json::to_string(&passkey).map_err(|e| AppError::InternalError(e.to_string()))?)
+            .bind(Utc::now())
+            .bind(row.get::<String, _>("id"))
+            .execute(&self.pool)
+            .await?;
+
+        Ok(row.get("user_id"))
+    }
+
+    pub async fn list(&self, user_id: &str) -> AppResult<Vec<LoginPasskey>> {
+        let rows = sqlx::query(
+            "SELECT id, name, created_at, last_used_at FROM login_passkeys WHERE user_id = ?1 ORDER BY created_at",
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| LoginPasskey {
+                id: row.get("id"),
+                name: row.get("name"),
+                created_at: row.get("created_at"),
+                last_used_at: row.get("last_used_at"),
+            })
+            .collect())
+    }
+
+    pub async fn delete(&self, user_id: &str, id: &str) -> AppResult<()> {
+        let result = sqlx::query("DELETE FROM login_passkeys WHERE id = ?1 AND user_id = ?2")
+            .bind(id)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("Passkey not found: {}", id)));
+        }
+        Ok(())
+    }
+
+    /// Removes the passkeys and user handle of a deleted user.
+    pub async fn delete_user(&self, user_id: &str) -> AppResult<()> {
+        let mut transaction = self.pool.begin().await?;
+        for table in ["login_passkeys", "webauthn_users"] {
+            sqlx::query(&format!("DELETE FROM {} WHERE user_id = ?1", table))
+                .bind(user_id)
+                .execute(&mut *transaction)
+                .await?;
+        }
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    /// Stable WebAuthn user handle, created on first use.
+    async fn user_handle(&self, user_id: &str) -> AppResult<Uuid> {
+        sqlx::query("INSERT OR IGNORE INTO webauthn_users (user_id, user_handle) VALUES (?1, ?2)")
+            .bind(user_id)
+            .bind(Uuid::new_v4().to_string())
+            .execute(&self.pool)
+            .await?;
+
+        let row = sqlx::query("SELECT user_handle FROM webauthn_users WHERE user_id = ?1")
+            .bind(user_id)
+            .fetch_one(&self.pool)
+            .await?;
+        Uuid::parse_str(row.get("user_handle"))
+            .map_err(|e| AppError::DatabaseError(format!("Invalid WebAuthn user handle: {}", e)))
+    }
+
+    async fn passkeys(&self, user_id: Option<&str>) -> AppResult<Vec<(String, Passkey)>> {
+        let rows = match user_id {
+            Some(user_id) => {
+                sqlx::query("SELECT user_id, passkey FROM login_passkeys WHERE user_id = ?1")
+                    .bind(user_id)
+                    .fetch_all(&self.pool)
+                    .await?
+            }
+            None => {
+                sqlx::query("SELECT user_id, passkey FROM login_passkeys")
+                    .fetch_all(&self.pool)
+                    .await?
+            }
+        };
+
+        rows.iter()
+            .map(|row| Ok((row.get("user_id"), parse_passkey(row.get("passkey"))?)))
+            .collect()
+    }
+}
+
+fn parse_passkey(json: &str) -> AppResult<Passkey> {
+    serde_json::from_str(json).map_err(|e| AppError::DatabaseError(format!("Invalid stored passkey: {}", e)))
+}
+
+fn insert_pending<T>(pending: &Mutex<HashMap<String, Pending<T>>>, user_id: Option<&str>, state: T) -> String {
+    let ceremony_id = Uuid::new_v4().to_string();
+    let now = Instant::now();
+
+    let mut pending = pending.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    pending.retain(|_, ceremony| ceremony.expires_at > now);
+    pending.insert(
+        ceremony_id.clone(),
+        Pending {
+            user_id: user_id.map(str::to_string),
+            state,
+            expires_at: now + CEREMONY_TIMEOUT,
+        },
+    );
+    ceremony_id
+}
+
+/// Removes a ceremony, so each one can be finished only once.
+fn take_pending<T>(pending: &Mutex<HashMap<String, Pending<T>>>, ceremony_id: &str) -> AppResult<Pending<T>> {
+    let mut pending = pending.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    pending
+        .remove(ceremony_id)
+        .filter(|ceremony| ceremony.expires_at > Instant::now())
+        .ok_or_else(|| AppError::NotFound("Unknown or expired passkey ceremony".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use webauthn_authenticator_rs::{softpasskey::SoftPasskey, WebauthnAuthenticator};
+
+    async fn create_test_passkeys() -> LoginPasskeys {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        LoginPasskeys::new(&AuthConfig::default(), pool).await.unwrap()
+    }
+
+    fn origin() -> url::Url {
+        url::Url::parse(&AuthConfig::default().webauthn_origin).unwrap()
+    }
+
+    async fn register(
+        passkeys: &LoginPasskeys,
+        authenticator: &mut WebauthnAuthenticator<SoftPasskey>,
+        name: &str,
+    ) -> LoginPasskey {
+        let start = passkeys.start_registration("user").await.unwrap();
+        let credential = authenticator.do_registration(origin(), start.options).unwrap();
+        passkeys
+            .finish_registration("user", &start.ceremony_id, name, &credential)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_register_and_log_in_with_software_authenticator() {
+        let passkeys = create_test_passkeys().await;
+        let mut authenticator = WebauthnAuthenticator::new(SoftPasskey::new(true));
+        assert!(matches!(passkeys.start_authentication().await, Err(AppError::NotFound(_))));
+
+        let registered = register(&passkeys, &mut authenticator, "Laptop").await;
+        assert_eq!(registered.name, "Laptop");
+
+        let start = passkeys.start_authentication().await.unwrap();
+        let assertion = authenticator.do_authentication(origin(), start.options).unwrap();
+        let user_id = passkeys.finish_authentication(&start.ceremony_id, &assertion).await.unwrap();
+        assert_eq!(user_id, "user");
+
+        // A ceremony cannot be finished twice
+        assert!(matches!(
+            passkeys.finish_authentication(&start.ceremony_id, &assertion).await,
+            Err(AppError::AuthenticationFailed(_))
+        ));
+
+        let listed = passkeys.list("user").await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert!(listed[0].last_used_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_deleted_passkey_cannot_log_in() {
+        let passkeys = create_test_passkeys().await;
+        let mut authenticator = WebauthnAuthenticator::new(SoftPasskey::new(true));
+        let registered = register(&passkeys, &mut authenticator, "Security key").await;
+
+        let mut other = WebauthnAuthenticator::new(SoftPasskey::new(true));
+        register(&passkeys, &mut other, "Phone").await;
+
+        let start = passkeys.start_authentication().await.unwrap();
+        passkeys.delete("user", &registered.id).await.unwrap();
+        let assertion = authenticator.do_authentication(origin(), start.options).unwrap();
+        assert!(matches!(
+            passkeys.finish_authentication(&start.ceremony_id, &assertion).await,
+            Err(AppError::AuthenticationFailed(_))
+        ));
+
+        assert!(matches!(passkeys.delete("user", &registered.id).await, Err(AppError::NotFound(_))));
+    }
+}
@@ -0,0 +1,378 @@
+use crate::{
+    config::AuthConfig,
+    error::{AppError, AppResult},
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+use std::collections::HashMap;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+/// Client key of the counter shared by all clients.
+pub const GLOBAL_CLIENT: &str = "*";
+
+/// Failures are forgotten after this long without another one.
+const FAILURE_MEMORY_HOURS: i64 = 24;
+/// Upper bound for delays and lockouts.
+const MAX_LOCKOUT_HOURS: i64 = 24;
+
+#[derive(Debug, Clone)]
+pub struct ThrottlePolicy {
+    pub max_failures: u32,
+    pub global_max_failures: u32,
+    pub lockout: Duration,
+}
+
+impl ThrottlePolicy {
+    pub fn from_config(config: &AuthConfig) -> Self {
+        Self {
+            max_failures: config.login_max_failures,
+            global_max_failures: config.login_global_max_failures,
+            lockout: Duration::minutes(config.login_lockout_minutes as i64),
+        }
+    }
+
+    /// How long `client` must wait after its `failures`th failure in a row.
+    /// Single clients wait 1, 2, 4... seconds until they reach the limit;
+    /// the global counter only starts throttling at its limit. From there
+    /// the lockout doubles with every further failure.
+    pub fn delay(&self, client: &str, failures: u32) -> Duration {
+        let max = Duration::hours(MAX_LOCKOUT_HOURS);
+        let limit = self.limit(client);
+
+        if failures >= limit {
+            let doublings = (failures - limit).min(16);
+            return (self.lockout * 2i32.pow(doublings)).min(max);
+        }
+        if client == GLOBAL_CLIENT || failures == 0 {
+            return Duration::zero();
+        }
+        Duration::seconds(1i64 << (failures - 1).min(16)).min(self.lockout).min(max)
+    }
+
+    /// Failures after which `client` is locked out.
+    fn limit(&self, client: &str) -> u32 {
+        if client == GLOBAL_CLIENT {
+            self.global_max_failures
+        } else {
+            self.max_failures
+        }
+    }
+}
+
+/// Failed login counter for one client, or for all of them.
+#[derive(Debug, Clone, Serialize)]
+pub struct LoginFailures {
+    pub client: String,
+    pub failures: u32,
+    pub last_failure: DateTime<Utc>,
+    /// No login from the client is attempted before this time.
+    pub locked_until: DateTime<Utc>,
+}
+
+/// Throttles failed logins per client and globally. Counters live in the
+/// local database, so restarting the server does not reset a lockout.
+#[derive(Debug)]
+pub struct LoginThrottle {
+    pool: SqlitePool,
+    policy: ThrottlePolicy,
+    /// Serializes admitting attempts, not the attempts themselves.
+    admission: Mutex<()>,
+    /// Attempts still running, per client and globally.
+    in_flight: std::sync::Mutex<HashMap<String, u32>>,
+}
+
+/// A login attempt admitted by [`LoginThrottle::begin`]. It counts against
+/// the limits until dropped, which should happen after its failure or
+/// success has been recorded.
+#[derive(Debug)]
+pub struct LoginAttempt<'a> {
+    throttle: &'a LoginThrottle,
+    client: String,
+}
+
+impl Drop for LoginAttempt<'_> {
+    fn drop(&mut self) {
+        let mut in_flight = self.throttle.in_flight();
+        for key in [self.client.as_str(), GLOBAL_CLIENT] {
+            if let Some(pending) = in_flight.get_mut(key) {
+                *pending -= 1;
+                if *pending == 0 {
+                    in_flight.remove(key);
+                }
+            }
+        }
+    }
+}
+
+impl LoginThrottle {
+    pub async fn new(pool: SqlitePool, policy: ThrottlePolicy) -> AppResult<Self> {
+        let throttle = Self {
+            pool,
+            policy,
+            admission: Mutex::new(()),
+            in_flight: std::sync::Mutex::new(HashMap::new()),
+        };
+        throttle.init_schema().await?;
+        Ok(throttle)
+    }
+
+    async fn init_schema(&self) -> AppResult<()> {
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS login_failures (
+                client TEXT PRIMARY KEY,
+                failures INTEGER NOT NULL,
+                last_failure TIMESTAMP NOT NULL,
+                locked_until TIMESTAMP NOT NULL
+            );
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Admits a login attempt from `client`, or fails with `RateLimited`.
+    /// Attempts still running count as failures until they finish, so
+    /// parallel guesses cannot get past the limits while slow ones, such as
+    /// a token exchange with the identity provider, do not hold up others.
+    pub async fn begin(&self, client: &str) -> AppResult<LoginAttempt<'_>> {
+        let _admission = self.admission.lock().await;
+        self.check(client).await?;
+
+        let keys = [client, GLOBAL_CLIENT];
+        let mut failures = Vec::with_capacity(keys.len());
+        for key in keys {
+            failures.push(self.recent_failures(key, Utc::now()).await?);
+        }
+
+        let mut in_flight = self.in_flight();
+        for (key, failures) in keys.into_iter().zip(failures) {
+            let pending = in_flight.get(key).copied().unwrap_or(0);
+            if failures + pending >= self.policy.limit(key) {
+                return Err(AppError::RateLimited(
+                    "Too many login attempts in progress, try again shortly".to_string(),
+                ));
+            }
+        }
+        for key in keys {
+            *in_flight.entry(key.to_string()).or_default() += 1;
+        }
+
+        Ok(LoginAttempt {
+            throttle: self,
+            client: client.to_string(),
+        })
+    }
+
+    fn in_flight(&self) -> std::sync::MutexGuard<'_, HashMap<String, u32>> {
+        // Counts are only changed in whole steps, so a panicked holder
+        // leaves nothing inconsistent
+        self.in_flight.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Fails with `RateLimited` while `client` or all logins are locked out.
+    pub async fn check(&self, client: &str) -> AppResult<()> {
+        let now = Utc::now();
+
+        for key in [client, GLOBAL_CLIENT] {
+            let Some(entry) = self.get(key).await? else {
+                continue;
+            };
+            if entry.locked_until > now {
+                let seconds = (entry.locked_until - now).num_seconds().max(1);
+                return Err(AppError::RateLimited(format!(
+                    "Too many failed logins, try again in {} seconds",
+                    seconds
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn record_failure(&self, client: &str) -> AppResult<()> {
+        let now = Utc::now();
+
+        for key in [client, GLOBAL_CLIENT] {
+            let failures = self.recent_failures(key, now).await? + 1;
+            let delay = self.policy.delay(key, failures);
+            if delay >= self.policy.lockout {
+                warn!("Login locked out for {} after {} failures", key, failures);
+            }
+
+            sqlx::query(
+                r#"
+                INSERT INTO login_failures (client, failures, last_failure, locked_until) VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT (client) DO UPDATE SET
+                    failures = excluded.failures,
+                    last_failure = excluded.last_failure,
+                    locked_until = excluded.locked_until
+                "#,
+            )
+            .bind(key)
+            .bind(failures as i64)
+            .bind(now)
+            .bind(now + delay)
+            .execute(&self.pool)
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Forgets the failures of `client` after it logged in. The global
+    /// counter only decays with time.
+    pub async fn record_success(&self, client: &str) -> AppResult<()> {
+        sqlx::query("DELETE FROM login_failures WHERE client = ?1")
+            .bind(client)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn list(&self) -> AppResult<Vec<LoginFailures>> {
+        let rows = sqlx::query(
+            "SELECT client, failures, last_failure, locked_until FROM login_failures ORDER BY last_failure DESC",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(row_to_failures).collect())
+    }
+
+    /// Clears the counter of one client, or every counter when `client` is
+    /// `None`. Returns how many were cleared.
+    pub async fn clear(&self, client: Option<&str>) -> AppResult<u64> {
+        let result = match client {
+            Some(client) => {
+                sqlx::query("DELETE FROM login_failures WHERE client = ?1")
+                    .bind(client)
+                    .execute(&self.pool)
+                    .await?
+            }
+            None => sqlx::query("DELETE FROM login_failures").execute(&self.pool).await?,
+        };
+
+        Ok(result.rows_affected())
+    }
+
+    /// Failures of `client` that are not forgotten yet.
+    async fn recent_failures(&self, client: &str, now: DateTime<Utc>) -> AppResult<u32> {
+        Ok(match self.get(client).await? {
+            Some(entry) if now - entry.last_failure < Duration::hours(FAILURE_MEMORY_HOURS) => entry.failures,
+            _ => 0,
+        })
+    }
+
+    async fn get(&self, client: &str) -> AppResult<Option<LoginFailures>> {
+        let row = sqlx::query(
+            "SELECT client, failures, last_failure, locked_until FROM login_failures WHERE client = ?1",
+        )
+        .bind(client)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.as_ref().map(row_to_failures))
+    }
+}
+
+fn row_to_failures(row: &sqlx::sqlite::SqliteRow) -> LoginFailures {
+    LoginFailures {
+        client: row.get("client"),
+        failures: row.get::<i64, _>("failures") as u32,
+        last_failure: row.get("last_failure"),
+        locked_until: row.get("locked_until"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn test_policy() -> ThrottlePolicy {
+        ThrottlePolicy {
+            max_failures: 3,
+            global_max_failures: 5,
+            lockout: Duration::minutes(15),
+        }
+    }
+
+    async fn create_test_throttle() -> LoginThrottle {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        LoginThrottle::new(pool, test_policy()).await.unwrap()
+    }
+
+    #[test]
+    fn test_delay_grows_exponentially() {
+        let policy = test_policy();
+
+        assert_eq!(policy.delay("192.0.2.1", 1), Duration::seconds(1));
+        assert_eq!(policy.delay("192.0.2.1", 2), Duration::seconds(2));
+        assert_eq!(policy.delay("192.0.2.1", 3), Duration::minutes(15));
+        assert_eq!(policy.delay("192.0.2.1", 4), Duration::minutes(30));
+        assert_eq!(policy.delay("192.0.2.1", 40), Duration::hours(24));
+
+        assert_eq!(policy.delay(GLOBAL_CLIENT, 4), Duration::zero());
+        assert_eq!(policy.delay(GLOBAL_CLIENT, 5), Duration::minutes(15));
+    }
+
+    #[tokio::test]
+    async fn test_failures_lock_out_client() {
+        let throttle = create_test_throttle().await;
+
+        throttle.check("192.0.2.1").await.unwrap();
+        throttle.record_failure("192.0.2.1").await.unwrap();
+        assert!(matches!(throttle.check("192.0.2.1").await, Err(AppError::RateLimited(_))));
+        // Other clients are unaffected until the global limit is reached
+        throttle.check("192.0.2.2").await.unwrap();
+
+        let failures = throttle.list().await.unwrap();
+        assert_eq!(failures.len(), 2);
+        assert!(failures.iter().all(|entry| entry.failures == 1));
+
+        assert_eq!(throttle.clear(Some("192.0.2.1")).await.unwrap(), 1);
+        throttle.check("192.0.2.1").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_running_attempts_count_against_limits() {
+        let throttle = create_test_throttle().await;
+
+        let first = throttle.begin("192.0.2.1").await.unwrap();
+        let _second = throttle.begin("192.0.2.1").await.unwrap();
+        let _third = throttle.begin("192.0.2.1").await.unwrap();
+        assert!(matches!(throttle.begin("192.0.2.1").await, Err(AppError::RateLimited(_))));
+        // Running attempts do not hold up other clients below the global limit
+        let _other = throttle.begin("192.0.2.2").await.unwrap();
+
+        // A finished attempt frees its place, and a recorded failure takes it
+        drop(first);
+        let attempt = throttle.begin("192.0.2.1").await.unwrap();
+        throttle.record_failure("192.0.2.1").await.unwrap();
+        drop(attempt);
+        assert!(matches!(throttle.begin("192.0.2.1").await, Err(AppError::RateLimited(_))));
+    }
+
+    #[tokio::test]
+    async fn test_global_limit_locks_out_everyone() {
+        let throttle = create_test_throttle().await;
+
+        for n in 0..5 {
+            throttle.record_failure(&format!("192.0.2.{}", n)).await.unwrap();
+        }
+        assert!(matches!(throttle.check("198.51.100.1").await, Err(AppError::RateLimited(_))));
+
+        // A successful login does not reset the global counter
+        throttle.record_success("192.0.2.0").await.unwrap();
+        assert!(throttle.check("192.0.2.0").await.is_err());
+
+        assert_eq!(throttle.clear(None).await.unwrap(), 5);
+        throttle.check("198.51.100.1").await.unwrap();
+    }
+}
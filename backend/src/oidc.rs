@@ -0,0 +1,605 @@
+use crate::{
+    config::OidcConfig,
+    error::{AppError, AppResult},
+    users::{self, Role},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::RngCore;
+use ring::{digest, signature};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio::sync::RwLock;
+
+/// How long a login can be completed at the provider.
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// How long the TOTP code can be entered after a first-factor login.
+const SECOND_FACTOR_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+/// Clock difference to the provider tolerated when checking expiry.
+const CLOCK_SKEW_SECONDS: i64 = 60;
+
+/// OpenID Connect relying party. Login state stays on the server; the
+/// browser only carries the `state` value.
+pub struct OidcClient {
+    config: OidcConfig,
+    http: reqwest::Client,
+    /// Discovered on first use, so the provider need not be up at startup.
+    provider: RwLock<Option<Provider>>,
+    logins: Mutex<HashMap<String, Pending<PendingLogin>>>,
+    /// Users who logged in at the provider and still owe a TOTP code.
+    second_factors: Mutex<HashMap<String, Pending<String>>>,
+}
+
+struct Pending<T> {
+    value: T,
+    expires_at: Instant,
+}
+
+struct PendingLogin {
+    code_verifier: String,
+    nonce: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Provider {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Jwk {
+    kty: String,
+    #[serde(default)]
+    kid: Option<String>,
+    #[serde(default)]
+    n: Option<String>,
+    #[serde(default)]
+    e: Option<String>,
+    #[serde(default)]
+    crv: Option<String>,
+    #[serde(default)]
+    x: Option<String>,
+    #[serde(default)]
+    y: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JwtHeader {
+    alg: String,
+    #[serde(default)]
+    kid: Option<String>,
+}
+
+/// Where to send the browser, and the `state` to bind to it.
+#[derive(Debug, Serialize)]
+pub struct LoginStart {
+    pub state: String,
+    pub authorization_url: String,
+}
+
+/// A user as the provider's ID token describes them. Only the issuer and
+/// subject identify them; the other claims may change at the provider.
+#[derive(Debug, Clone)]
+pub struct OidcIdentity {
+    pub issuer: String,
+    pub subject: String,
+    /// Account to create on first login.
+    pub user_id: String,
+    pub email: Option<String>,
+    pub groups: Vec<String>,
+}
+
+impl OidcIdentity {
+    /// Role the user's groups grant, or `None` if they may not log in.
+    pub fn role(&self, config: &OidcConfig) -> Option<Role> {
+        users::role_for_groups(&self.groups, &config.admin_groups, &config.member_groups)
+    }
+}
+
+impl OidcClient {
+    pub fn new(config: OidcConfig) -> Self {
+        Self {
+            config,
+            http: reqwest::Client::new(),
+            provider: RwLock::new(None),
+            logins: Mutex::new(HashMap::new()),
+            second_factors: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn config(&self) -> &OidcConfig {
+        &self.config
+    }
+
+    /// Starts a login: the authorization request with PKCE and a nonce.
+    pub async fn start(&self) -> AppResult<LoginStart> {
+        let provider = self.provider().await?;
+        let state = random_token();
+        let nonce = random_token();
+        let code_verifier = random_token();
+        let code_challenge = URL_SAFE_NO_PAD.encode(digest::digest(&digest::SHA256, code_verifier.as_bytes()));
+
+        let authorization_url = url::Url::parse_with_params(
+            &provider.authorization_endpoint,
+            [
+                ("response_type", "code"),
+                ("client_id", self.config.client_id.as_str()),
+                ("redirect_uri", self.config.redirect_uri.as_str()),
+                ("scope", self.config.scopes.as_str()),
+                ("state", state.as_str()),
+                ("nonce", nonce.as_str()),
+                ("code_challenge", code_challenge.as_str()),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map_err(|e| AppError::OidcError(format!("Invalid authorization endpoint: {}", e)))?;
+
+        insert_pending(&self.logins, state.clone(), PendingLogin { code_verifier, nonce }, LOGIN_TIMEOUT);
+        Ok(LoginStart {
+            state,
+            authorization_url: authorization_url.into(),
+        })
+    }
+
+    /// Finishes the login of `state`: redeems the authorization code and
+    /// verifies the ID token it returns.
+    pub async fn finish(&self, state: &str, code: &str) -> AppResult<OidcIdentity> {
+        let login = take_pending(&self.logins, state)
+            .ok_or_else(|| AppError::AuthenticationFailed("Unknown or expired login".to_string()))?;
+        let provider = self.provider().await?;
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", self.config.redirect_uri.as_str()),
+            ("client_id", self.config.client_id.as_str()),
+            ("code_verifier", login.code_verifier.as_str()),
+        ];
+        if let Some(secret) = &self.config.client_secret {
+            form.push(("client_secret", secret.as_str()));
+        }
+        let response = self
+            .http
+            .post(&provider.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .map_err(provider_error)?;
+        if response.status().is_client_error() {
+            return Err(AppError::AuthenticationFailed("Authorization code was rejected".to_string()));
+        }
+        let tokens: TokenResponse = response
+            .error_for_status()
+            .map_err(provider_error)?
+            .json()
+            .await
+            .map_err(provider_error)?;
+
+        // Keys are fetched for each login, so rotated keys need no restart
+        let keys: JwkSet = self.get_json(&provider.jwks_uri).await?;
+        let claims = verify_id_token(
+            &tokens.id_token,
+            &keys.keys,
+            &provider.issuer,
+            &self.config.client_id,
+            &login.nonce,
+        )?;
+        self.identity(&claims)
+    }
+
+    /// Remembers that `user_id` logged in at the provider, for the TOTP
+    /// step of a first-factor login. Returns the ticket for that step.
+    pub fn issue_ticket(&self, user_id: &str) -> String {
+        let ticket = random_token();
+        insert_pending(&self.second_factors, ticket.clone(), user_id.to_string(), SECOND_FACTOR_TIMEOUT);
+        ticket
+    }
+
+    /// User of an unexpired ticket. Tickets stay valid after a wrong code,
+    /// so typos need no new provider login; the login throttle limits
+    /// guessing.
+    pub fn ticket_user(&self, ticket: &str) -> AppResult<String> {
+        let mut pending = self.second_factors.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        pending.retain(|_, login| login.expires_at > Instant::now());
+        pending
+            .get(ticket)
+            .map(|login| login.value.clone())
+            .ok_or_else(|| AppError::AuthenticationFailed("Unknown or expired login".to_string()))
+    }
+
+    pub fn redeem_ticket(&self, ticket: &str) {
+        take_pending(&self.second_factors, ticket);
+    }
+
+    async fn provider(&self) -> AppResult<Provider> {
+        if let Some(provider) = self.provider.read().await.clone() {
+            return Ok(provider);
+        }
+
+        let url = format!("{}/.well-known/openid-configuration", self.config.issuer);
+        let provider: Provider = self.get_json(&url).await?;
+        if provider.issuer.trim_end_matches('/') != self.config.issuer {
+            return Err(AppError::OidcError(format!(
+                "Provider reports issuer {} instead of {}",
+                provider.issuer, self.config.issuer
+            )));
+        }
+
+        *self.provider.write().await = Some(provider.clone());
+        Ok(provider)
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, url: &str) -> AppResult<T> {
+        self.http
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(provider_error)?
+            .json()
+            .await
+            .map_err(provider_error)
+    }
+
+    fn identity(&self, claims: &Map<String, Value>) -> AppResult<OidcIdentity> {
+        let claim = |name: &str| claims.get(name).and_then(Value::as_str).map(str::to_string);
+        let subject = claim("sub").ok_or_else(|| invalid_token("no subject"))?;
+        let user_id = claim(&self.config.user_claim).ok_or_else(|| {
+            AppError::AuthenticationFailed(format!("ID token has no {} claim", self.config.user_claim))
+        })?;
+        let groups = match claims.get(&self.config.groups_claim) {
+            Some(Value::Array(groups)) => groups.iter().filter_map(Value::as_str).map(str::to_string).collect(),
+            Some(Value::String(group)) => vec![group.clone()],
+            _ => Vec::new(),
+        };
+
+        Ok(OidcIdentity {
+            issuer: self.config.issuer.clone(),
+            subject,
+            user_id,
+            email: claim("email"),
+            groups,
+        })
+    }
+}
+
+/// Checks the signature and the claims of an ID token and returns the
+/// claims. RS256 and ES256 are supported.
+fn verify_id_token(
+    id_token: &str,
+    keys: &[Jwk],
+    issuer: &str,
+    client_id: &str,
+    nonce: &str,
+) -> AppResult<Map<String, Value>> {
+    let parts: Vec<&str> = id_token.split('.').collect();
+    let [header, payload, signature] = parts[..] else {
+        return Err(invalid_token("malformed"));
+    };
+    let decode = |part: &str| URL_SAFE_NO_PAD.decode(part).map_err(|_| invalid_token("malformed"));
+    let header: JwtHeader = serde_json::from_slice(&decode(header)?).map_err(|_| invalid_token("malformed header"))?;
+    let signed = &id_token[..id_token.len() - signature.len() - 1];
+    let signature = decode(signature)?;
+
+    let key_type = match header.alg.as_str() {
+        "RS256" => "RSA",
+        "ES256" => "EC",
+        other => return Err(invalid_token(&format!("unsupported algorithm {}", other))),
+    };
+    let verified = keys
+        .iter()
+        .filter(|key| key.kty == key_type)
+        .filter(|key| header.kid.is_none() || key.kid == header.kid)
+        .any(|key| verify_signature(key, signed.as_bytes(), &signature));
+    if !verified {
+        return Err(invalid_token("bad signature"));
+    }
+
+    let claims: Map<String, Value> =
+        serde_json::from_slice(&decode(payload)?).map_err(|_| invalid_token("malformed claims"))?;
+    if claims.get("iss").and_then(Value::as_str) != Some(issuer) {
+        return Err(invalid_token("wrong issuer"));
+    }
+    let for_us = match claims.get("aud") {
+        Some(Value::String(audience)) => audience == client_id,
+        Some(Value::Array(audiences)) => audiences.iter().any(|audience| audience.as_str() == Some(client_id)),
+        _ => false,
+    };
+    if !for_us {
+        return Err(invalid_token("wrong audience"));
+    }
+    let expires_at = claims.get("exp").and_then(Value::as_i64).ok_or_else(|| invalid_token("no expiry"))?;
+    if expires_at + CLOCK_SKEW_SECONDS <= chrono::Utc::now().timestamp() {
+        return Err(invalid_token("expired"));
+    }
+    if claims.get("nonce").and_then(Value::as_str) != Some(nonce) {
+        return Err(invalid_token("wrong nonce"));
+    }
+
+    Ok(claims)
+}
+
+fn verify_signature(key: &Jwk, message: &[u8], signature: &[u8]) -> bool {
+    let decode = |part: &Option<String>| part.as_deref().and_then(|part| URL_SAFE_NO_PAD.decode(part).ok());
+    match key.kty.as_str() {
+        "RSA" => {
+            let (Some(n), Some(e)) = (decode(&key.n), decode(&key.e)) else {
+                return false;
+            };
+            signature::RsaPublicKeyComponents { n: &n, e: &e }
+                .verify(&signature::RSA_PKCS1_2048_8192_SHA256, message, signature)
+                .is_ok()
+        }
+        "EC" if key.crv.as_deref() == Some("P-256") => {
+            let (Some(x), Some(y)) = (decode(&key.x), decode(&key.y)) else {
+                return false;
+            };
+            let point = [&[0x04][..], &x, &y].concat();
+            signature::UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_FIXED, point)
+                .verify(message, signature)
+                .is_ok()
+        }
+        _ => false,
+    }
+}
+
+fn invalid_token(reason: &str) -> AppError {
+    AppError::AuthenticationFailed(format!("Invalid ID token: {}", reason))
+}
+
+fn provider_error(e: reqwest::Error) -> AppError {
+    AppError::OidcError(e.to_string())
+}
+
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::rngs::OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn insert_pending<T>(pending: &Mutex<HashMap<String, Pending<T>>>, key: String, value: T, timeout: Duration) {
+    let now = Instant::now();
+    let mut pending = pending.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    pending.retain(|_, login| login.expires_at > now);
+    pending.insert(key, Pending { value, expires_at: now + timeout });
+}
+
+/// Removes a pending login, so each one can be finished only once.
+fn take_pending<T>(pending: &Mutex<HashMap<String, Pending<T>>>, key: &str) -> Option<T> {
+    let mut pending = pending.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    pending
+        .remove(key)
+        .filter(|login| login.expires_at > Instant::now())
+        .map(|login| login.value)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::config::OidcLogin;
+    use axum::{
+        extract::{Form, State},
+        routing::{get, post},
+        Json, Router,
+    };
+    use ring::{rand::SystemRandom, signature::KeyPair};
+    use serde_json::json;
+    use std::sync::Arc;
+
+    /// A provider on a local port that authorizes whatever the test tells
+    /// it to and signs ID tokens with an ES256 key.
+    pub(crate) struct MockIdp {
+        pub issuer: String,
+        key: signature::EcdsaKeyPair,
+        /// Authorization code to its nonce, PKCE challenge and claims.
+        codes: Mutex<HashMap<String, (String, String, Value)>>,
+    }
+
+    impl MockIdp {
+        pub(crate) async fn start() -> Arc<Self> {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let rng = SystemRandom::new();
+            let pkcs8 =
+                signature::EcdsaKeyPair::generate_pkcs8(&signature::ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+            let idp = Arc::new(Self {
+                issuer: format!("http://{}", listener.local_addr().unwrap()),
+                key: signature::EcdsaKeyPair::from_pkcs8(
+                    &signature::ECDSA_P256_SHA256_FIXED_SIGNING,
+                    pkcs8.as_ref(),
+                    &rng,
+                )
+                .unwrap(),
+                codes: Mutex::new(HashMap::new()),
+            });
+
+            let app = Router::new()
+                .route("/.well-known/openid-configuration", get(discovery))
+                .route("/jwks", get(jwks))
+                .route("/token", post(token))
+                .with_state(idp.clone());
+            tokio::spawn(async move { axum::serve(listener, app).await });
+            idp
+        }
+
+        pub(crate) fn config(&self) -> OidcConfig {
+            test_config(&self.issuer)
+        }
+
+        /// Plays the user logging in at the provider: returns the code the
+        /// provider would redirect back with.
+        pub(crate) fn authorize(&self, start: &LoginStart, claims: Value) -> String {
+            let url = url::Url::parse(&start.authorization_url).unwrap();
+            let param = |name: &str| url.query_pairs().find(|(key, _)| key == name).unwrap().1.into_owned();
+            let code = random_token();
+            self.codes
+                .lock()
+                .unwrap()
+                .insert(code.clone(), (param("nonce"), param("code_challenge"), claims));
+            code
+        }
+
+        fn sign(&self, claims: &Value) -> String {
+            let header = URL_SAFE_NO_PAD.encode(json!({"alg": "ES256", "kid": "test"}).to_string());
+            let payload = URL_SAFE_NO_PAD.encode(claims.to_string());
+            let signed = format!("{}.{}", header, payload);
+            let signature = self.key.sign(&SystemRandom::new(), signed.as_bytes()).unwrap();
+            format!("{}.{}", signed, URL_SAFE_NO_PAD.encode(signature.as_ref()))
+        }
+    }
+
+    pub(crate) fn test_config(issuer: &str) -> OidcConfig {
+        OidcConfig {
+            issuer: issuer.to_string(),
+            client_id: "kagikanri".to_string(),
+            client_secret: Some("secret".to_string()),
+            redirect_uri: "https://kagikanri.local/api/auth/login/oidc/callback".to_string(),
+            scopes: "openid email profile".to_string(),
+            login: OidcLogin::Alternative,
+            user_claim: "preferred_username".to_string(),
+            groups_claim: "groups".to_string(),
+            admin_groups: vec!["kagikanri-admins".to_string()],
+            member_groups: Vec::new(),
+        }
+    }
+
+    async fn discovery(State(idp): State<Arc<MockIdp>>) -> Json<Value> {
+        Json(json!({
+            "issuer": idp.issuer,
+            "authorization_endpoint": format!("{}/auth", idp.issuer),
+            "token_endpoint": format!("{}/token", idp.issuer),
+            "jwks_uri": format!("{}/jwks", idp.issuer),
+        }))
+    }
+
+    async fn jwks(State(idp): State<Arc<MockIdp>>) -> Json<Value> {
+        let point = idp.key.public_key().as_ref();
+        Json(json!({"keys": [{
+            "kty": "EC",
+            "crv": "P-256",
+            "kid": "test",
+            "x": URL_SAFE_NO_PAD.encode(&point[1..33]),
+            "y": URL_SAFE_NO_PAD.encode(&point[33..]),
+        }]}))
+    }
+
+    async fn token(
+        State(idp): State<Arc<MockIdp>>,
+        Form(form): Form<HashMap<String, String>>,
+    ) -> Result<Json<Value>, axum::http::StatusCode> {
+        let (nonce, challenge, claims) = idp
+            .codes
+            .lock()
+            .unwrap()
+            .remove(&form["code"])
+            .ok_or(axum::http::StatusCode::BAD_REQUEST)?;
+        let verifier_hash = URL_SAFE_NO_PAD.encode(digest::digest(&digest::SHA256, form["code_verifier"].as_bytes()));
+        if verifier_hash != challenge || form.get("client_secret").map(String::as_str) != Some("secret") {
+            return Err(axum::http::StatusCode::BAD_REQUEST);
+        }
+
+        let mut id_token = json!({
+            "iss": idp.issuer,
+            "aud": form["client_id"],
+            "exp": chrono::Utc::now().timestamp() + 300,
+            "nonce": nonce,
+        });
+        for (name, value) in claims.as_object().unwrap() {
+            id_token[name] = value.clone();
+        }
+        Ok(Json(json!({"id_token": idp.sign(&id_token), "token_type": "Bearer"})))
+    }
+
+    #[tokio::test]
+    async fn test_login_with_mock_provider() {
+        let idp = MockIdp::start().await;
+        let client = OidcClient::new(idp.config());
+
+        let start = client.start().await.unwrap();
+        let code = idp.authorize(
+            &start,
+            json!({
+                "sub": "1234",
+                "preferred_username": "alice",
+                "email": "alice@example.com",
+                "groups": ["/kagikanri-admins"],
+            }),
+        );
+        let identity = client.finish(&start.state, &code).await.unwrap();
+        assert_eq!(identity.user_id, "alice");
+        assert_eq!(identity.email.as_deref(), Some("alice@example.com"));
+        assert_eq!(identity.role(client.config()), Some(Role::Admin));
+
+        // Each login is finished once
+        assert!(matches!(
+            client.finish(&start.state, &code).await,
+            Err(AppError::AuthenticationFailed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_tokens_for_other_clients_are_rejected() {
+        let idp = MockIdp::start().await;
+        let client = OidcClient::new(idp.config());
+
+        let start = client.start().await.unwrap();
+        let code = idp.authorize(&start, json!({"sub": "1234", "preferred_username": "alice", "aud": "other-app"}));
+        assert!(matches!(
+            client.finish(&start.state, &code).await,
+            Err(AppError::AuthenticationFailed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_tampered_tokens_are_rejected() {
+        let idp = MockIdp::start().await;
+        let claims = json!({
+            "iss": idp.issuer,
+            "aud": "kagikanri",
+            "exp": chrono::Utc::now().timestamp() + 300,
+            "nonce": "n",
+        });
+        let token = idp.sign(&claims);
+        let keys: JwkSet = reqwest::get(format!("{}/jwks", idp.issuer)).await.unwrap().json().await.unwrap();
+        assert!(verify_id_token(&token, &keys.keys, &idp.issuer, "kagikanri", "n").is_ok());
+        assert!(verify_id_token(&token, &keys.keys, &idp.issuer, "kagikanri", "other").is_err());
+
+        let (signed, _) = token.rsplit_once('.').unwrap();
+        let forged = format!("{}.{}", signed, URL_SAFE_NO_PAD.encode([0u8; 64]));
+        assert!(verify_id_token(&forged, &keys.keys, &idp.issuer, "kagikanri", "n").is_err());
+    }
+
+    #[test]
+    fn test_groups_map_to_roles() {
+        let mut config = test_config("https://idp.example.com");
+        let identity = |groups: &[&str]| OidcIdentity {
+            issuer: config.issuer.clone(),
+            subject: "1234".to_string(),
+            user_id: "alice".to_string(),
+            email: None,
+            groups: groups.iter().map(|group| group.to_string()).collect(),
+        };
+
+        assert_eq!(identity(&["kagikanri-admins"]).role(&config), Some(Role::Admin));
+        assert_eq!(identity(&[]).role(&config), Some(Role::Member));
+
+        config.member_groups = vec!["/kagikanri".to_string()];
+        assert_eq!(identity(&["/kagikanri"]).role(&config), Some(Role::Member));
+        assert_eq!(identity(&["other"]).role(&config), None);
+    }
+}
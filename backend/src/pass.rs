@@ -2,7 +2,6 @@ use crate::{config::PassConfig, error::{AppError, AppResult}};
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
-    path::{Path, PathBuf},
     process::Command,
 };
 use tracing::{debug, info};
@@ -50,6 +49,20 @@ impl PassInterface {
         Ok(PasswordList { entries })
     }
 
+    /// Entries below `folder`, which has no entries if it does not exist.
+    pub async fn list_folder(&self, folder: &str) -> AppResult<PasswordList> {
+        info!("Listing passwords in {}", folder);
+        
+        if !self.config.store_dir.join(folder).is_dir() {
+            return Ok(PasswordList { entries: Vec::new() });
+        }
+        let output = self.run_pass_command(&["ls", folder]).await?;
+        // The first line names the folder itself
+        let entries = self.parse_password_list(output.split_once('\n').map_or("", |(_, rest)| rest));
+        
+        Ok(PasswordList { entries })
+    }
+
     pub async fn get_password(&self, path: &str) -> AppResult<PasswordEntry> {
         info!("Getting password for path: {}", path);
         
@@ -66,6 +79,7 @@ impl PassInterface {
         
         // Use echo to pipe password to pass insert
         let mut cmd = Command::new("sh");
+        self.apply_store_env(&mut cmd);
         cmd.arg("-c")
            .arg(format!("echo '{}' | pass insert --multiline --force '{}'", content, path));
         
@@ -111,6 +125,7 @@ impl PassInterface {
         
         // Insert the TOTP secret using pass otp
         let mut cmd = Command::new("sh");
+        self.apply_store_env(&mut cmd);
         cmd.arg("-c")
            .arg(format!("echo '{}' | pass otp insert '{}'", secret, path));
         
@@ -130,9 +145,7 @@ impl PassInterface {
         
         let mut cmd = Command::new("pass");
         cmd.args(args);
-        
-        // Set PASSWORD_STORE_DIR
-        cmd.env("PASSWORD_STORE_DIR", &self.config.store_dir);
+        self.apply_store_env(&mut cmd);
         
         let output = cmd.output()
             .map_err(|e| AppError::PassError(format!("Failed to run pass command: {}", e)))?;
@@ -148,6 +161,16 @@ impl PassInterface {
         Ok(stdout)
     }
 
+    /// Points a `pass` invocation at the configured store.
+    ///
+    /// Kagikanri commits every write itself (see `GitSync::commit_change`), so
+    /// pass's own git integration is switched off by giving it a `GIT_DIR`
+    /// that is not a repository.
+    fn apply_store_env(&self, cmd: &mut Command) {
+        cmd.env("PASSWORD_STORE_DIR", &self.config.store_dir);
+        cmd.env("GIT_DIR", "/dev/null");
+    }
+
     fn parse_password_list(&self, output: &str) -> Vec<PasswordItem> {
         let mut entries = Vec::new();
         
@@ -170,7 +193,7 @@ impl PassInterface {
                 clean_line.strip_suffix(".gpg").unwrap_or(&clean_line).to_string()
             };
             
-            let name = path.split('/').last().unwrap_or(&path).to_string();
+            let name = path.split('/').next_back().unwrap_or(&path).to_string();
             
             entries.push(PasswordItem {
                 path,
@@ -1,11 +1,11 @@
 use crate::{
     config::DatabaseConfig,
     error::{AppError, AppResult},
+    users::BOOTSTRAP_ADMIN,
 };
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::{sqlite::SqlitePoolOptions, Row, SqlitePool};
-use std::collections::HashMap;
 use uuid::Uuid;
 use webauthn_rs::{
     prelude::*,
@@ -16,6 +16,7 @@ use webauthn_rs::{
 #[derive(Debug, Clone)]
 pub struct PasskeyStore {
     pool: SqlitePool,
+    #[allow(dead_code)] // Not wired into the simplified registration flow yet
     webauthn: Webauthn,
     encryption_key: [u8; 32],
 }
@@ -23,6 +24,8 @@ pub struct PasskeyStore {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StoredPasskey {
     pub id: String,
+    /// Kagikanri user the passkey belongs to.
+    pub user_id: String,
     pub domain: String,
     pub user_handle: Option<Vec<u8>>,
     pub credential_id: Vec<u8>,
@@ -91,11 +94,17 @@ impl PasskeyStore {
         Ok(store)
     }
 
+    /// Connection pool of the local database, shared with other stores.
+    pub fn pool(&self) -> SqlitePool {
+        self.pool.clone()
+    }
+
     async fn init_schema(&self) -> AppResult<()> {
         sqlx::query(
             r#"
             CREATE TABLE IF NOT EXISTS passkeys (
                 id TEXT PRIMARY KEY,
+                user_id TEXT NOT NULL,
                 domain TEXT NOT NULL,
                 user_handle BLOB,
                 credential_id BLOB NOT NULL,
@@ -120,6 +129,23 @@ impl PasskeyStore {
         .execute(&self.pool)
         .await?;
 
+        // Passkeys of single-user installations belong to the bootstrap admin
+        let has_user_id = sqlx::query("SELECT 1 FROM pragma_table_info('passkeys') WHERE name = 'user_id'")
+            .fetch_optional(&self.pool)
+            .await?
+            .is_some();
+        if !has_user_id {
+            sqlx::query(&format!(
+                "ALTER TABLE passkeys ADD COLUMN user_id TEXT NOT NULL DEFAULT '{}'",
+                BOOTSTRAP_ADMIN
+            ))
+            .execute(&self.pool)
+            .await?;
+        }
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_passkeys_user ON passkeys(user_id)")
+            .execute(&self.pool)
+            .await?;
+
         Ok(())
     }
 
@@ -139,7 +165,8 @@ impl PasskeyStore {
 
     pub async fn finish_registration(
         &self,
-        request: PasskeyRegistrationFinish,
+        user_id: &str,
+        _request: PasskeyRegistrationFinish,
     ) -> AppResult<StoredPasskey> {
         // This is a simplified implementation
         // In a real implementation, you'd need to properly handle the WebAuthn flow
@@ -150,6 +177,7 @@ impl PasskeyStore {
         // For now, create a placeholder entry
         let passkey = StoredPasskey {
             id: id.clone(),
+            user_id: user_id.to_string(),
             domain: "example.com".to_string(), // TODO: Extract from request
             user_handle: Some(vec![1, 2, 3, 4]), // Placeholder
             credential_id: vec![5, 6, 7, 8], // Placeholder
@@ -164,11 +192,12 @@ impl PasskeyStore {
 
         sqlx::query(
             r#"
-            INSERT INTO passkeys (id, domain, user_handle, credential_id, public_key, private_key_encrypted, counter, created_at, salt)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+            INSERT INTO passkeys (id, user_id, domain, user_handle, credential_id, public_key, private_key_encrypted, counter, created_at, salt)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
             "#,
         )
         .bind(&id)
+        .bind(&passkey.user_id)
         .bind(&passkey.domain)
         .bind(&passkey.user_handle)
         .bind(&passkey.credential_id)
@@ -183,10 +212,13 @@ impl PasskeyStore {
         Ok(passkey)
     }
 
-    pub async fn list_passkeys(&self) -> AppResult<Vec<StoredPasskey>> {
+    /// Passkeys of `owner`, or of every user when `None`.
+    pub async fn list_passkeys(&self, owner: Option<&str>) -> AppResult<Vec<StoredPasskey>> {
         let rows = sqlx::query(
-            "SELECT id, domain, user_handle, credential_id, public_key, counter, created_at FROM passkeys ORDER BY created_at DESC"
+            "SELECT id, user_id, domain, user_handle, credential_id, public_key, counter, created_at FROM passkeys \
+             WHERE ?1 IS NULL OR user_id = ?1 ORDER BY created_at DESC"
         )
+        .bind(owner)
         .fetch_all(&self.pool)
         .await?;
 
@@ -194,6 +226,7 @@ impl PasskeyStore {
         for row in rows {
             let passkey = StoredPasskey {
                 id: row.get("id"),
+                user_id: row.get("user_id"),
                 domain: row.get("domain"),
                 user_handle: row.get("user_handle"),
                 credential_id: row.get("credential_id"),
@@ -207,9 +240,12 @@ impl PasskeyStore {
         Ok(passkeys)
     }
 
-    pub async fn delete_passkey(&self, id: &str) -> AppResult<()> {
-        let result = sqlx::query("DELETE FROM passkeys WHERE id = ?1")
+    /// Deletes a passkey of `owner`, or of any user when `None`. Passkeys
+    /// of other users are reported as not found.
+    pub async fn delete_passkey(&self, id: &str, owner: Option<&str>) -> AppResult<()> {
+        let result = sqlx::query("DELETE FROM passkeys WHERE id = ?1 AND (?2 IS NULL OR user_id = ?2)")
             .bind(id)
+            .bind(owner)
             .execute(&self.pool)
             .await?;
 
@@ -239,6 +275,7 @@ impl PasskeyStore {
         Ok(encrypted)
     }
 
+    #[allow(dead_code)]
     fn decrypt_data(&self, encrypted_data: &[u8], salt: &[u8]) -> AppResult<Vec<u8>> {
         // Simple XOR decryption (XOR is its own inverse)
         self.encrypt_data(encrypted_data, salt)
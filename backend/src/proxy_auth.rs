@@ -0,0 +1,131 @@
+use axum::http::HeaderMap;
+use std::net::IpAddr;
+use crate::{
+    client_ip::ip_in_range,
+    config::ProxyAuthConfig,
+    users::{self, Role},
+};
+
+/// A login asserted by an authenticating reverse proxy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyIdentity {
+    pub user_id: String,
+    pub groups: Vec<String>,
+}
+
+impl ProxyIdentity {
+    /// Role the user's groups grant, or `None` if they may not log in.
+    pub fn role(&self, config: &ProxyAuthConfig) -> Option<Role> {
+        users::role_for_groups(&self.groups, &config.admin_groups, &config.member_groups)
+    }
+}
+
+/// Takes the identity headers out of a request. They are only believed
+/// when the connection comes from a trusted proxy; anyone else could set
+/// them, so from other peers they are dropped without a word.
+pub fn take_identity(headers: &mut HeaderMap, peer: Option<IpAddr>, config: &ProxyAuthConfig) -> Option<ProxyIdentity> {
+    let user = remove_all(headers, &config.user_header);
+    let groups = remove_all(headers, &config.groups_header);
+
+    let trusted = peer.is_some_and(|peer| config.trusted_proxies.iter().any(|range| ip_in_range(peer, range)));
+    if !trusted {
+        if !user.is_empty() {
+            tracing::warn!("Ignored {} header from untrusted peer {:?}", config.user_header, peer);
+        }
+        return None;
+    }
+
+    // A repeated user header is ambiguous, so it counts as none
+    let [user_id] = user.as_slice() else {
+        return None;
+    };
+    let user_id = user_id.trim();
+    if user_id.is_empty() {
+        return None;
+    }
+
+    Some(ProxyIdentity {
+        user_id: user_id.to_string(),
+        groups: groups
+            .iter()
+            .flat_map(|value| value.split(','))
+            .map(|group| group.trim().to_string())
+            .filter(|group| !group.is_empty())
+            .collect(),
+    })
+}
+
+/// Removes every `name` header, returning the values that are valid text.
+fn remove_all(headers: &mut HeaderMap, name: &str) -> Vec<String> {
+    let values = headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok().map(str::to_string))
+        .collect();
+    headers.remove(name);
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> ProxyAuthConfig {
+        ProxyAuthConfig {
+            trusted_proxies: vec!["10.0.0.0/24".to_string()],
+            user_header: "Remote-User".to_string(),
+            groups_header: "Remote-Groups".to_string(),
+            admin_groups: vec!["vault-admins".to_string()],
+            member_groups: vec!["vault".to_string()],
+        }
+    }
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_identity_from_trusted_proxy() {
+        let config = config();
+        let mut request = headers(&[("remote-user", "alice"), ("remote-groups", "vault, vault-admins")]);
+
+        let identity = take_identity(&mut request, Some("10.0.0.5".parse().unwrap()), &config).unwrap();
+        assert_eq!(identity.user_id, "alice");
+        assert_eq!(identity.groups, vec!["vault", "vault-admins"]);
+        assert_eq!(identity.role(&config), Some(Role::Admin));
+        assert!(request.is_empty());
+    }
+
+    #[test]
+    fn test_headers_from_elsewhere_are_stripped() {
+        let config = config();
+        for peer in [Some("192.0.2.1".parse().unwrap()), None] {
+            let mut request = headers(&[("remote-user", "alice"), ("remote-groups", "vault-admins"), ("accept", "*/*")]);
+
+            assert_eq!(take_identity(&mut request, peer, &config), None);
+            assert!(!request.contains_key("remote-user"));
+            assert!(!request.contains_key("remote-groups"));
+            assert!(request.contains_key("accept"));
+        }
+    }
+
+    #[test]
+    fn test_ambiguous_or_missing_user() {
+        let config = config();
+        let proxy = Some("10.0.0.5".parse().unwrap());
+
+        let mut request = headers(&[("remote-user", "alice"), ("remote-user", "bob")]);
+        assert_eq!(take_identity(&mut request, proxy, &config), None);
+
+        let mut request = headers(&[("remote-groups", "vault")]);
+        assert_eq!(take_identity(&mut request, proxy, &config), None);
+
+        let mut request = headers(&[("remote-user", "carol"), ("remote-groups", "staff")]);
+        let identity = take_identity(&mut request, proxy, &config).unwrap();
+        assert_eq!(identity.role(&config), None);
+    }
+}
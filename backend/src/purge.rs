@@ -0,0 +1,441 @@
+use crate::error::{AppError, AppResult};
+use git2::{Commit, ObjectType, Oid, ReferenceType, Repository, Sort, Tree};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+
+/// What purging `paths` from history would do. Nothing is changed until the
+/// purge is confirmed with `confirmation_token`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PurgePlan {
+    pub paths: Vec<String>,
+    /// Every file in history matched by `paths`.
+    pub files: Vec<String>,
+    pub commits_total: usize,
+    pub commits_affected: Vec<AffectedCommit>,
+    /// Branches, tags and remote-tracking refs besides HEAD's branch that
+    /// still reach purged files. Only HEAD's branch is rewritten, so the
+    /// purge is refused until these are deleted.
+    pub blocking_refs: Vec<String>,
+    /// Ties a confirmation to this exact plan; it changes when HEAD moves.
+    pub confirmation_token: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AffectedCommit {
+    pub id: String,
+    pub summary: String,
+    pub files: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PurgeResult {
+    pub new_head: Option<String>,
+    pub commits_rewritten: usize,
+    pub remotes: Vec<PurgePush>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PurgePush {
+    pub remote: String,
+    pub error: Option<String>,
+}
+
+/// Normalizes requested paths. Each one names an entry (`work/vpn`), a
+/// folder (`work/old`) or a file (`notes.txt`).
+pub fn normalize_paths(paths: &[String]) -> AppResult<Vec<String>> {
+    let paths: BTreeSet<String> = paths
+        .iter()
+        .map(|path| path.trim().trim_matches('/').to_string())
+        .collect();
+
+    if paths.is_empty() || paths.contains("") {
+        return Err(AppError::ValidationError(
+            "Purge paths must name entries or folders in the store".to_string(),
+        ));
+    }
+    if paths.iter().any(|path| path.split('/').any(|part| part == ".." || part == ".git")) {
+        return Err(AppError::ValidationError("Invalid purge path".to_string()));
+    }
+
+    Ok(paths.into_iter().collect())
+}
+
+/// Dry run of a purge: which commits and files would be rewritten.
+pub fn plan(repo: &Repository, paths: &[String]) -> AppResult<PurgePlan> {
+    let paths = normalize_paths(paths)?;
+    let head = repo.head()?.peel_to_commit()?;
+
+    let still_present = matching_files(&head.tree()?, &paths)?;
+    if !still_present.is_empty() {
+        return Err(AppError::ValidationError(format!(
+            "{} still exists in the store; remove it before purging its history",
+            still_present.join(", ")
+        )));
+    }
+
+    let mut files = BTreeSet::new();
+    let mut commits_affected = Vec::new();
+    let mut affected_ids = Vec::new();
+    let mut commits_total = 0;
+
+    for oid in walk(repo)? {
+        let commit = repo.find_commit(oid)?;
+        commits_total += 1;
+
+        let matched = matching_files(&commit.tree()?, &paths)?;
+        if matched.is_empty() {
+            continue;
+        }
+        affected_ids.push(oid);
+        files.extend(matched.iter().cloned());
+        commits_affected.push(AffectedCommit {
+            id: commit.id().to_string(),
+            summary: commit.summary().unwrap_or_default().to_string(),
+            files: matched,
+        });
+    }
+
+    Ok(PurgePlan {
+        confirmation_token: confirmation_token(head.id(), &paths),
+        blocking_refs: blocking_refs(repo, &paths, &affected_ids)?,
+        paths,
+        files: files.into_iter().collect(),
+        commits_total,
+        commits_affected,
+    })
+}
+
+/// Refs that reach purged files and that a purge would not rewrite. HEAD's
+/// branch and its remote-tracking branches are updated by the purge itself.
+fn blocking_refs(repo: &Repository, paths: &[String], affected: &[Oid]) -> AppResult<Vec<String>> {
+    let head = repo.head()?;
+    let head_name = head.name().unwrap_or_default().to_string();
+    let branch = head.shorthand().unwrap_or_default().to_string();
+    let head_id = head.peel_to_commit()?.id();
+
+    let mut blocking = Vec::new();
+    for reference in repo.references()? {
+        let reference = reference?;
+        let Some(name) = reference.name().map(str::to_string) else {
+            continue;
+        };
+        let tracks_branch = name
+            .strip_prefix("refs/remotes/")
+            .and_then(|rest| rest.split_once('/'))
+            .is_some_and(|(_, tracked)| tracked == branch);
+        if name == head_name || tracks_branch || reference.kind() == Some(ReferenceType::Symbolic) {
+            continue;
+        }
+
+        // Refs to anything but a commit cannot be checked, so they block too
+        let reaches = match reference.peel_to_commit() {
+            Ok(commit) => reaches_purged(repo, commit.id(), head_id, paths, affected)?,
+            Err(_) => true,
+        };
+        if reaches {
+            blocking.push(name);
+        }
+    }
+
+    blocking.sort();
+    Ok(blocking)
+}
+
+/// True if `tip` reaches an affected commit on HEAD, or a commit of its
+/// own that contains purged files.
+fn reaches_purged(
+    repo: &Repository,
+    tip: Oid,
+    head: Oid,
+    paths: &[String],
+    affected: &[Oid],
+) -> AppResult<bool> {
+    for commit in affected {
+        if tip == *commit || repo.graph_descendant_of(tip, *commit)? {
+            return Ok(true);
+        }
+    }
+
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(tip)?;
+    revwalk.hide(head)?;
+    for oid in revwalk {
+        if !matching_files(&repo.find_commit(oid?)?.tree()?, paths)?.is_empty() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Token a caller must echo back to confirm the plan they reviewed.
+pub fn confirmation_token(head: Oid, paths: &[String]) -> String {
+    let input = format!("purge\n{}\n{}", head, paths.join("\n"));
+    hex::encode(digest::digest(&digest::SHA256, input.as_bytes()))
+}
+
+/// Rewrites every commit on HEAD without the files matched by `paths`.
+/// Commits whose tree and parents are unchanged keep their id. New commits
+/// are written by `write_commit`, which receives the original commit, the
+/// filtered tree and the rewritten parents. Returns the new HEAD commit and
+/// the number of commits rewritten; no refs are moved.
+pub fn rewrite(
+    repo: &Repository,
+    paths: &[String],
+    mut write_commit: impl FnMut(&Commit, &Tree, &[&Commit]) -> AppResult<Oid>,
+) -> AppResult<(Oid, usize)> {
+    let mut commits: HashMap<Oid, Oid> = HashMap::new();
+    let mut trees: HashMap<Oid, Oid> = HashMap::new();
+    let mut rewritten = 0;
+    let mut head = None;
+
+    for oid in walk(repo)? {
+        let commit = repo.find_commit(oid)?;
+
+        let tree_id = match trees.get(&commit.tree_id()) {
+            Some(tree_id) => *tree_id,
+            None => {
+                let filtered = filter_tree(repo, &commit.tree()?, "", paths)?;
+                let tree_id = match filtered {
+                    Some(tree_id) => tree_id,
+                    None => repo.treebuilder(None)?.write()?,
+                };
+                trees.insert(commit.tree_id(), tree_id);
+                tree_id
+            }
+        };
+
+        let parent_ids: Vec<Oid> = commit
+            .parent_ids()
+            .map(|parent| commits.get(&parent).copied().unwrap_or(parent))
+            .collect();
+
+        let unchanged = tree_id == commit.tree_id() && parent_ids.iter().copied().eq(commit.parent_ids());
+        let new_id = if unchanged {
+            oid
+        } else {
+            let parents = parent_ids
+                .iter()
+                .map(|parent| repo.find_commit(*parent))
+                .collect::<Result<Vec<_>, _>>()?;
+            let parent_refs: Vec<&Commit> = parents.iter().collect();
+            rewritten += 1;
+            write_commit(&commit, &repo.find_tree(tree_id)?, &parent_refs)?
+        };
+
+        commits.insert(oid, new_id);
+        head = Some(new_id);
+    }
+
+    let head = head.ok_or_else(|| AppError::GitError("Repository has no commits".to_string()))?;
+    Ok((head, rewritten))
+}
+
+/// Commits on HEAD, parents before children.
+fn walk(repo: &Repository) -> AppResult<Vec<Oid>> {
+    let mut revwalk = repo.revwalk()?;
+    revwalk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+    revwalk.push_head()?;
+    Ok(revwalk.collect::<Result<Vec<_>, _>>()?)
+}
+
+fn matches(path: &str, paths: &[String]) -> bool {
+    paths.iter().any(|purged| {
+        path == purged
+            || path.strip_suffix(".gpg") == Some(purged.as_str())
+            || path
+                .strip_prefix(purged.as_str())
+                .is_some_and(|rest| rest.starts_with('/'))
+    })
+}
+
+fn matching_files(tree: &Tree, paths: &[String]) -> AppResult<Vec<String>> {
+    let mut files = Vec::new();
+    tree.walk(git2::TreeWalkMode::PreOrder, |dir, entry| {
+        if entry.kind() == Some(ObjectType::Blob) {
+            let path = format!("{}{}", dir, entry.name().unwrap_or_default());
+            if matches(&path, paths) {
+                files.push(path);
+            }
+        }
+        git2::TreeWalkResult::Ok
+    })?;
+    Ok(files)
+}
+
+/// Copy of `tree` without matched files. Trees left empty are dropped, as
+/// git does.
+fn filter_tree(repo: &Repository, tree: &Tree, prefix: &str, paths: &[String]) -> AppResult<Option<Oid>> {
+    let mut builder = repo.treebuilder(Some(tree))?;
+    let mut changed = false;
+
+    for entry in tree.iter() {
+        let name = entry.name().unwrap_or_default().to_string();
+        let path = format!("{}{}", prefix, name);
+
+        match entry.kind() {
+            Some(ObjectType::Tree) => {
+                let subtree = repo.find_tree(entry.id())?;
+                match filter_tree(repo, &subtree, &format!("{}/", path), paths)? {
+                    Some(id) if id == entry.id() => {}
+                    Some(id) => {
+                        builder.insert(&name, id, entry.filemode())?;
+                        changed = true;
+                    }
+                    None => {
+                        builder.remove(&name)?;
+                        changed = true;
+                    }
+                }
+            }
+            _ if matches(&path, paths) => {
+                builder.remove(&name)?;
+                changed = true;
+            }
+            _ => {}
+        }
+    }
+
+    if builder.is_empty() {
+        return Ok(None);
+    }
+    if !changed {
+        return Ok(Some(tree.id()));
+    }
+    Ok(Some(builder.write()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::Signature;
+    use pretty_assertions::assert_eq;
+    use std::{fs, path::Path};
+    use tempfile::TempDir;
+
+    fn commit(repo: &Repository, writes: &[(&str, Option<&str>)], message: &str) -> Oid {
+        let workdir = repo.workdir().unwrap().to_path_buf();
+        let mut index = repo.index().unwrap();
+        for (file, content) in writes {
+            let path = workdir.join(file);
+            match content {
+                Some(content) => {
+                    fs::create_dir_all(path.parent().unwrap()).unwrap();
+                    fs::write(&path, content).unwrap();
+                    index.add_path(Path::new(file)).unwrap();
+                }
+                None => {
+                    fs::remove_file(&path).unwrap();
+                    index.remove_path(Path::new(file)).unwrap();
+                }
+            }
+        }
+        index.write().unwrap();
+
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("Admin", "admin@example.com").unwrap();
+        let parent = repo.head().ok().map(|head| head.peel_to_commit().unwrap());
+        let parents: Vec<&Commit> = parent.iter().collect();
+        repo.commit(Some("HEAD"), &signature, &signature, message, &tree, &parents)
+            .unwrap()
+    }
+
+    /// Store where a departed employee's folder was added and later removed.
+    fn create_test_repo() -> (TempDir, Repository, Oid) {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+
+        let first = commit(&repo, &[("github.com.gpg", Some("github"))], "Add github.com");
+        commit(
+            &repo,
+            &[("alice/vpn.gpg", Some("vpn")), ("alice/mail.gpg", Some("mail"))],
+            "Add alice's entries",
+        );
+        commit(&repo, &[("alice/vpn.gpg", None), ("alice/mail.gpg", None)], "Remove alice");
+        commit(&repo, &[("gitlab.com.gpg", Some("gitlab"))], "Add gitlab.com");
+        (temp_dir, repo, first)
+    }
+
+    fn copy_commit(repo: &Repository) -> impl FnMut(&Commit, &Tree, &[&Commit]) -> AppResult<Oid> + '_ {
+        move |original, tree, parents| {
+            Ok(repo.commit(
+                None,
+                &original.author(),
+                &original.committer(),
+                original.message().unwrap_or_default(),
+                tree,
+                parents,
+            )?)
+        }
+    }
+
+    #[test]
+    fn test_plan_reports_affected_commits() {
+        let (_temp_dir, repo, _) = create_test_repo();
+        let plan = plan(&repo, &["/alice/".to_string()]).unwrap();
+
+        assert_eq!(plan.paths, vec!["alice".to_string()]);
+        assert_eq!(plan.files, vec!["alice/mail.gpg".to_string(), "alice/vpn.gpg".to_string()]);
+        assert_eq!(plan.commits_total, 4);
+        assert_eq!(plan.commits_affected.len(), 1);
+        assert_eq!(plan.commits_affected[0].summary, "Add alice's entries");
+        assert!(plan.blocking_refs.is_empty());
+
+        let head = repo.head().unwrap().target().unwrap();
+        assert_eq!(plan.confirmation_token, confirmation_token(head, &plan.paths));
+    }
+
+    #[test]
+    fn test_plan_reports_refs_that_keep_purged_files() {
+        let (_temp_dir, repo, first) = create_test_repo();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        let alice = head.parent(0).unwrap().parent(0).unwrap();
+        repo.branch("backup", &alice, false).unwrap();
+        repo.tag_lightweight("v1", alice.as_object(), false).unwrap();
+        // Refs from before the purged files were added are harmless
+        repo.branch("old", &repo.find_commit(first).unwrap(), false).unwrap();
+
+        let plan = plan(&repo, &["alice".to_string()]).unwrap();
+        assert_eq!(
+            plan.blocking_refs,
+            vec!["refs/heads/backup".to_string(), "refs/tags/v1".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_plan_rejects_entries_still_in_store() {
+        let (_temp_dir, repo, _) = create_test_repo();
+
+        assert!(matches!(
+            plan(&repo, &["github.com".to_string()]),
+            Err(AppError::ValidationError(_))
+        ));
+        assert!(plan(&repo, &["/".to_string()]).is_err());
+        assert!(plan(&repo, &["../etc".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_rewrite_removes_paths_from_every_commit() {
+        let (_temp_dir, repo, first) = create_test_repo();
+        let old_head = repo.head().unwrap().peel_to_commit().unwrap();
+        let paths = vec!["alice".to_string()];
+
+        let (new_head, rewritten) = rewrite(&repo, &paths, copy_commit(&repo)).unwrap();
+        assert_eq!(rewritten, 3);
+
+        let new_head = repo.find_commit(new_head).unwrap();
+        assert_eq!(new_head.tree_id(), old_head.tree_id());
+        assert_eq!(new_head.message(), old_head.message());
+
+        let mut revwalk = repo.revwalk().unwrap();
+        revwalk.push(new_head.id()).unwrap();
+        let history: Vec<Oid> = revwalk.map(|oid| oid.unwrap()).collect();
+        assert_eq!(history.len(), 4);
+        // Commits before the purged paths appeared are kept as they are
+        assert_eq!(*history.last().unwrap(), first);
+        for oid in history {
+            let tree = repo.find_commit(oid).unwrap().tree().unwrap();
+            assert!(matching_files(&tree, &paths).unwrap().is_empty());
+        }
+    }
+}
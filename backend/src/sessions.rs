@@ -0,0 +1,387 @@
+use crate::{
+    config::AuthConfig,
+    error::{AppError, AppResult},
+};
+use chrono::{DateTime, Duration, Utc};
+use rand::RngCore;
+use ring::digest;
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+use uuid::Uuid;
+
+/// Last-seen times are only written when they moved by at least this much,
+/// so busy sessions do not write on every request.
+const LAST_SEEN_RESOLUTION_SECONDS: i64 = 60;
+
+#[derive(Debug, Clone)]
+pub struct SessionPolicy {
+    /// Lifetime from login, however active the session is.
+    pub absolute: Duration,
+    /// Sessions unused for this long expire early.
+    pub idle: Duration,
+}
+
+impl SessionPolicy {
+    pub fn from_config(config: &AuthConfig) -> Self {
+        Self {
+            absolute: Duration::hours(config.session_timeout_hours as i64),
+            idle: Duration::minutes(config.session_idle_timeout_minutes as i64),
+        }
+    }
+}
+
+/// A logged-in device. The token in its cookie is never stored; `id`
+/// names the session when listing and revoking it.
+#[derive(Debug, Clone, Serialize)]
+pub struct Session {
+    pub id: String,
+    pub user_id: String,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    /// When the session ends unless it is used again: the earlier of the
+    /// absolute and the idle timeout.
+    pub expires_at: DateTime<Utc>,
+    /// When the session ends however active it is.
+    pub absolute_expires_at: DateTime<Utc>,
+    /// Last time the user proved their identity in this session, by
+    /// logging in or re-authenticating.
+    pub authenticated_at: DateTime<Utc>,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+/// Sessions kept in the local database, so restarts do not log users out.
+#[derive(Debug)]
+pub struct SessionStore {
+    pool: SqlitePool,
+    policy: SessionPolicy,
+}
+
+impl SessionStore {
+    pub async fn new(pool: SqlitePool, policy: SessionPolicy) -> AppResult<Self> {
+        let store = Self { pool, policy };
+        store.init_schema().await?;
+        Ok(store)
+    }
+
+    async fn init_schema(&self) -> AppResult<()> {
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS sessions (
+                id TEXT PRIMARY KEY,
+                token_hash TEXT NOT NULL UNIQUE,
+                user_id TEXT NOT NULL,
+                created_at TIMESTAMP NOT NULL,
+                last_seen_at TIMESTAMP NOT NULL,
+                absolute_expires_at TIMESTAMP NOT NULL,
+                user_agent TEXT,
+                ip_address TEXT,
+                authenticated_at TIMESTAMP NOT NULL
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_sessions_user ON sessions(user_id);
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Starts a session and returns the token for its cookie.
+    pub async fn create(
+        &self,
+        user_id: &str,
+        user_agent: Option<&str>,
+        ip_address: Option<&str>,
+    ) -> AppResult<(String, Session)> {
+        self.insert(user_id, user_agent, ip_address, true).await
+    }
+
+    /// Starts a session whose user has not proven a factor to Kagikanri,
+    /// such as one asserted by a reverse proxy. Sensitive operations need a
+    /// re-authentication first.
+    pub async fn create_unverified(
+        &self,
+        user_id: &str,
+        user_agent: Option<&str>,
+        ip_address: Option<&str>,
+    ) -> AppResult<(String, Session)> {
+        self.insert(user_id, user_agent, ip_address, false).await
+    }
+
+    async fn insert(
+        &self,
+        user_id: &str,
+        user_agent: Option<&str>,
+        ip_address: Option<&str>,
+        verified: bool,
+    ) -> AppResult<(String, Session)> {
+        self.remove_expired().await?;
+
+        let mut bytes = [0u8; 32];
+        rand::rngs::OsRng.fill_bytes(&mut bytes);
+        let token = hex::encode(bytes);
+
+        let now = Utc::now();
+        let absolute_expires_at = now + self.policy.absolute;
+        sqlx::query(
+            r#"
+            INSERT INTO sessions (
+                id, token_hash, user_id, created_at, last_seen_at, absolute_expires_at, user_agent, ip_address,
+                authenticated_at
+            )
+            VALUES (?1, ?2, ?3, ?4, ?4, ?5, ?6, ?7, ?8)
+            "#,
+        )
+        .bind(Uuid::new_v4().to_string())
+        .bind(hash_token(&token))
+        .bind(user_id)
+        .bind(now)
+        .bind(absolute_expires_at)
+        .bind(user_agent)
+        .bind(ip_address)
+        .bind(if verified { now } else { DateTime::<Utc>::UNIX_EPOCH })
+        .execute(&self.pool)
+        .await?;
+
+        let session = self
+            .get(&token)
+            .await?
+            .ok_or_else(|| AppError::InternalError("Session vanished after creation".to_string()))?;
+        Ok((token, session))
+    }
+
+    /// Session of `token` if it has not expired, marking it as seen now.
+    pub async fn get(&self, token: &str) -> AppResult<Option<Session>> {
+        let row = sqlx::query(&format!("{} WHERE token_hash = ?1", SELECT_SESSIONS))
+            .bind(hash_token(token))
+            .fetch_optional(&self.pool)
+            .await?;
+        let Some(mut session) = row.map(|row| self.row_to_session(&row)) else {
+            return Ok(None);
+        };
+
+        let now = Utc::now();
+        if session.expires_at <= now {
+            return Ok(None);
+        }
+        if now - session.last_seen_at >= Duration::seconds(LAST_SEEN_RESOLUTION_SECONDS) {
+            sqlx::query("UPDATE sessions SET last_seen_at = ?1 WHERE id = ?2")
+                .bind(now)
+                .bind(&session.id)
+                .execute(&self.pool)
+                .await?;
+            session.last_seen_at = now;
+            session.expires_at = session.absolute_expires_at.min(now + self.policy.idle);
+        }
+
+        Ok(Some(session))
+    }
+
+    /// Unexpired sessions of `user_id`, most recently used first.
+    pub async fn list(&self, user_id: &str) -> AppResult<Vec<Session>> {
+        let rows = sqlx::query(&format!(
+            "{} WHERE user_id = ?1 ORDER BY last_seen_at DESC",
+            SELECT_SESSIONS
+        ))
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let now = Utc::now();
+        Ok(rows
+            .iter()
+            .map(|row| self.row_to_session(row))
+            .filter(|session| session.expires_at > now)
+            .collect())
+    }
+
+    /// Records that the user of session `id` just proved their identity.
+    pub async fn mark_authenticated(&self, id: &str) -> AppResult<DateTime<Utc>> {
+        let now = Utc::now();
+        sqlx::query("UPDATE sessions SET authenticated_at = ?1 WHERE id = ?2")
+            .bind(now)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(now)
+    }
+
+    /// Ends the session of `token`, as when logging out.
+    pub async fn remove(&self, token: &str) -> AppResult<()> {
+        sqlx::query("DELETE FROM sessions WHERE token_hash = ?1")
+            .bind(hash_token(token))
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Ends one of `user_id`'s sessions by its ID.
+    pub async fn revoke(&self, user_id: &str, id: &str) -> AppResult<()> {
+        let result = sqlx::query("DELETE FROM sessions WHERE id = ?1 AND user_id = ?2")
+            .bind(id)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("Session not found: {}", id)));
+        }
+        Ok(())
+    }
+
+    /// Ends every session of `user_id` except the one with ID `keep`.
+    /// Returns how many were ended.
+    pub async fn revoke_user(&self, user_id: &str, keep: Option<&str>) -> AppResult<u64> {
+        let result = sqlx::query("DELETE FROM sessions WHERE user_id = ?1 AND id IS NOT ?2")
+            .bind(user_id)
+            .bind(keep)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn remove_expired(&self) -> AppResult<()> {
+        let now = Utc::now();
+        sqlx::query("DELETE FROM sessions WHERE absolute_expires_at <= ?1 OR last_seen_at <= ?2")
+            .bind(now)
+            .bind(now - self.policy.idle)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    fn row_to_session(&self, row: &sqlx::sqlite::SqliteRow) -> Session {
+        let last_seen_at: DateTime<Utc> = row.get("last_seen_at");
+        let absolute_expires_at: DateTime<Utc> = row.get("absolute_expires_at");
+        let created_at: DateTime<Utc> = row.get("created_at");
+
+        Session {
+            id: row.get("id"),
+            user_id: row.get("user_id"),
+            created_at,
+            last_seen_at,
+            expires_at: absolute_expires_at.min(last_seen_at + self.policy.idle),
+            absolute_expires_at,
+            authenticated_at: row.get("authenticated_at"),
+            user_agent: row.get("user_agent"),
+            ip_address: row.get("ip_address"),
+        }
+    }
+}
+
+const SELECT_SESSIONS: &str = "SELECT id, user_id, created_at, last_seen_at, absolute_expires_at, user_agent, \
+     ip_address, authenticated_at FROM sessions";
+
+/// Tokens are random with 256 bits of entropy, so a fast hash suffices to
+/// keep them out of the database.
+fn hash_token(token: &str) -> String {
+    hex::encode(digest::digest(&digest::SHA256, token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    async fn create_test_store() -> SessionStore {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        let policy = SessionPolicy {
+            absolute: Duration::hours(24),
+            idle: Duration::minutes(30),
+        };
+        SessionStore::new(pool, policy).await.unwrap()
+    }
+
+    /// Moves a session's timestamps into the past.
+    async fn age(store: &SessionStore, id: &str, since_login: Duration, since_seen: Duration) {
+        let now = Utc::now();
+        sqlx::query("UPDATE sessions SET created_at = ?1, absolute_expires_at = ?2, last_seen_at = ?3 WHERE id = ?4")
+            .bind(now - since_login)
+            .bind(now - since_login + store.policy.absolute)
+            .bind(now - since_seen)
+            .bind(id)
+            .execute(&store.pool)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_session_records_device() {
+        let store = create_test_store().await;
+        let (token, session) = store.create("alice", Some("Firefox"), Some("192.0.2.1")).await.unwrap();
+
+        assert_ne!(token, session.id);
+        assert_eq!(session.user_agent.as_deref(), Some("Firefox"));
+        assert_eq!(session.ip_address.as_deref(), Some("192.0.2.1"));
+        assert_eq!(session.expires_at, session.last_seen_at + Duration::minutes(30));
+
+        assert_eq!(store.get(&token).await.unwrap().unwrap().id, session.id);
+        assert!(store.get("unknown").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_idle_and_absolute_timeouts() {
+        let store = create_test_store().await;
+
+        let (idle, session) = store.create("alice", None, None).await.unwrap();
+        age(&store, &session.id, Duration::hours(1), Duration::minutes(31)).await;
+        assert!(store.get(&idle).await.unwrap().is_none());
+
+        // Activity keeps a session alive until the absolute timeout
+        let (active, session) = store.create("alice", None, None).await.unwrap();
+        age(&store, &session.id, Duration::hours(1), Duration::minutes(20)).await;
+        let seen = store.get(&active).await.unwrap().unwrap();
+        assert!(seen.last_seen_at > Utc::now() - Duration::minutes(1));
+
+        age(&store, &session.id, Duration::hours(25), Duration::minutes(1)).await;
+        assert!(store.get(&active).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_mark_authenticated() {
+        let store = create_test_store().await;
+        let (token, session) = store.create("alice", None, None).await.unwrap();
+        assert_eq!(session.authenticated_at, session.created_at);
+
+        age(&store, &session.id, Duration::hours(1), Duration::minutes(1)).await;
+        let authenticated_at = store.mark_authenticated(&session.id).await.unwrap();
+        let session = store.get(&token).await.unwrap().unwrap();
+        assert_eq!(session.authenticated_at, authenticated_at);
+        assert!(session.authenticated_at > session.created_at);
+
+        let (token, session) = store.create_unverified("alice", None, None).await.unwrap();
+        assert!(session.authenticated_at < session.created_at);
+        store.mark_authenticated(&session.id).await.unwrap();
+        let session = store.get(&token).await.unwrap().unwrap();
+        assert!(session.authenticated_at >= session.created_at);
+    }
+
+    #[tokio::test]
+    async fn test_revoke_sessions() {
+        let store = create_test_store().await;
+        let (first, current) = store.create("alice", None, None).await.unwrap();
+        let (second, other) = store.create("alice", None, None).await.unwrap();
+        let (third, _) = store.create("alice", None, None).await.unwrap();
+        let (bob, _) = store.create("bob", None, None).await.unwrap();
+        assert_eq!(store.list("alice").await.unwrap().len(), 3);
+
+        // Users can only revoke their own sessions
+        assert!(matches!(store.revoke("bob", &other.id).await, Err(AppError::NotFound(_))));
+        store.revoke("alice", &other.id).await.unwrap();
+        assert!(store.get(&second).await.unwrap().is_none());
+
+        assert_eq!(store.revoke_user("alice", Some(&current.id)).await.unwrap(), 1);
+        assert!(store.get(&first).await.unwrap().is_some());
+        assert!(store.get(&third).await.unwrap().is_none());
+
+        assert_eq!(store.revoke_user("alice", None).await.unwrap(), 1);
+        assert!(store.get(&first).await.unwrap().is_none());
+        assert!(store.get(&bob).await.unwrap().is_some());
+    }
+}
@@ -0,0 +1,197 @@
+use crate::error::{AppError, AppResult};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+use tracing::debug;
+use uuid::Uuid;
+
+/// Outcome of checking a commit signature against the trusted key list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SignatureStatus {
+    /// The commit carries no signature.
+    Unsigned,
+    /// Good signature from a key in the allowlist.
+    Trusted,
+    /// Good signature from a key that is not in the allowlist.
+    Untrusted,
+    /// Signed by a key that is not in the local keyring.
+    UnknownKey,
+    /// The signature does not match the commit, or the key was revoked.
+    Bad,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignatureCheck {
+    pub status: SignatureStatus,
+    pub fingerprint: Option<String>,
+}
+
+/// Creates an ASCII-armored detached signature of `data` with `key_id`.
+pub fn sign(data: &str, key_id: &str) -> AppResult<String> {
+    debug!("Signing commit with key {}", key_id);
+
+    let mut child = Command::new("gpg")
+        .args(["--batch", "--yes", "--armor", "--detach-sign", "--local-user", key_id])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| AppError::GitError(format!("Failed to run gpg: {}", e)))?;
+
+    child
+        .stdin
+        .take()
+        .ok_or_else(|| AppError::GitError("Failed to open gpg stdin".to_string()))?
+        .write_all(data.as_bytes())?;
+
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(AppError::GitError(format!("Failed to sign commit: {}", stderr)));
+    }
+
+    String::from_utf8(output.stdout)
+        .map_err(|e| AppError::GitError(format!("Invalid gpg signature output: {}", e)))
+}
+
+/// Verifies a detached `signature` over `signed_data` and checks the signing
+/// key against `trusted_keys` (fingerprints or long key IDs).
+pub fn verify(signature: &[u8], signed_data: &[u8], trusted_keys: &[String]) -> AppResult<SignatureCheck> {
+    // gpg only reads one of signature and data from stdin, so the signature
+    // goes through a short-lived file
+    let signature_path = std::env::temp_dir().join(format!("kagikanri-{}.sig", Uuid::new_v4()));
+    std::fs::write(&signature_path, signature)?;
+
+    let output = Command::new("gpg")
+        .args(["--batch", "--status-fd", "1", "--verify"])
+        .arg(&signature_path)
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .and_then(|mut child| {
+            if let Some(mut stdin) = child.stdin.take() {
+                stdin.write_all(signed_data)?;
+            }
+            child.wait_with_output()
+        });
+    let _ = std::fs::remove_file(&signature_path);
+
+    let output = output.map_err(|e| AppError::GitError(format!("Failed to run gpg: {}", e)))?;
+    Ok(parse_status_output(&String::from_utf8_lossy(&output.stdout), trusted_keys))
+}
+
+/// Interprets `gpg --status-fd` output for a single signature.
+fn parse_status_output(status_output: &str, trusted_keys: &[String]) -> SignatureCheck {
+    let mut check = SignatureCheck {
+        status: SignatureStatus::Bad,
+        fingerprint: None,
+    };
+
+    for line in status_output.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            ["[GNUPG:]", "BADSIG", ..] | ["[GNUPG:]", "REVKEYSIG", ..] => {
+                return SignatureCheck {
+                    status: SignatureStatus::Bad,
+                    fingerprint: check.fingerprint,
+                };
+            }
+            ["[GNUPG:]", "NO_PUBKEY", key_id, ..] => {
+                check.status = SignatureStatus::UnknownKey;
+                check.fingerprint = Some(key_id.to_string());
+            }
+            ["[GNUPG:]", "VALIDSIG", fingerprint, rest @ ..] => {
+                // The primary key fingerprint is the last field when present
+                let primary = rest.get(8).copied().unwrap_or(fingerprint);
+                let trusted = [*fingerprint, primary]
+                    .iter()
+                    .any(|candidate| is_trusted(candidate, trusted_keys));
+
+                check.fingerprint = Some(primary.to_string());
+                check.status = if trusted {
+                    SignatureStatus::Trusted
+                } else {
+                    SignatureStatus::Untrusted
+                };
+            }
+            _ => {}
+        }
+    }
+
+    check
+}
+
+fn is_trusted(fingerprint: &str, trusted_keys: &[String]) -> bool {
+    let fingerprint = normalize_key_id(fingerprint);
+    trusted_keys.iter().any(|key| {
+        let key = normalize_key_id(key);
+        key.len() >= 16 && fingerprint.ends_with(&key)
+    })
+}
+
+fn normalize_key_id(key: &str) -> String {
+    key.trim_start_matches("0x")
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_uppercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const FINGERPRINT: &str = "0123456789ABCDEF0123456789ABCDEF01234567";
+
+    fn validsig_output(fingerprint: &str) -> String {
+        format!(
+            "[GNUPG:] NEWSIG\n[GNUPG:] GOODSIG 89ABCDEF01234567 Alice <alice@example.com>\n\
+             [GNUPG:] VALIDSIG {fpr} 2026-01-01 1767225600 0 4 0 22 10 00 {fpr}\n",
+            fpr = fingerprint
+        )
+    }
+
+    #[test]
+    fn test_trusted_signature() {
+        let check = parse_status_output(&validsig_output(FINGERPRINT), &[FINGERPRINT.to_lowercase()]);
+        assert_eq!(check.status, SignatureStatus::Trusted);
+        assert_eq!(check.fingerprint.as_deref(), Some(FINGERPRINT));
+    }
+
+    #[test]
+    fn test_trusted_by_long_key_id() {
+        let check = parse_status_output(&validsig_output(FINGERPRINT), &["0x89ABCDEF01234567".to_string()]);
+        assert_eq!(check.status, SignatureStatus::Trusted);
+    }
+
+    #[test]
+    fn test_untrusted_signature() {
+        let check = parse_status_output(&validsig_output(FINGERPRINT), &["FFFFFFFFFFFFFFFF".to_string()]);
+        assert_eq!(check.status, SignatureStatus::Untrusted);
+    }
+
+    #[test]
+    fn test_short_key_ids_are_not_accepted() {
+        let check = parse_status_output(&validsig_output(FINGERPRINT), &["01234567".to_string()]);
+        assert_eq!(check.status, SignatureStatus::Untrusted);
+    }
+
+    #[test]
+    fn test_bad_and_unknown_signatures() {
+        let bad = parse_status_output("[GNUPG:] BADSIG 89ABCDEF01234567 Alice\n", &[FINGERPRINT.to_string()]);
+        assert_eq!(bad.status, SignatureStatus::Bad);
+
+        let unknown = parse_status_output(
+            "[GNUPG:] ERRSIG 89ABCDEF01234567 22 10 00 1767225600 9 -\n[GNUPG:] NO_PUBKEY 89ABCDEF01234567\n",
+            &[FINGERPRINT.to_string()],
+        );
+        assert_eq!(unknown.status, SignatureStatus::UnknownKey);
+        assert_eq!(unknown.fingerprint.as_deref(), Some("89ABCDEF01234567"));
+    }
+}
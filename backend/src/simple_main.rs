@@ -1,13 +1,11 @@
 use axum::{
     extract::{Path, State},
-    http::HeaderMap,
     response::Json,
-    routing::{get, post},
+    routing::get,
     Router,
 };
 use serde_json::json;
 use std::net::SocketAddr;
-use tokio;
 
 #[derive(Clone)]
 struct AppState {}
@@ -1,20 +1,60 @@
 use crate::{
+    api_tokens::{ApiToken, ApiTokenStore},
+    audit::{AuditEvent, AuditLog},
+    auth::AuthService,
     config::Config,
+    credentials::CredentialStore,
     error::{AppError, AppResult},
-    git::GitSync,
+    git::{CommitAuthor, GitSync, StoreChange, SyncStatus},
+    history::Restore,
+    integrity::{IntegrityReport, Severity},
+    login_passkeys::LoginPasskeys,
+    login_throttle::{LoginThrottle, ThrottlePolicy},
+    oidc::OidcClient,
     pass::PassInterface,
     passkey::PasskeyStore,
+    purge::PurgeResult,
+    sessions::{SessionPolicy, SessionStore},
+    sync_actor::SyncHandle,
+    users::{User, UserStore},
+    webhook::RateLimiter,
+};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
 };
-use std::{path::PathBuf, sync::Arc};
 use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+pub use crate::sessions::Session;
+
+/// Authenticated webhook deliveries accepted per minute.
+const WEBHOOK_RATE_LIMIT: u32 = 30;
 
 #[derive(Clone)]
 pub struct AppState {
     pub config: Config,
     pub pass: Arc<PassInterface>,
     pub passkey_store: Arc<PasskeyStore>,
-    pub git_sync: Arc<RwLock<GitSync>>,
-    pub session_store: Arc<RwLock<SessionStore>>,
+    pub credentials: Arc<CredentialStore>,
+    pub users: Arc<UserStore>,
+    pub login_throttle: Arc<LoginThrottle>,
+    /// Passkeys for logging into Kagikanri.
+    pub login_passkeys: Arc<LoginPasskeys>,
+    /// Local repository operations: commits, history and reports.
+    pub git_sync: Arc<GitSync>,
+    /// Pulls and pushes, which run on the sync actor's own thread.
+    pub sync: SyncHandle,
+    pub sessions: Arc<SessionStore>,
+    /// Personal access tokens for scripts and CI.
+    pub api_tokens: Arc<ApiTokenStore>,
+    /// Single sign-on, when an OpenID Connect provider is configured.
+    pub oidc: Option<Arc<OidcClient>>,
+    /// Hash-chained record of logins and secret access.
+    pub audit: Arc<AuditLog>,
+    pub webhook_limiter: Arc<Mutex<RateLimiter>>,
+    /// Result of the most recent store integrity check.
+    pub store_check: Arc<RwLock<Option<IntegrityReport>>>,
 }
 
 impl AppState {
@@ -24,141 +64,328 @@ impl AppState {
         
         // Initialize passkey store with encrypted database
         let passkey_store = Arc::new(PasskeyStore::new(&config.database).await?);
-        
-        // Initialize git sync
-        let git_sync = Arc::new(RwLock::new(GitSync::new(config.git.clone())?));
-        
-        // Initialize session store
-        let session_store = Arc::new(RwLock::new(SessionStore::new()));
+        let credentials = Arc::new(CredentialStore::new(passkey_store.pool()).await?);
+        let users = Arc::new(UserStore::new(passkey_store.pool()).await?);
+        let login_throttle = Arc::new(
+            LoginThrottle::new(passkey_store.pool(), ThrottlePolicy::from_config(&config.auth)).await?,
+        );
+        let login_passkeys = Arc::new(LoginPasskeys::new(&config.auth, passkey_store.pool()).await?);
+        let audit = Arc::new(AuditLog::new(passkey_store.pool()).await?);
+        
+        // Initialize git sync. The actor gets its own clone; both share the
+        // lock that serializes changes to the repository.
+        let git_sync = GitSync::new(config.git.clone(), config.pass.store_dir.clone())?;
+        let interval = Duration::from_secs(config.git.sync_interval_minutes.max(1) * 60);
+        let sync = SyncHandle::spawn(git_sync.clone(), audit.clone(), interval)?;
+        
+        // Sessions share the local database, so they survive restarts
+        let sessions = Arc::new(
+            SessionStore::new(passkey_store.pool(), SessionPolicy::from_config(&config.auth)).await?,
+        );
+        let api_tokens = Arc::new(ApiTokenStore::new(passkey_store.pool()).await?);
+        let oidc = config.auth.oidc.clone().map(|oidc| Arc::new(OidcClient::new(oidc)));
 
         let state = AppState {
             config,
             pass,
             passkey_store,
-            git_sync,
-            session_store,
+            credentials,
+            users,
+            login_throttle,
+            login_passkeys,
+            git_sync: Arc::new(git_sync),
+            sync,
+            sessions,
+            api_tokens,
+            oidc,
+            audit,
+            webhook_limiter: Arc::new(Mutex::new(RateLimiter::new(WEBHOOK_RATE_LIMIT, Duration::from_secs(60)))),
+            store_check: Arc::new(RwLock::new(None)),
         };
 
-        // Perform initial git sync
-        state.sync_git().await?;
+        // Perform initial git sync. An unreachable remote is not fatal: the
+        // server starts degraded and keeps retrying in the background.
+        if let Err(e) = state.sync.sync_now("startup", AuditEvent::default()).await {
+            warn!("Initial git sync failed, starting in degraded mode: {}", e);
+        }
+        state.spawn_store_checks();
 
         Ok(state)
     }
 
-    pub async fn sync_git(&self) -> AppResult<()> {
-        let mut git_sync = self.git_sync.write().await;
-        git_sync.sync().await?;
-        Ok(())
+    pub fn auth_service(&self) -> AuthService {
+        AuthService::new(
+            self.config.auth.clone(),
+            self.pass.clone(),
+            self.credentials.clone(),
+            self.users.clone(),
+        )
     }
 
-    pub async fn is_authenticated(&self, session_id: &str) -> bool {
-        let session_store = self.session_store.read().await;
-        session_store.is_valid(session_id)
+    /// Account behind a session. Sessions of disabled or deleted users are
+    /// ended when that happens, so this only fails in between.
+    pub async fn current_user(&self, session: &Session) -> AppResult<User> {
+        self.users
+            .get_active(&session.user_id)
+            .await?
+            .ok_or_else(|| AppError::AuthenticationFailed("Account is no longer active".to_string()))
     }
 
-    pub async fn create_session(&self, user_id: &str) -> String {
-        let mut session_store = self.session_store.write().await;
-        session_store.create_session(user_id)
+    pub async fn require_admin(&self, session: &Session) -> AppResult<User> {
+        let user = self.current_user(session).await?;
+        if !user.is_admin() {
+            return Err(AppError::AuthorizationFailed("Only admins can do this".to_string()));
+        }
+        Ok(user)
     }
 
-    pub async fn remove_session(&self, session_id: &str) {
-        let mut session_store = self.session_store.write().await;
-        session_store.remove_session(session_id);
+    pub fn oidc(&self) -> AppResult<&OidcClient> {
+        self.oidc
+            .as_deref()
+            .ok_or_else(|| AppError::NotFound("OpenID Connect is not configured".to_string()))
     }
-}
 
-use chrono::{DateTime, Utc};
-use std::collections::HashMap;
-use uuid::Uuid;
+    /// Sensitive operations need the user to have logged in or
+    /// re-authenticated within the configured window.
+    pub fn require_recent_auth(&self, session: &Session) -> AppResult<()> {
+        let window = chrono::Duration::minutes(self.config.auth.reauth_window_minutes as i64);
+        if chrono::Utc::now() - session.authenticated_at > window {
+            return Err(AppError::ReauthRequired(
+                "Re-enter your master password, a TOTP code or a passkey to continue".to_string(),
+            ));
+        }
+        Ok(())
+    }
 
-pub struct SessionStore {
-    sessions: HashMap<String, Session>,
-}
+    /// Like `require_recent_auth`, for operations on the whole store such
+    /// as rollbacks and purges. Only a user re-authenticating in a browser
+    /// session counts; an API token never does, however it is scoped.
+    pub fn require_interactive_auth(&self, session: &Session, api_token: Option<&ApiToken>) -> AppResult<()> {
+        if api_token.is_some() {
+            return Err(AppError::AuthorizationFailed(
+                "API tokens cannot change the whole store".to_string(),
+            ));
+        }
+        self.require_recent_auth(session)
+    }
 
-#[derive(Debug, Clone)]
-pub struct Session {
-    pub user_id: String,
-    pub created_at: DateTime<Utc>,
-    pub expires_at: DateTime<Utc>,
-}
+    /// Removes an account with its credentials, sessions and tokens. Its
+    /// entries stay in the store, where an admin can move or purge them.
+    pub async fn delete_user(&self, user_id: &str, session: &Session) -> AppResult<()> {
+        self.users.delete(user_id).await?;
+        self.credentials.delete_user(user_id).await?;
+        self.login_passkeys.delete_user(user_id).await?;
+        self.remove_user_sessions(user_id).await?;
+        self.api_tokens.delete_user(user_id).await?;
 
-impl Default for SessionStore {
-    fn default() -> Self {
-        Self::new()
+        warn!("User {} deleted by {}", user_id, session.user_id);
+        Ok(())
+    }
+
+    /// Commit identity of a user: the configured author name with the
+    /// user's email, and their ID in a trailer. Users without an email,
+    /// such as the bootstrap admin, commit with the configured email.
+    async fn commit_author(&self, user_id: &str) -> AppResult<CommitAuthor> {
+        let email = self.users.get(user_id).await?.and_then(|user| user.email);
+        let default = self.git_sync.default_author();
+
+        Ok(CommitAuthor {
+            name: default.name,
+            email: email.unwrap_or(default.email),
+            user_id: Some(user_id.to_string()),
+        })
+    }
+
+    /// Runs the store integrity check now and keeps the result for `/api/store/check?latest=true`.
+    pub async fn run_store_check(&self) -> AppResult<IntegrityReport> {
+        let report = self.with_repository(|git_sync| git_sync.check_integrity()).await?;
+        *self.store_check.write().await = Some(report.clone());
+        Ok(report)
     }
-}
 
-impl SessionStore {
-    pub fn new() -> Self {
-        Self {
-            sessions: HashMap::new(),
+    fn spawn_store_checks(&self) {
+        let hours = self.config.git.store_check_interval_hours;
+        if hours == 0 {
+            return;
         }
+
+        let state = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match state.run_store_check().await {
+                    Ok(report) => info!(
+                        "Store check finished with {} critical and {} warning findings",
+                        report.count(Severity::Critical),
+                        report.count(Severity::Warning)
+                    ),
+                    Err(e) => warn!("Store check failed: {}", e),
+                }
+                tokio::time::sleep(Duration::from_secs(hours * 60 * 60)).await;
+            }
+        });
     }
 
-    pub fn create_session(&mut self, user_id: &str) -> String {
-        let session_id = Uuid::new_v4().to_string();
-        let now = Utc::now();
-        let expires_at = now + chrono::Duration::hours(24); // TODO: Use config
+    /// Asks the sync actor to push the latest changes, without waiting for it.
+    pub fn request_sync(&self) {
+        self.sync.notify_changed();
+    }
 
-        let session = Session {
-            user_id: user_id.to_string(),
-            created_at: now,
-            expires_at,
-        };
+    /// Sync status with pending counts as of now, so commits made since
+    /// the last sync are included.
+    pub fn sync_status(&self) -> SyncStatus {
+        self.git_sync.with_pending_counts(self.sync.status())
+    }
 
-        self.sessions.insert(session_id.clone(), session);
-        
-        // Clean up expired sessions
-        self.cleanup_expired();
-        
-        session_id
+    /// True while the last sync with the primary remote failed.
+    pub fn is_degraded(&self) -> bool {
+        self.sync.status().error.is_some()
     }
 
-    pub fn is_valid(&self, session_id: &str) -> bool {
-        if let Some(session) = self.sessions.get(session_id) {
-            session.expires_at > Utc::now()
-        } else {
-            false
-        }
+    /// Commits a store change on behalf of the session's user.
+    pub async fn commit_change(&self, change: StoreChange, session: &Session) -> AppResult<Option<String>> {
+        self.commit_change_as(change, &session.user_id).await
+    }
+
+    /// Commits a store change on behalf of `user_id`, for changes made
+    /// before the user has a session.
+    pub async fn commit_change_as(&self, change: StoreChange, user_id: &str) -> AppResult<Option<String>> {
+        let author = self.commit_author(user_id).await?;
+        self.with_repository(move |git_sync| git_sync.commit_change(&change, &author))
+            .await
+    }
+
+    pub async fn revert_commit(&self, commit: &str, session: &Session) -> AppResult<Restore> {
+        let author = self.commit_author(&session.user_id).await?;
+        let commit = commit.to_string();
+        self.with_repository(move |git_sync| git_sync.revert(&commit, &author))
+            .await
+    }
+
+    pub async fn rollback_to(&self, commit: &str, session: &Session) -> AppResult<Restore> {
+        let author = self.commit_author(&session.user_id).await?;
+        let commit = commit.to_string();
+        self.with_repository(move |git_sync| git_sync.rollback(&commit, &author))
+            .await
+    }
+
+    /// Purges `paths` from history and force-pushes the result. Store
+    /// writes wait until the pushes are done.
+    pub async fn purge_history(
+        &self,
+        paths: Vec<String>,
+        confirmation_token: String,
+        session: &Session,
+    ) -> AppResult<PurgeResult> {
+        warn!("History purge of {} requested by {}", paths.join(", "), session.user_id);
+        self.with_repository(move |git_sync| git_sync.purge(&paths, &confirmation_token))
+            .await
+    }
+
+    /// Runs repository work on the blocking pool, so history walks and gpg
+    /// calls stay off the async runtime. Changes may wait for the sync actor
+    /// to finish merging, but never for network I/O.
+    pub async fn with_repository<T, F>(&self, f: F) -> AppResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&GitSync) -> AppResult<T> + Send + 'static,
+    {
+        let git_sync = self.git_sync.clone();
+        tokio::task::spawn_blocking(move || f(&git_sync))
+            .await
+            .map_err(|e| AppError::InternalError(format!("Repository task failed: {}", e)))?
+    }
+
+    /// Starts a session for `user_id` and returns its cookie token.
+    pub async fn create_session(
+        &self,
+        user_id: &str,
+        user_agent: Option<&str>,
+        ip_address: Option<&str>,
+    ) -> AppResult<(String, Session)> {
+        self.sessions.create(user_id, user_agent, ip_address).await
     }
 
-    pub fn remove_session(&mut self, session_id: &str) {
-        self.sessions.remove(session_id);
+    /// Session cookie for the whole site; the server ends idle sessions
+    /// earlier than its lifetime.
+    pub fn session_cookie(&self, token: &str) -> String {
+        format!(
+            "session={}; HttpOnly; Secure; SameSite=Strict; Path=/; Max-Age={}",
+            token,
+            self.config.auth.session_timeout_hours * 3600
+        )
     }
 
-    pub fn get_session(&self, session_id: &str) -> Option<&Session> {
-        self.sessions.get(session_id)
+    pub async fn get_session(&self, token: &str) -> AppResult<Option<Session>> {
+        self.sessions.get(token).await
     }
 
-    fn cleanup_expired(&mut self) {
-        let now = Utc::now();
-        self.sessions.retain(|_, session| session.expires_at > now);
+    pub async fn remove_session(&self, token: &str) -> AppResult<()> {
+        self.sessions.remove(token).await
+    }
+
+    pub async fn remove_user_sessions(&self, user_id: &str) -> AppResult<u64> {
+        self.sessions.revoke_user(user_id, None).await
     }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
     use crate::config::{AuthConfig, DatabaseConfig, GitConfig, PassConfig, ServerConfig};
+    use axum::http::{header, HeaderValue, Method};
+    use axum_test::{TestRequest, TestServer};
     use pretty_assertions::assert_eq;
-    use std::{path::PathBuf, sync::Arc};
+    use std::path::PathBuf;
     use tempfile::TempDir;
-    use tokio_test;
+
+    /// Sends requests through the whole router like a same-origin browser
+    /// with a fresh session of one user.
+    pub(crate) struct TestBrowser {
+        server: TestServer,
+        cookie: HeaderValue,
+    }
+
+    impl TestBrowser {
+        pub(crate) async fn login(state: &AppState, user_id: &str) -> Self {
+            let (token, _) = state.create_session(user_id, None, None).await.unwrap();
+            Self {
+                server: TestServer::new(crate::create_router(state.clone())).unwrap(),
+                cookie: HeaderValue::from_str(&format!("session={}", token)).unwrap(),
+            }
+        }
+
+        pub(crate) fn request(&self, method: Method, path: &str) -> TestRequest {
+            self.server
+                .method(method, path)
+                .add_header(header::COOKIE, self.cookie.clone())
+                .add_header(header::HeaderName::from_static("sec-fetch-site"), HeaderValue::from_static("same-origin"))
+        }
+    }
 
     async fn create_test_app_state() -> AppResult<(AppState, TempDir)> {
+        create_test_app_state_with(|_| {}).await
+    }
+
+    /// App state on a local database and an unreachable remote, with
+    /// `configure` applied to the test configuration.
+    pub(crate) async fn create_test_app_state_with(
+        configure: impl FnOnce(&mut Config),
+    ) -> AppResult<(AppState, TempDir)> {
         let temp_dir = TempDir::new().expect("Failed to create temp directory");
         let temp_path = temp_dir.path().to_string_lossy().to_string();
 
-        let config = Config {
+        let mut config = Config {
             server: ServerConfig {
                 host: "127.0.0.1".to_string(),
                 port: 8080,
                 log_level: "info".to_string(),
+                ..ServerConfig::default()
             },
             auth: AuthConfig {
                 master_password_path: "test/master-password".to_string(),
                 totp_path: "test/totp".to_string(),
                 session_timeout_hours: 1,
+                ..AuthConfig::default()
             },
             pass: PassConfig {
                 store_dir: PathBuf::from(format!("{}/password-store", temp_path)),
@@ -168,6 +395,7 @@ mod tests {
                 repo_url: "https://github.com/test/test-passwords.git".to_string(),
                 access_token: "test-token".to_string(),
                 sync_interval_minutes: 5,
+                ..GitConfig::default()
             },
             database: DatabaseConfig {
                 url: "sqlite::memory:".to_string(), // Use in-memory SQLite for tests
@@ -175,138 +403,18 @@ mod tests {
             },
         };
 
+        configure(&mut config);
+
         let state = AppState::new(config).await?;
         Ok((state, temp_dir))
     }
 
-    #[test]
-    fn test_session_store_new() {
-        let session_store = SessionStore::new();
-        assert!(session_store.sessions.is_empty());
-    }
-
-    #[test]
-    fn test_session_store_default() {
-        let session_store = SessionStore::default();
-        assert!(session_store.sessions.is_empty());
-    }
-
-    #[test]
-    fn test_create_session() {
-        let mut session_store = SessionStore::new();
-        let session_id = session_store.create_session("test_user");
-        
-        assert!(!session_id.is_empty());
-        assert!(session_store.sessions.contains_key(&session_id));
-        
-        let session = session_store.sessions.get(&session_id).unwrap();
-        assert_eq!(session.user_id, "test_user");
-        assert!(session.expires_at > session.created_at);
-    }
-
-    #[test]
-    fn test_create_multiple_sessions() {
-        let mut session_store = SessionStore::new();
-        let session_id1 = session_store.create_session("user1");
-        let session_id2 = session_store.create_session("user2");
-        
-        assert_ne!(session_id1, session_id2);
-        assert_eq!(session_store.sessions.len(), 2);
-        
-        let session1 = session_store.sessions.get(&session_id1).unwrap();
-        let session2 = session_store.sessions.get(&session_id2).unwrap();
-        
-        assert_eq!(session1.user_id, "user1");
-        assert_eq!(session2.user_id, "user2");
-    }
-
-    #[test]
-    fn test_is_valid_session() {
-        let mut session_store = SessionStore::new();
-        let session_id = session_store.create_session("test_user");
-        
-        // Valid session should return true
-        assert!(session_store.is_valid(&session_id));
-        
-        // Invalid session should return false
-        assert!(!session_store.is_valid("invalid_session_id"));
-    }
-
-    #[test]
-    fn test_remove_session() {
-        let mut session_store = SessionStore::new();
-        let session_id = session_store.create_session("test_user");
-        
-        assert!(session_store.is_valid(&session_id));
-        
-        session_store.remove_session(&session_id);
-        
-        assert!(!session_store.is_valid(&session_id));
-        assert!(!session_store.sessions.contains_key(&session_id));
-    }
-
-    #[test]
-    fn test_get_session() {
-        let mut session_store = SessionStore::new();
-        let session_id = session_store.create_session("test_user");
-        
-        let session = session_store.get_session(&session_id);
-        assert!(session.is_some());
-        assert_eq!(session.unwrap().user_id, "test_user");
-        
-        let invalid_session = session_store.get_session("invalid_id");
-        assert!(invalid_session.is_none());
-    }
-
-    #[test]
-    fn test_cleanup_expired_sessions() {
-        let mut session_store = SessionStore::new();
-        
-        // Create a session manually with expired time
-        let session_id = uuid::Uuid::new_v4().to_string();
-        let expired_session = Session {
-            user_id: "expired_user".to_string(),
-            created_at: Utc::now() - chrono::Duration::hours(2),
-            expires_at: Utc::now() - chrono::Duration::hours(1), // Expired 1 hour ago
-        };
-        session_store.sessions.insert(session_id.clone(), expired_session);
-        
-        // Before creating valid session, assert we have the expired one
-        assert_eq!(session_store.sessions.len(), 1);
-        assert!(session_store.sessions.contains_key(&session_id));
-        
-        // Create a valid session (this will trigger cleanup_expired internally)
-        let valid_session_id = session_store.create_session("valid_user");
-        
-        // After creating session, expired one should be cleaned up automatically
-        assert_eq!(session_store.sessions.len(), 1);
-        assert!(!session_store.sessions.contains_key(&session_id));
-        assert!(session_store.sessions.contains_key(&valid_session_id));
-    }
-
-    #[test]
-    fn test_expired_session_validation() {
-        let mut session_store = SessionStore::new();
-        
-        // Create an expired session manually
-        let session_id = uuid::Uuid::new_v4().to_string();
-        let expired_session = Session {
-            user_id: "expired_user".to_string(),
-            created_at: Utc::now() - chrono::Duration::hours(2),
-            expires_at: Utc::now() - chrono::Duration::hours(1),
-        };
-        session_store.sessions.insert(session_id.clone(), expired_session);
-        
-        // Expired session should not be valid
-        assert!(!session_store.is_valid(&session_id));
-    }
-
     #[tokio::test]
     async fn test_app_state_creation() {
         match create_test_app_state().await {
             Ok((state, _temp_dir)) => {
                 // Verify all components are initialized  
-                assert!(state.session_store.read().await.sessions.is_empty());
+                assert!(state.sessions.list("test_user").await.unwrap().is_empty());
             }
             Err(_) => {
                 // Skip test in read-only environments (expected in CI/test environments)
@@ -326,16 +434,17 @@ mod tests {
         };
         
         // Test session creation
-        let session_id = state.create_session("test_user").await;
-        assert!(!session_id.is_empty());
+        let (token, session) = state.create_session("test_user", Some("curl"), None).await.unwrap();
+        assert!(!token.is_empty());
+        assert_eq!(session.user_id, "test_user");
         
         // Test session validation
-        assert!(state.is_authenticated(&session_id).await);
-        assert!(!state.is_authenticated("invalid_session").await);
+        assert!(state.get_session(&token).await.unwrap().is_some());
+        assert!(state.get_session("invalid_session").await.unwrap().is_none());
         
         // Test session removal
-        state.remove_session(&session_id).await;
-        assert!(!state.is_authenticated(&session_id).await);
+        state.remove_session(&token).await.unwrap();
+        assert!(state.get_session(&token).await.unwrap().is_none());
     }
 
     #[tokio::test]
@@ -354,8 +463,8 @@ mod tests {
                 let state = state.clone();
                 tokio::spawn(async move {
                     let user_id = format!("user_{}", i);
-                    let session_id = state.create_session(&user_id).await;
-                    (session_id, user_id)
+                    let (token, _) = state.create_session(&user_id, None, None).await.unwrap();
+                    (token, user_id)
                 })
             })
             .collect();
@@ -370,8 +479,8 @@ mod tests {
         assert_eq!(results.len(), 10);
         
         // Verify all sessions are valid
-        for (session_id, _) in &results {
-            assert!(state.is_authenticated(session_id).await);
+        for (token, _) in &results {
+            assert!(state.get_session(token).await.unwrap().is_some());
         }
         
         // Verify all session IDs are unique
@@ -391,20 +500,50 @@ mod tests {
             }
         };
         
-        let session_id = state.create_session("test_user").await;
+        let (token, session) = state.create_session("test_user", None, None).await.unwrap();
         
-        // Session should be valid immediately
-        assert!(state.is_authenticated(&session_id).await);
+        // Session should be valid immediately, and expire no later than
+        // the configured absolute timeout
+        assert!(state.get_session(&token).await.unwrap().is_some());
+        assert!(session.absolute_expires_at <= session.created_at + chrono::Duration::hours(1));
         
-        // Manually expire the session by modifying it (this is a test-only operation)
-        {
-            let mut session_store = state.session_store.write().await;
-            if let Some(session) = session_store.sessions.get_mut(&session_id) {
-                session.expires_at = Utc::now() - chrono::Duration::minutes(1);
+        // Ending all of the user's sessions invalidates it
+        assert_eq!(state.remove_user_sessions("test_user").await.unwrap(), 1);
+        assert!(state.get_session(&token).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_require_recent_auth() {
+        let (state, _temp_dir) = match create_test_app_state().await {
+            Ok(result) => result,
+            Err(_) => {
+                println!("Skipping recent authentication test - filesystem constraints");
+                return;
             }
-        }
+        };
+        
+        let (_, mut session) = state.create_session("test_user", None, None).await.unwrap();
+        
+        // Logging in counts as authenticating
+        assert!(state.require_recent_auth(&session).is_ok());
         
-        // Session should now be invalid
-        assert!(!state.is_authenticated(&session_id).await);
+        // Outside the window the user has to re-authenticate
+        session.authenticated_at -= chrono::Duration::minutes(state.config.auth.reauth_window_minutes as i64 + 1);
+        assert!(matches!(state.require_recent_auth(&session), Err(AppError::ReauthRequired(_))));
+    }
+
+    #[tokio::test]
+    async fn test_api_tokens_never_change_the_whole_store() {
+        let (state, _temp_dir) = create_test_app_state().await.unwrap();
+        let request = serde_json::from_value(serde_json::json!({"name": "ci"})).unwrap();
+        let (_, token) = state.api_tokens.create("user", request).await.unwrap();
+
+        // Token sessions count as fresh for entries, but not for the store
+        let session = token.session(None);
+        assert!(state.require_recent_auth(&session).is_ok());
+        assert!(matches!(
+            state.require_interactive_auth(&session, Some(&token)),
+            Err(AppError::AuthorizationFailed(_))
+        ));
     }
 }
\ No newline at end of file
@@ -0,0 +1,369 @@
+use crate::{
+    audit::{AuditAction, AuditEvent, AuditLog},
+    error::{AppError, AppResult},
+    git::{GitSync, SyncStatus},
+};
+use std::{
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use tokio::{
+    runtime::Handle,
+    sync::{oneshot, watch},
+};
+use tracing::{info, warn};
+
+/// Quiet period after a store write before pushing, so bursts of writes
+/// go out in one push.
+const DEBOUNCE: Duration = Duration::from_secs(2);
+/// Upper bound on how long a steady stream of writes can delay a push.
+const DEBOUNCE_MAX: Duration = Duration::from_secs(30);
+/// First retry delay for a failed sync; doubles up to the cap.
+const SYNC_RETRY_INITIAL: Duration = Duration::from_secs(15);
+const SYNC_RETRY_MAX: Duration = Duration::from_secs(10 * 60);
+
+enum SyncRequest {
+    /// The store changed; sync once writes have settled.
+    Changed,
+    /// Sync right away.
+    Now(Requested),
+}
+
+/// An explicit sync, audited with what asked for it and on whose behalf.
+struct Requested {
+    trigger: &'static str,
+    event: AuditEvent,
+    /// Receives the result, unless the requester does not wait for it.
+    reply: Option<oneshot::Sender<AppResult<SyncStatus>>>,
+}
+
+/// Handle to the git sync actor. libgit2 network and disk I/O is blocking,
+/// so all syncs run on one dedicated thread rather than on the async
+/// runtime, and callers never wait on a push unless they ask to.
+#[derive(Debug, Clone)]
+pub struct SyncHandle {
+    requests: mpsc::Sender<SyncRequest>,
+    status: watch::Receiver<SyncStatus>,
+}
+
+impl SyncHandle {
+    /// Starts the actor. Besides explicit requests it syncs every `interval`,
+    /// or with exponential backoff while the last sync failed. Every sync
+    /// and its outcome is appended to `audit`. The thread exits once every
+    /// handle has been dropped. Must be called within the Tokio runtime.
+    pub fn spawn(git_sync: GitSync, audit: Arc<AuditLog>, interval: Duration) -> AppResult<Self> {
+        let (requests, receiver) = mpsc::channel();
+        let (status_sender, status) = watch::channel(git_sync.get_status());
+        let runtime = Handle::current();
+
+        thread::Builder::new()
+            .name("git-sync".to_string())
+            .spawn(move || run(git_sync, receiver, status_sender, interval, Auditor { runtime, audit }))
+            .map_err(|e| AppError::InternalError(format!("Failed to start git sync thread: {}", e)))?;
+
+        Ok(Self { requests, status })
+    }
+
+    /// Schedules a debounced sync after a store write.
+    pub fn notify_changed(&self) {
+        if self.requests.send(SyncRequest::Changed).is_err() {
+            warn!("Git sync actor has stopped, change will not be pushed");
+        }
+    }
+
+    /// Syncs immediately and waits for the result. The sync is audited as
+    /// `trigger`, attributed like `event`.
+    pub async fn sync_now(&self, trigger: &'static str, event: AuditEvent) -> AppResult<SyncStatus> {
+        let (reply, result) = oneshot::channel();
+        self.send_now(trigger, event, Some(reply))?;
+
+        result
+            .await
+            .map_err(|_| AppError::InternalError("Git sync actor has stopped".to_string()))?
+    }
+
+    /// Syncs immediately without waiting for the result, e.g. to pull a
+    /// push announced by the git host.
+    pub fn request_now(&self, trigger: &'static str, event: AuditEvent) -> AppResult<()> {
+        self.send_now(trigger, event, None)
+    }
+
+    fn send_now(
+        &self,
+        trigger: &'static str,
+        event: AuditEvent,
+        reply: Option<oneshot::Sender<AppResult<SyncStatus>>>,
+    ) -> AppResult<()> {
+        self.requests
+            .send(SyncRequest::Now(Requested { trigger, event, reply }))
+            .map_err(|_| AppError::InternalError("Git sync actor has stopped".to_string()))
+    }
+
+    /// Status as of the most recent sync.
+    pub fn status(&self) -> SyncStatus {
+        self.status.borrow().clone()
+    }
+
+    /// Receiver that is notified whenever the sync status changes.
+    pub fn subscribe(&self) -> watch::Receiver<SyncStatus> {
+        self.status.clone()
+    }
+}
+
+/// Appends sync records from the actor thread, which is outside the
+/// runtime the audit log's database runs on.
+struct Auditor {
+    runtime: Handle,
+    audit: Arc<AuditLog>,
+}
+
+impl Auditor {
+    fn record(&self, trigger: &str, event: AuditEvent, result: &AppResult<SyncStatus>) {
+        let detail = match result {
+            Ok(_) => format!("{}: succeeded", trigger),
+            Err(e) => format!("{}: failed: {}", trigger, e),
+        };
+        let event = AuditEvent {
+            detail: Some(detail),
+            ..event
+        };
+        // The sync has happened either way, so a failure is only logged
+        if let Err(e) = self.runtime.block_on(self.audit.append(AuditAction::Sync, event)) {
+            warn!("Failed to audit git sync: {}", e);
+        }
+    }
+}
+
+fn run(
+    mut git_sync: GitSync,
+    requests: Receiver<SyncRequest>,
+    status: watch::Sender<SyncStatus>,
+    interval: Duration,
+    auditor: Auditor,
+) {
+    let mut failing = false;
+    let mut retry_delay = SYNC_RETRY_INITIAL;
+
+    loop {
+        let wait = if failing { retry_delay } else { interval };
+        let mut requested = Vec::new();
+
+        let trigger = match requests.recv_timeout(wait) {
+            Ok(SyncRequest::Now(request)) => {
+                requested.push(request);
+                "requested"
+            }
+            Ok(SyncRequest::Changed) => {
+                debounce(&requests, &mut requested);
+                "store changed"
+            }
+            Err(RecvTimeoutError::Timeout) if failing => "retry",
+            Err(RecvTimeoutError::Timeout) => "scheduled",
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+
+        // Anything queued meanwhile is covered by this sync
+        while let Ok(request) = requests.try_recv() {
+            if let SyncRequest::Now(request) = request {
+                requested.push(request);
+            }
+        }
+
+        status.send_modify(|status| status.is_syncing = true);
+        let result = git_sync.sync();
+        status.send_replace(git_sync.get_status());
+        // One record per request the sync served, or one for the sync itself
+        if requested.is_empty() {
+            auditor.record(trigger, AuditEvent::default(), &result);
+        }
+
+        match &result {
+            Ok(_) => {
+                if failing {
+                    info!("Git sync recovered");
+                }
+                failing = false;
+                retry_delay = SYNC_RETRY_INITIAL;
+            }
+            Err(e) => {
+                if failing {
+                    retry_delay = (retry_delay * 2).min(SYNC_RETRY_MAX);
+                }
+                failing = true;
+                warn!("Git sync failed, retrying in {:?}: {}", retry_delay, e);
+            }
+        }
+
+        for request in requested {
+            auditor.record(request.trigger, request.event, &result);
+            if let Some(reply) = request.reply {
+                let result = match &result {
+                    Ok(status) => Ok(status.clone()),
+                    Err(e) => Err(AppError::GitError(e.to_string())),
+                };
+                let _ = reply.send(result);
+            }
+        }
+    }
+}
+
+/// Waits until writes stop arriving for `DEBOUNCE`, up to `DEBOUNCE_MAX`.
+/// An explicit sync request ends the wait early.
+fn debounce(requests: &Receiver<SyncRequest>, requested: &mut Vec<Requested>) {
+    let deadline = Instant::now() + DEBOUNCE_MAX;
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match requests.recv_timeout(DEBOUNCE.min(remaining)) {
+            Ok(SyncRequest::Changed) if !remaining.is_zero() => {}
+            Ok(SyncRequest::Changed) => return,
+            Ok(SyncRequest::Now(request)) => {
+                requested.push(request);
+                return;
+            }
+            Err(_) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{audit::AuditQuery, config::GitConfig};
+    use git2::{Repository, Signature};
+    use sqlx::SqlitePool;
+    use std::{fs, path::Path};
+    use tempfile::TempDir;
+
+    fn commit_file(repo: &Repository, name: &str, content: &str) {
+        fs::write(repo.workdir().unwrap().join(name), content).unwrap();
+
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new(name)).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        let parent = repo.head().ok().map(|head| head.peel_to_commit().unwrap());
+        let parents: Vec<_> = parent.iter().collect();
+        repo.commit(Some("HEAD"), &signature, &signature, "Update", &tree, &parents)
+            .unwrap();
+    }
+
+    /// A bare remote with one commit and a local clone to sync.
+    fn create_synced_clone(temp_dir: &TempDir) -> (Repository, Repository, GitSync) {
+        let remote_path = temp_dir.path().join("remote.git");
+        let remote = Repository::init_bare(&remote_path).unwrap();
+
+        let seed = Repository::init(temp_dir.path().join("seed")).unwrap();
+        commit_file(&seed, "github.com.gpg", "ciphertext");
+        let head = seed.head().unwrap().name().unwrap().to_string();
+        seed.remote("origin", remote_path.to_str().unwrap())
+            .unwrap()
+            .push(&[format!("{}:{}", head, head)], None)
+            .unwrap();
+
+        let local_path = temp_dir.path().join("local");
+        let local = Repository::clone(remote_path.to_str().unwrap(), &local_path).unwrap();
+        let config = GitConfig {
+            repo_url: remote_path.to_string_lossy().to_string(),
+            ..GitConfig::default()
+        };
+        (remote, local, GitSync::new(config, local_path).unwrap())
+    }
+
+    async fn create_test_audit() -> Arc<AuditLog> {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        Arc::new(AuditLog::new(pool).await.unwrap())
+    }
+
+    async fn sync_details(audit: &AuditLog) -> Vec<String> {
+        let query = AuditQuery {
+            action: Some(AuditAction::Sync),
+            ..AuditQuery::default()
+        };
+        let page = audit.list(&query).await.unwrap();
+        page.records.into_iter().filter_map(|record| record.detail).collect()
+    }
+
+    fn requested_by(user_id: &str) -> AuditEvent {
+        AuditEvent {
+            user_id: Some(user_id.to_string()),
+            ..AuditEvent::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sync_now_reports_status() {
+        let temp_dir = TempDir::new().unwrap();
+        let (remote, local, git_sync) = create_synced_clone(&temp_dir);
+        let audit = create_test_audit().await;
+        let handle = SyncHandle::spawn(git_sync, audit.clone(), Duration::from_secs(3600)).unwrap();
+
+        commit_file(&local, "gitlab.com.gpg", "ciphertext");
+        let status = handle.sync_now("manual", requested_by("alice")).await.unwrap();
+
+        assert!(status.error.is_none());
+        assert!(status.last_sync.is_some());
+        assert_eq!(handle.status().last_commit, status.last_commit);
+
+        let local_head = local.head().unwrap().target().unwrap();
+        let remote_head = remote.head().unwrap().target().unwrap();
+        assert_eq!(local_head, remote_head);
+        // Recorded once, for whoever asked
+        let query = AuditQuery {
+            action: Some(AuditAction::Sync),
+            ..AuditQuery::default()
+        };
+        let records = audit.list(&query).await.unwrap().records;
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].user_id.as_deref(), Some("alice"));
+        assert_eq!(records[0].detail.as_deref(), Some("manual: succeeded"));
+    }
+
+    #[tokio::test]
+    async fn test_changes_are_pushed_after_debounce() {
+        let temp_dir = TempDir::new().unwrap();
+        let (remote, local, git_sync) = create_synced_clone(&temp_dir);
+        let handle = SyncHandle::spawn(git_sync, create_test_audit().await, Duration::from_secs(3600)).unwrap();
+        let mut status = handle.subscribe();
+
+        // A burst of writes produces a single sync once they settle
+        for n in 0..3 {
+            commit_file(&local, "gitlab.com.gpg", &n.to_string());
+            handle.notify_changed();
+        }
+
+        tokio::time::timeout(
+            DEBOUNCE * 3,
+            status.wait_for(|status| status.last_sync.is_some()),
+        )
+        .await
+        .expect("debounced sync did not run")
+        .unwrap();
+
+        let local_head = local.head().unwrap().target().unwrap();
+        let remote_head = remote.head().unwrap().target().unwrap();
+        assert_eq!(local_head, remote_head);
+    }
+
+    #[tokio::test]
+    async fn test_failed_sync_is_reported() {
+        let temp_dir = TempDir::new().unwrap();
+        let (_remote, _local, git_sync) = create_synced_clone(&temp_dir);
+        fs::remove_dir_all(temp_dir.path().join("remote.git")).unwrap();
+
+        let audit = create_test_audit().await;
+        let handle = SyncHandle::spawn(git_sync, audit.clone(), Duration::from_secs(3600)).unwrap();
+        assert!(handle.sync_now("manual", requested_by("alice")).await.is_err());
+        assert!(handle.status().error.is_some());
+        assert!(!handle.status().is_syncing);
+
+        let details = sync_details(&audit).await;
+        assert!(matches!(details.as_slice(), [detail] if detail.starts_with("manual: failed")));
+    }
+}
@@ -0,0 +1,269 @@
+use axum::{extract::ConnectInfo, http::Request, Router};
+use hyper::body::Incoming;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto::Builder,
+    service::TowerToHyperService,
+};
+use ring::digest;
+use rustls::{server::WebPkiClientVerifier, RootCertStore};
+use rustls_pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+use std::{net::SocketAddr, path::Path, sync::Arc};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use tower::ServiceExt;
+use tracing::{debug, warn};
+use x509_parser::{certificate::X509Certificate, extensions::GeneralName, prelude::FromDer};
+use crate::{
+    config::TlsConfig,
+    error::{AppError, AppResult},
+};
+
+/// Client certificate of a connection, verified against the configured CAs
+/// during the handshake. Handlers find it in the request extensions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientCertificate {
+    /// SHA-256 of the DER certificate, in hex.
+    pub fingerprint: String,
+    pub subject: String,
+    /// Common names and DNS, email and URI subject alternative names, any
+    /// of which API tokens can be bound to.
+    pub identities: Vec<String>,
+}
+
+impl ClientCertificate {
+    pub fn from_der(der: &[u8]) -> AppResult<Self> {
+        let (_, certificate) = X509Certificate::from_der(der)
+            .map_err(|e| AppError::AuthenticationFailed(format!("Unreadable client certificate: {}", e)))?;
+
+        let mut identities: Vec<String> = certificate
+            .subject()
+            .iter_common_name()
+            .filter_map(|name| name.as_str().ok())
+            .map(str::to_string)
+            .collect();
+        if let Ok(Some(alternative_names)) = certificate.subject_alternative_name() {
+            for name in &alternative_names.value.general_names {
+                if let GeneralName::DNSName(name) | GeneralName::RFC822Name(name) | GeneralName::URI(name) = name {
+                    identities.push(name.to_string());
+                }
+            }
+        }
+
+        Ok(Self {
+            fingerprint: hex::encode(digest::digest(&digest::SHA256, der)),
+            subject: certificate.subject().to_string(),
+            identities,
+        })
+    }
+
+    pub fn presents(&self, identity: &str) -> bool {
+        self.identities.iter().any(|own| own == identity)
+    }
+}
+
+/// Server settings for `config`. With a client CA, clients are asked for a
+/// certificate, which is optional unless required by the configuration so
+/// browsers can still log in as usual.
+pub fn server_config(config: &TlsConfig) -> AppResult<Arc<rustls::ServerConfig>> {
+    let certificates = read_certificates(&config.cert_path)?;
+    let key = PrivateKeyDer::from_pem_file(&config.key_path).map_err(|e| {
+        AppError::ConfigError(format!("Cannot read TLS key {}: {}", config.key_path.display(), e))
+    })?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = rustls::ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| AppError::ConfigError(format!("Invalid TLS settings: {}", e)))?;
+    let builder = match &config.client_ca_path {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for certificate in read_certificates(path)? {
+                roots.add(certificate).map_err(|e| {
+                    AppError::ConfigError(format!("Invalid client CA in {}: {}", path.display(), e))
+                })?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            let verifier = if config.require_client_cert {
+                verifier
+            } else {
+                verifier.allow_unauthenticated()
+            };
+            builder.with_client_cert_verifier(
+                verifier
+                    .build()
+                    .map_err(|e| AppError::ConfigError(format!("Invalid client CA: {}", e)))?,
+            )
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut server = builder
+        .with_single_cert(certificates, key)
+        .map_err(|e| AppError::ConfigError(format!("Invalid TLS certificate: {}", e)))?;
+    server.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(Arc::new(server))
+}
+
+fn read_certificates(path: &Path) -> AppResult<Vec<CertificateDer<'static>>> {
+    let invalid = |e| AppError::ConfigError(format!("Cannot read certificates from {}: {}", path.display(), e));
+    let certificates = CertificateDer::pem_file_iter(path)
+        .map_err(invalid)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(invalid)?;
+    if certificates.is_empty() {
+        return Err(AppError::ConfigError(format!("No certificates in {}", path.display())));
+    }
+    Ok(certificates)
+}
+
+/// Serves `app` over TLS. Like `axum::serve` with connect info, every
+/// request carries the peer address, and also the client certificate if
+/// the client sent one.
+pub async fn serve(listener: TcpListener, app: Router, tls: Arc<rustls::ServerConfig>) -> std::io::Result<()> {
+    let acceptor = TlsAcceptor::from(tls);
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                warn!("Failed to accept connection: {}", e);
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        let app = app.clone();
+
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    debug!("TLS handshake with {} failed: {}", peer, e);
+                    return;
+                }
+            };
+            let certificate = match stream.get_ref().1.peer_certificates().and_then(|chain| chain.first()) {
+                Some(der) => match ClientCertificate::from_der(der) {
+                    Ok(certificate) => Some(certificate),
+                    Err(e) => {
+                        warn!("Rejected client certificate from {}: {}", peer, e);
+                        return;
+                    }
+                },
+                None => None,
+            };
+
+            let service = app.map_request(move |mut request: Request<Incoming>| {
+                request.extensions_mut().insert(ConnectInfo::<SocketAddr>(peer));
+                if let Some(certificate) = &certificate {
+                    request.extensions_mut().insert(certificate.clone());
+                }
+                request
+            });
+            if let Err(e) = Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(TokioIo::new(stream), TowerToHyperService::new(service))
+                .await
+            {
+                debug!("Connection from {} ended: {}", peer, e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::get, Extension};
+    use std::path::PathBuf;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    // A CA with a server certificate for localhost and a client
+    // certificate for ci-runner, both signed by it
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tls").join(name)
+    }
+
+    fn tls_config(require_client_cert: bool) -> TlsConfig {
+        TlsConfig {
+            cert_path: fixture("server.pem"),
+            key_path: fixture("server.key"),
+            client_ca_path: Some(fixture("ca.pem")),
+            require_client_cert,
+        }
+    }
+
+    #[test]
+    fn test_client_certificate_identities() {
+        let der = CertificateDer::from_pem_file(fixture("client.pem")).unwrap();
+        let certificate = ClientCertificate::from_der(&der).unwrap();
+
+        assert_eq!(certificate.identities, vec!["ci-runner", "ci.example.com", "ci@example.com"]);
+        assert!(certificate.presents("ci.example.com"));
+        assert!(!certificate.presents("ci-runner.example.com"));
+        assert_eq!(certificate.subject, "O=Example, CN=ci-runner");
+        assert_eq!(certificate.fingerprint, "d16c3d36a97fdbb082c6600e824ebd6032d042393f143ffe3e5af8c813caa644");
+    }
+
+    async fn request(address: SocketAddr, with_certificate: bool) -> std::io::Result<String> {
+        let mut roots = RootCertStore::empty();
+        roots.add(CertificateDer::from_pem_file(fixture("ca.pem")).unwrap()).unwrap();
+        let builder = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots);
+        let client = if with_certificate {
+            builder
+                .with_client_auth_cert(
+                    vec![CertificateDer::from_pem_file(fixture("client.pem")).unwrap()],
+                    PrivateKeyDer::from_pem_file(fixture("client.key")).unwrap(),
+                )
+                .unwrap()
+        } else {
+            builder.with_no_client_auth()
+        };
+
+        let stream = tokio::net::TcpStream::connect(address).await?;
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(client));
+        let mut stream = connector.connect("localhost".try_into().unwrap(), stream).await?;
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        Ok(response)
+    }
+
+    async fn start(require_client_cert: bool) -> SocketAddr {
+        let app = Router::new().route(
+            "/",
+            get(|certificate: Option<Extension<ClientCertificate>>| async move {
+                certificate.map_or("anonymous".to_string(), |Extension(certificate)| certificate.fingerprint)
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let tls = server_config(&tls_config(require_client_cert)).unwrap();
+        tokio::spawn(serve(listener, app, tls));
+        address
+    }
+
+    #[tokio::test]
+    async fn test_client_certificate_reaches_handlers() {
+        let address = start(false).await;
+
+        let response = request(address, true).await.unwrap();
+        assert!(response.ends_with("d16c3d36a97fdbb082c6600e824ebd6032d042393f143ffe3e5af8c813caa644"));
+
+        // Browsers without a certificate still connect
+        let response = request(address, false).await.unwrap();
+        assert!(response.ends_with("anonymous"));
+    }
+
+    #[tokio::test]
+    async fn test_required_client_certificate() {
+        let address = start(true).await;
+
+        assert!(request(address, true).await.is_ok());
+        // TLS 1.3 clients learn of the rejection after the handshake
+        assert!(request(address, false).await.map_or(true, |response| response.is_empty()));
+    }
+}
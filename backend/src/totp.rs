@@ -0,0 +1,165 @@
+use crate::{
+    error::{AppError, AppResult},
+    pass::PasswordEntry,
+};
+use totp_lite::{totp_custom, Sha1, Sha256, Sha512};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TotpAlgorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+/// TOTP parameters of a store entry: either an `otpauth://` URI as written
+/// by `pass otp`, or a bare base32 secret with the RFC 6238 defaults.
+#[derive(Debug, Clone)]
+pub struct Totp {
+    secret: Vec<u8>,
+    pub digits: u32,
+    pub period: u64,
+    pub algorithm: TotpAlgorithm,
+}
+
+impl Totp {
+    pub fn from_entry(entry: &PasswordEntry) -> AppResult<Self> {
+        if entry.password.starts_with("otpauth://") {
+            return Self::from_uri(&entry.password);
+        }
+        // pass-otp also accepts the URI on a later line, which the entry
+        // parser splits at its first colon
+        if let Some(rest) = entry.metadata.get("otpauth") {
+            return Self::from_uri(&format!("otpauth:{}", rest));
+        }
+
+        Ok(Self {
+            secret: decode_secret(&entry.password)?,
+            digits: 6,
+            period: 30,
+            algorithm: TotpAlgorithm::Sha1,
+        })
+    }
+
+    pub fn from_uri(uri: &str) -> AppResult<Self> {
+        let invalid = |reason: &str| AppError::AuthenticationFailed(format!("Invalid TOTP URI: {}", reason));
+
+        let url = url::Url::parse(uri).map_err(|_| invalid("not a URI"))?;
+        if url.scheme() != "otpauth" || url.host_str() != Some("totp") {
+            return Err(invalid("not an otpauth://totp URI"));
+        }
+
+        let mut totp = Self {
+            secret: Vec::new(),
+            digits: 6,
+            period: 30,
+            algorithm: TotpAlgorithm::Sha1,
+        };
+        for (key, value) in url.query_pairs() {
+            match key.to_ascii_lowercase().as_str() {
+                "secret" => totp.secret = decode_secret(&value)?,
+                "digits" => totp.digits = value.parse().map_err(|_| invalid("bad digits"))?,
+                "period" => totp.period = value.parse().map_err(|_| invalid("bad period"))?,
+                "algorithm" => {
+                    totp.algorithm = match value.to_ascii_uppercase().as_str() {
+                        "SHA1" => TotpAlgorithm::Sha1,
+                        "SHA256" => TotpAlgorithm::Sha256,
+                        "SHA512" => TotpAlgorithm::Sha512,
+                        _ => return Err(invalid("unsupported algorithm")),
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if totp.secret.is_empty() {
+            return Err(invalid("missing secret"));
+        }
+        if !(6..=10).contains(&totp.digits) || totp.period == 0 {
+            return Err(invalid("unsupported digits or period"));
+        }
+        Ok(totp)
+    }
+
+    /// Time step containing `unix_time`.
+    pub fn step(&self, unix_time: u64) -> u64 {
+        unix_time / self.period
+    }
+
+    pub fn code(&self, step: u64) -> String {
+        let time = step * self.period;
+        match self.algorithm {
+            TotpAlgorithm::Sha1 => totp_custom::<Sha1>(self.period, self.digits, &self.secret, time),
+            TotpAlgorithm::Sha256 => totp_custom::<Sha256>(self.period, self.digits, &self.secret, time),
+            TotpAlgorithm::Sha512 => totp_custom::<Sha512>(self.period, self.digits, &self.secret, time),
+        }
+    }
+
+    /// Time step whose code is `code`, looking `past` steps back and
+    /// `future` steps ahead of the one containing `unix_time`.
+    pub fn matching_step(&self, code: &str, unix_time: u64, past: u64, future: u64) -> Option<u64> {
+        let current = self.step(unix_time);
+        (current.saturating_sub(past)..=current + future).find(|step| self.code(*step) == code)
+    }
+}
+
+fn decode_secret(secret: &str) -> AppResult<Vec<u8>> {
+    let secret = secret.trim().trim_end_matches('=').replace(' ', "").to_ascii_uppercase();
+    // The decoder does not reject every character outside the alphabet
+    if !secret.chars().all(|c| c.is_ascii_uppercase() || ('2'..='7').contains(&c)) {
+        return Err(AppError::AuthenticationFailed("Invalid TOTP secret format".to_string()));
+    }
+    base32::decode(base32::Alphabet::RFC4648 { padding: false }, &secret)
+        .filter(|bytes| !bytes.is_empty())
+        .ok_or_else(|| AppError::AuthenticationFailed("Invalid TOTP secret format".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::collections::HashMap;
+
+    fn entry(password: &str) -> PasswordEntry {
+        PasswordEntry {
+            password: password.to_string(),
+            metadata: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_parse_otpauth_uri() {
+        let totp = Totp::from_entry(&entry(
+            "otpauth://totp/Kagikanri:me?secret=JBSWY3DPEHPK3PXP&digits=8&period=60&algorithm=SHA256&issuer=Kagikanri",
+        ))
+        .unwrap();
+
+        assert_eq!(totp.digits, 8);
+        assert_eq!(totp.period, 60);
+        assert_eq!(totp.algorithm, TotpAlgorithm::Sha256);
+        assert_eq!(totp.code(1).len(), 8);
+
+        assert!(Totp::from_uri("otpauth://hotp/x?secret=JBSWY3DPEHPK3PXP").is_err());
+        assert!(Totp::from_uri("otpauth://totp/x?secret=JBSWY3DPEHPK3PXP&algorithm=MD5").is_err());
+    }
+
+    #[test]
+    fn test_bare_secret_uses_defaults() {
+        let totp = Totp::from_entry(&entry("JBSWY3DPEHPK3PXP")).unwrap();
+
+        assert_eq!((totp.digits, totp.period, totp.algorithm), (6, 30, TotpAlgorithm::Sha1));
+        assert_eq!(totp.code(1), totp_custom::<Sha1>(30, 6, &totp.secret, 30));
+        assert!(Totp::from_entry(&entry("not base32!")).is_err());
+    }
+
+    #[test]
+    fn test_matching_step_respects_skew() {
+        let totp = Totp::from_entry(&entry("JBSWY3DPEHPK3PXP")).unwrap();
+        let now = 1_000_000;
+        let current = totp.step(now);
+
+        assert_eq!(totp.matching_step(&totp.code(current), now, 0, 0), Some(current));
+        assert_eq!(totp.matching_step(&totp.code(current + 1), now, 1, 1), Some(current + 1));
+        assert_eq!(totp.matching_step(&totp.code(current - 2), now, 1, 1), None);
+        assert_eq!(totp.matching_step(&totp.code(current - 2), now, 2, 0), Some(current - 2));
+    }
+}
@@ -0,0 +1,583 @@
+use crate::error::{AppError, AppResult};
+use chrono::{DateTime, Duration, Utc};
+use rand::RngCore;
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use sqlx::{Row, SqlitePool};
+
+/// Account of the single user of earlier versions. It keeps the store root
+/// and the configured credential entries, and is created as the first admin.
+pub const BOOTSTRAP_ADMIN: &str = "user";
+/// How long an invitation can be accepted.
+pub const INVITE_VALID_DAYS: i64 = 7;
+
+const MAX_USER_ID_LENGTH: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Sees the whole store and manages users and history.
+    Admin,
+    /// Confined to their home folder.
+    Member,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UserStatus {
+    /// Invited, but no master password set yet.
+    Invited,
+    Active,
+    Disabled,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct User {
+    pub id: String,
+    /// Commit author email. The bootstrap admin commits as the configured
+    /// identity and has none.
+    pub email: Option<String>,
+    pub role: Role,
+    pub status: UserStatus,
+    pub created_at: DateTime<Utc>,
+}
+
+impl User {
+    pub fn is_admin(&self) -> bool {
+        self.role == Role::Admin
+    }
+
+    /// Folder of the store the user works in. Admins work in the whole
+    /// store, so this only confines members.
+    pub fn home_dir(&self) -> String {
+        home_dir(&self.id)
+    }
+
+    /// Store path of the entry a request names. Members name entries
+    /// relative to their home folder and cannot leave it.
+    pub fn store_path(&self, path: &str) -> AppResult<String> {
+        if self.is_admin() {
+            return Ok(path.to_string());
+        }
+
+        let path = path.trim_matches('/');
+        if path.is_empty() || path.split('/').any(|part| part.is_empty() || part == "." || part == "..") {
+            return Err(AppError::ValidationError(format!("Invalid entry path: {}", path)));
+        }
+        Ok(format!("{}/{}", self.home_dir(), path))
+    }
+}
+
+/// Store folder of `user_id`'s entries.
+pub fn home_dir(user_id: &str) -> String {
+    format!("users/{}", user_id)
+}
+
+/// Pass entry with the login TOTP secret of a user other than the
+/// bootstrap admin. It lies outside the home folder, so members cannot
+/// change their own second factor through the store.
+pub fn totp_path(user_id: &str) -> String {
+    format!("kagikanri/users/{}/totp", user_id)
+}
+
+/// Role granted by membership of `groups`, for identities from a single
+/// sign-on provider, or `None` if the groups may not log in. Keycloak
+/// reports groups as paths, so leading slashes are ignored.
+pub fn role_for_groups(groups: &[String], admin_groups: &[String], member_groups: &[String]) -> Option<Role> {
+    let in_any = |allowed: &[String]| {
+        allowed
+            .iter()
+            .any(|group| groups.iter().any(|own| own.trim_start_matches('/') == group.trim_start_matches('/')))
+    };
+
+    if in_any(admin_groups) {
+        Some(Role::Admin)
+    } else if member_groups.is_empty() || in_any(member_groups) {
+        Some(Role::Member)
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InviteRequest {
+    pub user_id: String,
+    pub email: String,
+    #[serde(default = "default_role")]
+    pub role: Role,
+}
+
+fn default_role() -> Role {
+    Role::Member
+}
+
+/// User accounts. Credentials are kept per user in `CredentialStore`.
+#[derive(Debug, Clone)]
+pub struct UserStore {
+    pool: SqlitePool,
+}
+
+impl UserStore {
+    pub async fn new(pool: SqlitePool) -> AppResult<Self> {
+        let store = Self { pool };
+        store.init_schema().await?;
+        Ok(store)
+    }
+
+    async fn init_schema(&self) -> AppResult<()> {
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS users (
+                id TEXT PRIMARY KEY,
+                email TEXT,
+                role TEXT NOT NULL,
+                status TEXT NOT NULL,
+                invite_hash TEXT UNIQUE,
+                invite_expires_at TIMESTAMP,
+                oidc_issuer TEXT,
+                oidc_subject TEXT,
+                created_at TIMESTAMP NOT NULL,
+                UNIQUE (oidc_issuer, oidc_subject)
+            );
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // An existing single-user installation becomes the first admin
+        sqlx::query(
+            r#"
+            INSERT INTO users (id, role, status, created_at)
+            SELECT ?1, 'admin', 'active', ?2 WHERE NOT EXISTS (SELECT 1 FROM users)
+            "#,
+        )
+        .bind(BOOTSTRAP_ADMIN)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn get(&self, id: &str) -> AppResult<Option<User>> {
+        let row = sqlx::query("SELECT id, email, role, status, created_at FROM users WHERE id = ?1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        row.as_ref().map(row_to_user).transpose()
+    }
+
+    /// The account an OpenID Connect identity is linked to. Providers only
+    /// promise that the subject within an issuer never changes, unlike
+    /// names and other claims.
+    pub async fn get_by_oidc(&self, issuer: &str, subject: &str) -> AppResult<Option<User>> {
+        let row = sqlx::query(
+            "SELECT id, email, role, status, created_at FROM users WHERE oidc_issuer = ?1 AND oidc_subject = ?2",
+        )
+        .bind(issuer)
+        .bind(subject)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.as_ref().map(row_to_user).transpose()
+    }
+
+    /// The user if it exists and may log in.
+    pub async fn get_active(&self, id: &str) -> AppResult<Option<User>> {
+        Ok(self.get(id).await?.filter(|user| user.status == UserStatus::Active))
+    }
+
+    pub async fn list(&self) -> AppResult<Vec<User>> {
+        let rows = sqlx::query("SELECT id, email, role, status, created_at FROM users ORDER BY created_at, id")
+            .fetch_all(&self.pool)
+            .await?;
+
+        rows.iter().map(row_to_user).collect()
+    }
+
+    /// Creates an invited user and returns the invitation token, which is
+    /// only stored hashed.
+    pub async fn invite(&self, request: &InviteRequest) -> AppResult<(User, String)> {
+        validate_user_id(&request.user_id)?;
+        if !request.email.contains('@') {
+            return Err(AppError::ValidationError("A valid email address is required".to_string()));
+        }
+
+        let mut bytes = [0u8; 32];
+        rand::rngs::OsRng.fill_bytes(&mut bytes);
+        let token = hex::encode(bytes);
+
+        let user = User {
+            id: request.user_id.clone(),
+            email: Some(request.email.clone()),
+            role: request.role,
+            status: UserStatus::Invited,
+            created_at: Utc::now(),
+        };
+        let result = sqlx::query(
+            r#"
+            INSERT OR IGNORE INTO users (id, email, role, status, invite_hash, invite_expires_at, created_at)
+            VALUES (?1, ?2, ?3, 'invited', ?4, ?5, ?6)
+            "#,
+        )
+        .bind(&user.id)
+        .bind(&user.email)
+        .bind(role_name(user.role))
+        .bind(hash_token(&token))
+        .bind(user.created_at + Duration::days(INVITE_VALID_DAYS))
+        .bind(user.created_at)
+        .execute(&self.pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(AppError::Conflict(format!("User already exists: {}", user.id)));
+        }
+        Ok((user, token))
+    }
+
+    /// The user an unexpired invitation `token` was issued to.
+    pub async fn invited(&self, token: &str) -> AppResult<User> {
+        let row = sqlx::query(
+            r#"
+            SELECT id, email, role, status, created_at FROM users
+            WHERE invite_hash = ?1 AND status = 'invited' AND invite_expires_at > ?2
+            "#,
+        )
+        .bind(hash_token(token))
+        .bind(Utc::now())
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Unknown or expired invitation".to_string()))?;
+
+        row_to_user(&row)
+    }
+
+    /// Uses up the invitation of `user_id` and lets the user log in. Fails
+    /// if the invitation was accepted in the meantime.
+    pub async fn activate(&self, user_id: &str, token: &str) -> AppResult<()> {
+        let result = sqlx::query(
+            r#"
+            UPDATE users SET status = 'active', invite_hash = NULL, invite_expires_at = NULL
+            WHERE id = ?1 AND invite_hash = ?2 AND status = 'invited'
+            "#,
+        )
+        .bind(user_id)
+        .bind(hash_token(token))
+        .execute(&self.pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(AppError::Conflict("Invitation has already been accepted".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Disables or re-enables an account that has accepted its invitation.
+    pub async fn set_disabled(&self, user_id: &str, disabled: bool) -> AppResult<User> {
+        let user = self.existing(user_id).await?;
+        if user.status == UserStatus::Invited {
+            return Err(AppError::Conflict(format!("{} has not accepted the invitation yet", user_id)));
+        }
+        if disabled {
+            self.ensure_other_admin(&user).await?;
+        }
+
+        sqlx::query("UPDATE users SET status = ?1 WHERE id = ?2")
+            .bind(if disabled { "disabled" } else { "active" })
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(User {
+            status: if disabled { UserStatus::Disabled } else { UserStatus::Active },
+            ..user
+        })
+    }
+
+    /// Creates an active account for a user an identity provider vouches
+    /// for. It has no master password, so it can only log in through the
+    /// provider or with a passkey. `oidc` is the issuer and subject of the
+    /// OpenID Connect identity the account is linked to.
+    pub async fn provision(
+        &self,
+        user_id: &str,
+        email: Option<&str>,
+        role: Role,
+        oidc: Option<(&str, &str)>,
+    ) -> AppResult<User> {
+        validate_user_id(user_id)?;
+
+        let user = User {
+            id: user_id.to_string(),
+            email: email.map(str::to_string),
+            role,
+            status: UserStatus::Active,
+            created_at: Utc::now(),
+        };
+        let result = sqlx::query(
+            r#"
+            INSERT OR IGNORE INTO users (id, email, role, status, oidc_issuer, oidc_subject, created_at)
+            VALUES (?1, ?2, ?3, 'active', ?4, ?5, ?6)
+            "#,
+        )
+        .bind(&user.id)
+        .bind(&user.email)
+        .bind(role_name(user.role))
+        .bind(oidc.map(|(issuer, _)| issuer))
+        .bind(oidc.map(|(_, subject)| subject))
+        .bind(user.created_at)
+        .execute(&self.pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(AppError::Conflict(format!("User already exists: {}", user.id)));
+        }
+        Ok(user)
+    }
+
+    /// Links an existing account to an OpenID Connect identity, replacing
+    /// any earlier link. The bootstrap admin keeps its local credentials.
+    pub async fn link_oidc(&self, user_id: &str, issuer: &str, subject: &str) -> AppResult<User> {
+        let user = self.existing(user_id).await?;
+        if user.id == BOOTSTRAP_ADMIN {
+            return Err(AppError::Conflict(format!("{} logs in with its local credentials", user.id)));
+        }
+        if subject.is_empty() {
+            return Err(AppError::ValidationError("A subject is required".to_string()));
+        }
+        if let Some(linked) = self.get_by_oidc(issuer, subject).await? {
+            if linked.id != user.id {
+                return Err(AppError::Conflict(format!("Subject {} is linked to {}", subject, linked.id)));
+            }
+        }
+
+        sqlx::query("UPDATE users SET oidc_issuer = ?1, oidc_subject = ?2 WHERE id = ?3")
+            .bind(issuer)
+            .bind(subject)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(user)
+    }
+
+    pub async fn unlink_oidc(&self, user_id: &str) -> AppResult<User> {
+        let user = self.existing(user_id).await?;
+
+        sqlx::query("UPDATE users SET oidc_issuer = NULL, oidc_subject = NULL WHERE id = ?1")
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(user)
+    }
+
+    pub async fn set_role(&self, user_id: &str, role: Role) -> AppResult<User> {
+        let user = self.existing(user_id).await?;
+        if role != Role::Admin {
+            self.ensure_other_admin(&user).await?;
+        }
+
+        sqlx::query("UPDATE users SET role = ?1 WHERE id = ?2")
+            .bind(role_name(role))
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(User { role, ..user })
+    }
+
+    /// Removes the account. Its credentials are removed by their stores.
+    pub async fn delete(&self, user_id: &str) -> AppResult<()> {
+        let user = self.existing(user_id).await?;
+        self.ensure_other_admin(&user).await?;
+
+        sqlx::query("DELETE FROM users WHERE id = ?1")
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn existing(&self, user_id: &str) -> AppResult<User> {
+        self.get(user_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("User not found: {}", user_id)))
+    }
+
+    /// Refuses to take away the last active admin, which would leave nobody
+    /// able to manage users.
+    async fn ensure_other_admin(&self, user: &User) -> AppResult<()> {
+        if !user.is_admin() || user.status != UserStatus::Active {
+            return Ok(());
+        }
+
+        let row = sqlx::query("SELECT COUNT(*) AS admins FROM users WHERE role = 'admin' AND status = 'active'")
+            .fetch_one(&self.pool)
+            .await?;
+        if row.get::<i64, _>("admins") <= 1 {
+            return Err(AppError::Conflict("The last active admin cannot be removed".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// User IDs become store folders and commit author names, so they are
+/// limited to lowercase letters, digits, `.`, `_` and `-`.
+fn validate_user_id(user_id: &str) -> AppResult<()> {
+    let valid = !user_id.is_empty()
+        && user_id.len() <= MAX_USER_ID_LENGTH
+        && user_id
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '.' | '_' | '-'))
+        && user_id.starts_with(|c: char| c.is_ascii_alphanumeric());
+
+    if valid {
+        Ok(())
+    } else {
+        Err(AppError::ValidationError(format!(
+            "User IDs are 1 to {} lowercase letters, digits, '.', '_' or '-', starting with a letter or digit",
+            MAX_USER_ID_LENGTH
+        )))
+    }
+}
+
+fn role_name(role: Role) -> &'static str {
+    match role {
+        Role::Admin => "admin",
+        Role::Member => "member",
+    }
+}
+
+fn hash_token(token: &str) -> String {
+    hex::encode(digest::digest(&digest::SHA256, token.as_bytes()))
+}
+
+fn row_to_user(row: &sqlx::sqlite::SqliteRow) -> AppResult<User> {
+    let role = match row.get::<String, _>("role").as_str() {
+        "admin" => Role::Admin,
+        "member" => Role::Member,
+        other => return Err(AppError::DatabaseError(format!("Unknown role: {}", other))),
+    };
+    let status = match row.get::<String, _>("status").as_str() {
+        "invited" => UserStatus::Invited,
+        "active" => UserStatus::Active,
+        "disabled" => UserStatus::Disabled,
+        other => return Err(AppError::DatabaseError(format!("Unknown user status: {}", other))),
+    };
+
+    Ok(User {
+        id: row.get("id"),
+        email: row.get("email"),
+        role,
+        status,
+        created_at: row.get("created_at"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    async fn create_test_store() -> UserStore {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        UserStore::new(pool).await.unwrap()
+    }
+
+    fn invite(user_id: &str, role: Role) -> InviteRequest {
+        InviteRequest {
+            user_id: user_id.to_string(),
+            email: format!("{}@example.com", user_id),
+            role,
+        }
+    }
+
+    #[test]
+    fn test_members_are_confined_to_home_folder() {
+        let member = User {
+            id: "alice".to_string(),
+            email: Some("alice@example.com".to_string()),
+            role: Role::Member,
+            status: UserStatus::Active,
+            created_at: Utc::now(),
+        };
+        assert_eq!(member.store_path("email/work").unwrap(), "users/alice/email/work");
+        assert!(member.store_path("../bob/email").is_err());
+        assert!(member.store_path("email//work").is_err());
+
+        let admin = User { role: Role::Admin, ..member };
+        assert_eq!(admin.store_path("users/bob/email").unwrap(), "users/bob/email");
+    }
+
+    #[tokio::test]
+    async fn test_bootstrap_admin_is_created_once() {
+        let store = create_test_store().await;
+        let users = store.list().await.unwrap();
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].id, BOOTSTRAP_ADMIN);
+        assert!(users[0].is_admin());
+
+        store.init_schema().await.unwrap();
+        assert_eq!(store.list().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_invitation_is_accepted_once() {
+        let store = create_test_store().await;
+        let (user, token) = store.invite(&invite("alice", Role::Member)).await.unwrap();
+        assert_eq!(user.status, UserStatus::Invited);
+        assert!(store.get_active("alice").await.unwrap().is_none());
+
+        assert!(matches!(store.invite(&invite("alice", Role::Member)).await, Err(AppError::Conflict(_))));
+        assert!(matches!(store.invite(&invite("../alice", Role::Member)).await, Err(AppError::ValidationError(_))));
+        assert!(matches!(store.invited("wrong").await, Err(AppError::NotFound(_))));
+
+        assert_eq!(store.invited(&token).await.unwrap().id, "alice");
+        store.activate("alice", &token).await.unwrap();
+        assert!(store.get_active("alice").await.unwrap().is_some());
+        assert!(matches!(store.activate("alice", &token).await, Err(AppError::Conflict(_))));
+        assert!(store.invited(&token).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_last_admin_cannot_be_disabled_or_deleted() {
+        let store = create_test_store().await;
+        assert!(matches!(store.set_disabled(BOOTSTRAP_ADMIN, true).await, Err(AppError::Conflict(_))));
+        assert!(matches!(store.delete(BOOTSTRAP_ADMIN).await, Err(AppError::Conflict(_))));
+
+        let (_, token) = store.invite(&invite("bob", Role::Admin)).await.unwrap();
+        store.activate("bob", &token).await.unwrap();
+
+        let disabled = store.set_disabled(BOOTSTRAP_ADMIN, true).await.unwrap();
+        assert_eq!(disabled.status, UserStatus::Disabled);
+        assert!(store.get_active(BOOTSTRAP_ADMIN).await.unwrap().is_none());
+        assert!(store.delete("bob").await.is_err());
+
+        store.set_disabled(BOOTSTRAP_ADMIN, false).await.unwrap();
+        store.delete("bob").await.unwrap();
+        assert!(store.get("bob").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_oidc_identity_links_one_account() {
+        let store = create_test_store().await;
+        let issuer = "https://idp.example.com";
+        store.provision("carol", None, Role::Member, Some((issuer, "42"))).await.unwrap();
+        store.provision("dave", None, Role::Member, None).await.unwrap();
+        assert_eq!(store.get_by_oidc(issuer, "42").await.unwrap().unwrap().id, "carol");
+        assert!(store.get_by_oidc("https://other.example.com", "42").await.unwrap().is_none());
+
+        assert!(matches!(store.link_oidc("dave", issuer, "42").await, Err(AppError::Conflict(_))));
+        assert!(matches!(store.link_oidc(BOOTSTRAP_ADMIN, issuer, "7").await, Err(AppError::Conflict(_))));
+        store.link_oidc("dave", issuer, "43").await.unwrap();
+        assert_eq!(store.get_by_oidc(issuer, "43").await.unwrap().unwrap().id, "dave");
+
+        store.unlink_oidc("carol").await.unwrap();
+        assert!(store.get_by_oidc(issuer, "42").await.unwrap().is_none());
+        store.link_oidc("dave", issuer, "42").await.unwrap();
+        assert!(store.get_by_oidc(issuer, "43").await.unwrap().is_none());
+    }
+}
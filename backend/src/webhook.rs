@@ -0,0 +1,192 @@
+use crate::error::{AppError, AppResult};
+use axum::http::HeaderMap;
+use ring::hmac;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+/// Git host that sent a webhook, identified by its signature header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookSource {
+    GitHub,
+    Gitea,
+    GitLab,
+}
+
+/// Verifies that a webhook request was sent by a host that knows `secret`.
+///
+/// - Gitea signs the body with HMAC-SHA256 in `X-Gitea-Signature`
+/// - GitHub does the same in `X-Hub-Signature-256`, prefixed with `sha256=`
+/// - GitLab sends the secret itself in `X-Gitlab-Token`
+///
+/// Gitea also sends `X-Hub-Signature-256`, so its own header is checked first.
+pub fn verify_request(headers: &HeaderMap, body: &[u8], secret: &str) -> AppResult<WebhookSource> {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+
+    if let Some(signature) = header("x-gitea-signature") {
+        verify_hmac(secret, body, signature)?;
+        return Ok(WebhookSource::Gitea);
+    }
+
+    if let Some(signature) = header("x-hub-signature-256") {
+        let signature = signature.strip_prefix("sha256=").ok_or_else(|| {
+            AppError::AuthenticationFailed("Unsupported webhook signature format".to_string())
+        })?;
+        verify_hmac(secret, body, signature)?;
+        return Ok(WebhookSource::GitHub);
+    }
+
+    if let Some(token) = header("x-gitlab-token") {
+        if !constant_time_eq(token.as_bytes(), secret.as_bytes()) {
+            return Err(AppError::AuthenticationFailed("Invalid webhook token".to_string()));
+        }
+        return Ok(WebhookSource::GitLab);
+    }
+
+    Err(AppError::AuthenticationFailed("Missing webhook signature".to_string()))
+}
+
+/// True for events announcing new commits. Pings and other events are
+/// acknowledged without syncing.
+pub fn is_push_event(headers: &HeaderMap, source: WebhookSource) -> bool {
+    let name = match source {
+        WebhookSource::GitHub => "x-github-event",
+        WebhookSource::Gitea => "x-gitea-event",
+        WebhookSource::GitLab => "x-gitlab-event",
+    };
+    let event = headers.get(name).and_then(|value| value.to_str().ok());
+
+    match source {
+        WebhookSource::GitHub | WebhookSource::Gitea => event == Some("push"),
+        WebhookSource::GitLab => event == Some("Push Hook"),
+    }
+}
+
+fn verify_hmac(secret: &str, body: &[u8], signature: &str) -> AppResult<()> {
+    let signature = hex::decode(signature.trim())
+        .map_err(|_| AppError::AuthenticationFailed("Malformed webhook signature".to_string()))?;
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+
+    hmac::verify(&key, body, &signature)
+        .map_err(|_| AppError::AuthenticationFailed("Invalid webhook signature".to_string()))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Fixed-window limit on webhook deliveries. A burst of pushes only needs
+/// one sync, so excess deliveries are rejected rather than queued.
+#[derive(Debug)]
+pub struct RateLimiter {
+    max_requests: u32,
+    window: Duration,
+    window_start: Instant,
+    count: u32,
+}
+
+impl RateLimiter {
+    pub fn new(max_requests: u32, window: Duration) -> Self {
+        Self {
+            max_requests,
+            window,
+            window_start: Instant::now(),
+            count: 0,
+        }
+    }
+
+    /// Records a request and returns whether it is within the limit.
+    pub fn check(&mut self) -> bool {
+        let now = Instant::now();
+        if now.duration_since(self.window_start) >= self.window {
+            self.window_start = now;
+            self.count = 0;
+        }
+
+        if self.count >= self.max_requests {
+            return false;
+        }
+        self.count += 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    const SECRET: &str = "webhook-secret";
+    const BODY: &[u8] = br#"{"ref":"refs/heads/main"}"#;
+
+    fn sign(body: &[u8]) -> String {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, SECRET.as_bytes());
+        hex::encode(hmac::sign(&key, body).as_ref())
+    }
+
+    fn headers(pairs: &[(&'static str, String)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_github_signature() {
+        let signed = headers(&[
+            ("x-hub-signature-256", format!("sha256={}", sign(BODY))),
+            ("x-github-event", "push".to_string()),
+        ]);
+        let source = verify_request(&signed, BODY, SECRET).unwrap();
+        assert_eq!(source, WebhookSource::GitHub);
+        assert!(is_push_event(&signed, source));
+
+        let tampered = verify_request(&signed, br#"{"ref":"refs/heads/evil"}"#, SECRET);
+        assert!(matches!(tampered, Err(AppError::AuthenticationFailed(_))));
+    }
+
+    #[test]
+    fn test_gitea_signature_takes_precedence() {
+        let signed = headers(&[
+            ("x-gitea-signature", sign(BODY)),
+            ("x-hub-signature-256", format!("sha256={}", sign(BODY))),
+            ("x-gitea-event", "push".to_string()),
+        ]);
+        let source = verify_request(&signed, BODY, SECRET).unwrap();
+        assert_eq!(source, WebhookSource::Gitea);
+        assert!(is_push_event(&signed, source));
+    }
+
+    #[test]
+    fn test_gitlab_token() {
+        let valid = headers(&[
+            ("x-gitlab-token", SECRET.to_string()),
+            ("x-gitlab-event", "Push Hook".to_string()),
+        ]);
+        assert_eq!(verify_request(&valid, BODY, SECRET).unwrap(), WebhookSource::GitLab);
+        assert!(is_push_event(&valid, WebhookSource::GitLab));
+
+        let invalid = headers(&[("x-gitlab-token", "guess".to_string())]);
+        assert!(verify_request(&invalid, BODY, SECRET).is_err());
+    }
+
+    #[test]
+    fn test_unsigned_requests_are_rejected() {
+        assert!(verify_request(&HeaderMap::new(), BODY, SECRET).is_err());
+        let ping = headers(&[("x-github-event", "ping".to_string())]);
+        assert!(!is_push_event(&ping, WebhookSource::GitHub));
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut limiter = RateLimiter::new(2, Duration::from_secs(60));
+        assert!(limiter.check());
+        assert!(limiter.check());
+        assert!(!limiter.check());
+
+        let mut expired = RateLimiter::new(1, Duration::ZERO);
+        assert!(expired.check());
+        assert!(expired.check());
+    }
+}